@@ -0,0 +1,809 @@
+//! Recording RTT sessions to capture files and replaying them.
+//!
+//! A [`Recorder`] writes the data read from up channels and written to down channels into a
+//! capture file along with host timestamps and the control block metadata. A [`CaptureReader`]
+//! reads the file back, and [`Replay`] simulates a target that produces the recorded up channel
+//! data again, so that it can be used as an ordinary [`Rtt`] instance, e.g. for testing decoders
+//! offline.
+//!
+//! ## File format
+//!
+//! All integers are little-endian.
+//!
+//! ```text
+//! header:
+//!   magic               8 bytes  "RTTCAP\0\0"
+//!   version             u32      1
+//!   start time          u64      microseconds since the Unix epoch
+//!   control block       u32      address of the control block in target memory
+//!   channel count       u32
+//!   channels:
+//!     direction         u8       0 = up, 1 = down
+//!     number            u32
+//!     buffer size       u32
+//!     name length       u16      0xffff if the channel has no name
+//!     name              bytes
+//! records until end of file:
+//!   time                u64      microseconds since the start time
+//!   direction           u8       0 = up, 1 = down
+//!   channel number      u32
+//!   length              u32
+//!   data                bytes
+//! ```
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::channel::Channel;
+use crate::{
+    DownChannel, Error, Memory, Region, RegionKind, Rtt, RttChannel, ScanRegion, UpChannel,
+};
+
+const MAGIC: [u8; 8] = *b"RTTCAP\0\0";
+const VERSION: u32 = 1;
+const NO_NAME: u16 = 0xffff;
+
+/// Largest channel buffer size and record length in a capture file. Anything larger is treated as
+/// corruption rather than allocated. Longer data is split into several records when recording.
+pub const MAX_RECORD_LEN: usize = 16 * 1024 * 1024;
+
+/// Largest total size of the channel buffers and names in a capture file, which bounds the memory
+/// image of a [`Replay`].
+pub const MAX_IMAGE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Number of channels of each direction that a control block can have at most, as accepted by
+/// [`Rtt`].
+const MAX_CHANNELS: usize = 255;
+
+/// Direction of an RTT channel.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum Direction {
+    /// Target to host.
+    Up = 0,
+
+    /// Host to target.
+    Down = 1,
+}
+
+impl Direction {
+    fn from_u8(value: u8) -> Result<Direction, Error> {
+        match value {
+            0 => Ok(Direction::Up),
+            1 => Ok(Direction::Down),
+            _ => Err(Error::Protocol(format!(
+                "Invalid channel direction {}",
+                value
+            ))),
+        }
+    }
+}
+
+/// Metadata of a channel.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChannelInfo {
+    /// Direction of the channel.
+    pub direction: Direction,
+
+    /// Number of the channel.
+    pub number: usize,
+
+    /// Name of the channel.
+    pub name: Option<String>,
+
+    /// Buffer size of the channel in bytes.
+    pub buffer_size: usize,
+}
+
+impl ChannelInfo {
+    pub(crate) fn from_channel(direction: Direction, channel: &impl RttChannel) -> ChannelInfo {
+        ChannelInfo {
+            direction,
+            number: channel.number(),
+            name: channel.name().map(String::from),
+            buffer_size: channel.buffer_size(),
+        }
+    }
+
+    /// Appends the encoded channel metadata to `buf`.
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(self.direction as u8);
+        buf.extend_from_slice(&(self.number as u32).to_le_bytes());
+        buf.extend_from_slice(&(self.buffer_size as u32).to_le_bytes());
+
+        match &self.name {
+            Some(name) => {
+                let name = &name.as_bytes()[..name.len().min(NO_NAME as usize - 1)];
+                buf.extend_from_slice(&(name.len() as u16).to_le_bytes());
+                buf.extend_from_slice(name);
+            }
+            None => buf.extend_from_slice(&NO_NAME.to_le_bytes()),
+        }
+    }
+
+    /// Reads encoded channel metadata.
+    pub(crate) fn read_from(mut reader: impl Read) -> Result<ChannelInfo, Error> {
+        let direction = Direction::from_u8(read_u8(&mut reader)?)?;
+        let number = read_u32(&mut reader)? as usize;
+        let buffer_size = read_u32(&mut reader)? as usize;
+
+        if number >= MAX_CHANNELS {
+            return Err(Error::Protocol(format!(
+                "Channel number {} exceeds maximum of {}",
+                number,
+                MAX_CHANNELS - 1
+            )));
+        }
+
+        if buffer_size > MAX_RECORD_LEN {
+            return Err(Error::Protocol(format!(
+                "Channel buffer size {} exceeds maximum of {}",
+                buffer_size, MAX_RECORD_LEN
+            )));
+        }
+
+        let name = match read_u16(&mut reader)? {
+            NO_NAME => None,
+            len => {
+                let name = read_bytes(&mut reader, len as usize)?;
+                Some(String::from_utf8_lossy(&name).into_owned())
+            }
+        };
+
+        Ok(ChannelInfo {
+            direction,
+            number,
+            name,
+            buffer_size,
+        })
+    }
+}
+
+/// Capture file header.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CaptureHeader {
+    /// Address of the control block in target memory.
+    pub control_block: u32,
+
+    /// Host time when recording started.
+    pub start: SystemTime,
+
+    /// The channels that were present on the target.
+    pub channels: Vec<ChannelInfo>,
+}
+
+impl CaptureHeader {
+    /// Creates a header describing the control block and the channels of `rtt`. Channels that
+    /// have already been taken out of `rtt` are not included.
+    pub fn from_rtt(rtt: &mut Rtt) -> CaptureHeader {
+        let mut channels: Vec<ChannelInfo> = rtt
+            .up_channels()
+            .iter()
+            .map(|c| ChannelInfo::from_channel(Direction::Up, c))
+            .collect();
+
+        channels.extend(
+            rtt.down_channels()
+                .iter()
+                .map(|c| ChannelInfo::from_channel(Direction::Down, c)),
+        );
+
+        CaptureHeader {
+            control_block: rtt.ptr(),
+            start: SystemTime::now(),
+            channels,
+        }
+    }
+
+    /// Returns the metadata of the specified channel.
+    pub fn channel(&self, direction: Direction, number: usize) -> Option<&ChannelInfo> {
+        self.channels
+            .iter()
+            .find(|c| c.direction == direction && c.number == number)
+    }
+
+    fn write_to(&self, mut writer: impl Write) -> Result<(), Error> {
+        let start = self
+            .start
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+
+        let mut buf = MAGIC.to_vec();
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+        buf.extend_from_slice(&start.to_le_bytes());
+        buf.extend_from_slice(&self.control_block.to_le_bytes());
+        buf.extend_from_slice(&(self.channels.len() as u32).to_le_bytes());
+
+        for chan in &self.channels {
+            chan.encode(&mut buf);
+        }
+
+        writer.write_all(&buf)?;
+
+        Ok(())
+    }
+
+    fn read_from(mut reader: impl Read) -> Result<CaptureHeader, Error> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(Error::Protocol(String::from("Not an RTT capture file")));
+        }
+
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(Error::Protocol(format!(
+                "Unsupported capture file version {}",
+                version
+            )));
+        }
+
+        let start = SystemTime::UNIX_EPOCH + Duration::from_micros(read_u64(&mut reader)?);
+        let control_block = read_u32(&mut reader)?;
+        let count = read_u32(&mut reader)?;
+
+        let mut channels: Vec<ChannelInfo> = Vec::new();
+        let mut image_size = 0;
+
+        for _ in 0..count {
+            let chan = ChannelInfo::read_from(&mut reader)?;
+
+            if channels
+                .iter()
+                .any(|c| c.direction == chan.direction && c.number == chan.number)
+            {
+                return Err(Error::Protocol(format!(
+                    "Duplicate {:?} channel {}",
+                    chan.direction, chan.number
+                )));
+            }
+
+            image_size += chan.buffer_size + chan.name.as_ref().map_or(0, String::len);
+            if image_size > MAX_IMAGE_SIZE {
+                return Err(Error::Protocol(format!(
+                    "Channel buffers exceed maximum total size of {}",
+                    MAX_IMAGE_SIZE
+                )));
+            }
+
+            channels.push(chan);
+        }
+
+        Ok(CaptureHeader {
+            control_block,
+            start,
+            channels,
+        })
+    }
+}
+
+/// A chunk of data read from or written to a channel.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Chunk {
+    /// Time since the start of the capture.
+    pub time: Duration,
+
+    /// Direction of the channel.
+    pub direction: Direction,
+
+    /// Number of the channel.
+    pub channel: usize,
+
+    /// The data.
+    pub data: Vec<u8>,
+}
+
+/// Records channel data into a capture file.
+///
+/// ## Example
+///
+/// ```no_run
+/// # #[cfg(feature = "probe-rs")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::sync::{Arc, Mutex};
+/// use std::fs::File;
+/// use probe_rs::Probe;
+/// use probe_rs_rtt::{capture::Recorder, Rtt};
+///
+/// let probe = Probe::list_all()[0].open()?;
+/// let session = probe.attach("somechip")?;
+/// let mut rtt = Rtt::attach(Arc::new(Mutex::new(session)))?;
+///
+/// // Create the recorder before taking the channels so that they are included in the header
+/// let mut recorder = Recorder::new(File::create("session.rttcap")?, &mut rtt)?;
+///
+/// let input = rtt.up_channels().take(0).unwrap();
+/// let mut buf = [0u8; 1024];
+/// loop {
+///     let count = input.read(&mut buf[..])?;
+///     recorder.record_up(&input, &buf[..count])?;
+/// }
+/// # Ok(())
+/// # }
+/// # #[cfg(not(feature = "probe-rs"))]
+/// # fn main() {}
+/// ```
+#[derive(Debug)]
+pub struct Recorder<W: Write> {
+    writer: W,
+    start: Instant,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Creates a recorder and writes the header describing `rtt` to `writer`.
+    ///
+    /// Only channels still present in `rtt` are described in the header, so create the recorder
+    /// before taking any channels.
+    pub fn new(writer: W, rtt: &mut Rtt) -> Result<Recorder<W>, Error> {
+        Self::with_header(writer, &CaptureHeader::from_rtt(rtt))
+    }
+
+    /// Creates a recorder and writes the specified header to `writer`.
+    pub fn with_header(mut writer: W, header: &CaptureHeader) -> Result<Recorder<W>, Error> {
+        header.write_to(&mut writer)?;
+
+        Ok(Recorder {
+            writer,
+            start: Instant::now(),
+        })
+    }
+
+    /// Records data that was read from an up channel. Empty data is ignored.
+    pub fn record_up(&mut self, channel: &UpChannel, data: &[u8]) -> Result<(), Error> {
+        self.record(Direction::Up, channel.number(), data)
+    }
+
+    /// Records data that was written to a down channel. Empty data is ignored.
+    pub fn record_down(&mut self, channel: &DownChannel, data: &[u8]) -> Result<(), Error> {
+        self.record(Direction::Down, channel.number(), data)
+    }
+
+    /// Records data for the specified channel. Empty data is ignored.
+    pub fn record(
+        &mut self,
+        direction: Direction,
+        channel: usize,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        let time = self.start.elapsed().as_micros() as u64;
+
+        for data in data.chunks(MAX_RECORD_LEN) {
+            // Write each record in one go so that an unbuffered writer produces whole records
+            let mut buf = Vec::with_capacity(17 + data.len());
+            buf.extend_from_slice(&time.to_le_bytes());
+            buf.push(direction as u8);
+            buf.extend_from_slice(&(channel as u32).to_le_bytes());
+            buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            buf.extend_from_slice(data);
+
+            self.writer.write_all(&buf)?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Consumes the recorder and returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads a capture file.
+///
+/// Iterating over the reader yields the recorded chunks in order. A truncated record at the end
+/// of the file, e.g. because recording was interrupted, is ignored.
+#[derive(Debug)]
+pub struct CaptureReader<R: Read> {
+    reader: R,
+    header: CaptureHeader,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Reads the capture header from `reader`.
+    pub fn new(mut reader: R) -> Result<CaptureReader<R>, Error> {
+        let header = CaptureHeader::read_from(&mut reader)?;
+
+        Ok(CaptureReader { reader, header })
+    }
+
+    /// Returns the capture header.
+    pub fn header(&self) -> &CaptureHeader {
+        &self.header
+    }
+
+    /// Reads the next chunk, or returns `None` at the end of the file.
+    pub fn next_chunk(&mut self) -> Result<Option<Chunk>, Error> {
+        match self.read_chunk() {
+            Ok(chunk) => Ok(chunk),
+            Err(Error::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                log::warn!("Capture file ends with a truncated record");
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn read_chunk(&mut self) -> Result<Option<Chunk>, Error> {
+        let mut time = [0u8; 8];
+        let mut filled = 0;
+
+        // Distinguish the end of the file from a truncated record
+        while filled < time.len() {
+            match self.reader.read(&mut time[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                Ok(count) => filled += count,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        let direction = Direction::from_u8(read_u8(&mut self.reader)?)?;
+        let channel = read_u32(&mut self.reader)? as usize;
+        let len = read_u32(&mut self.reader)? as usize;
+
+        if len > MAX_RECORD_LEN {
+            return Err(Error::Protocol(format!(
+                "Record length {} exceeds maximum of {}",
+                len, MAX_RECORD_LEN
+            )));
+        }
+
+        let data = read_bytes(&mut self.reader, len)?;
+
+        Ok(Some(Chunk {
+            time: Duration::from_micros(u64::from_le_bytes(time)),
+            direction,
+            channel,
+            data,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<Chunk, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_chunk().transpose()
+    }
+}
+
+/// A simulated target that replays a capture.
+///
+/// The replay lays out a control block with the recorded channels in a memory image and feeds the
+/// recorded up channel data into the channel buffers as the host reads them. Data written to down
+/// channels is discarded.
+///
+/// ## Example
+///
+/// ```no_run
+/// use probe_rs_rtt::capture::Replay;
+///
+/// let mut rtt = Replay::open("session.rttcap")?.into_rtt()?;
+///
+/// let input = rtt.up_channels().take(0).unwrap();
+/// let mut buf = [0u8; 1024];
+/// let count = input.read(&mut buf[..])?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct Replay {
+    header: CaptureHeader,
+    chunks: VecDeque<Chunk>,
+    image: Vec<u8>,
+    up: Vec<ReplayChannel>,
+    down: Vec<ReplayChannel>,
+    realtime: bool,
+    start: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct ReplayChannel {
+    number: usize,
+    // Offset of the channel structure in the image
+    offset: usize,
+    // Offset of the buffer in the image
+    buffer: usize,
+    size: usize,
+}
+
+impl Replay {
+    /// Creates a replay of the specified chunks. Only up channel chunks are replayed.
+    pub fn new(header: CaptureHeader, chunks: impl IntoIterator<Item = Chunk>) -> Replay {
+        let max_number = |direction| {
+            header
+                .channels
+                .iter()
+                .filter(|c| c.direction == direction)
+                .map(|c| c.number + 1)
+                .max()
+                .unwrap_or(0)
+        };
+
+        let max_up = max_number(Direction::Up);
+        let max_down = max_number(Direction::Down);
+
+        let mut image = vec![0u8; Rtt::O_CHANNEL_ARRAYS + (max_up + max_down) * Channel::SIZE];
+        image[..Rtt::RTT_ID.len()].copy_from_slice(&Rtt::RTT_ID);
+        put_u32(&mut image, Rtt::O_MAX_UP_CHANNELS, max_up as u32);
+        put_u32(&mut image, Rtt::O_MAX_DOWN_CHANNELS, max_down as u32);
+
+        let mut up = Vec::new();
+        let mut down = Vec::new();
+
+        for chan in &header.channels {
+            let index = match chan.direction {
+                Direction::Up => chan.number,
+                Direction::Down => max_up + chan.number,
+            };
+            let offset = Rtt::O_CHANNEL_ARRAYS + index * Channel::SIZE;
+
+            if let Some(name) = &chan.name {
+                let name_offset = image.len();
+                image.extend_from_slice(name.as_bytes());
+                image.push(0);
+                put_u32(
+                    &mut image,
+                    offset + Channel::O_NAME,
+                    addr(&header, name_offset),
+                );
+            }
+
+            // Keep buffers word aligned and large enough to hold at least one byte
+            image.resize((image.len() + 3) & !3, 0);
+            let size = chan.buffer_size.max(2);
+            let buffer = image.len();
+            image.resize(buffer + size, 0);
+
+            put_u32(
+                &mut image,
+                offset + Channel::O_BUFFER_PTR,
+                addr(&header, buffer),
+            );
+            put_u32(&mut image, offset + Channel::O_SIZE, size as u32);
+
+            let replay_chan = ReplayChannel {
+                number: chan.number,
+                offset,
+                buffer,
+                size,
+            };
+
+            match chan.direction {
+                Direction::Up => up.push(replay_chan),
+                Direction::Down => down.push(replay_chan),
+            }
+        }
+
+        Replay {
+            header,
+            chunks: chunks
+                .into_iter()
+                .filter(|c| c.direction == Direction::Up)
+                .collect(),
+            image,
+            up,
+            down,
+            realtime: false,
+            start: None,
+        }
+    }
+
+    /// Reads a capture from `reader` and creates a replay of it.
+    pub fn from_reader(reader: impl Read) -> Result<Replay, Error> {
+        let mut reader = CaptureReader::new(reader)?;
+        let header = reader.header().clone();
+        let chunks = reader.by_ref().collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Replay::new(header, chunks))
+    }
+
+    /// Reads a capture file and creates a replay of it.
+    pub fn open(path: impl AsRef<Path>) -> Result<Replay, Error> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Sets whether data is replayed with the recorded timing. By default data is made available
+    /// as fast as the host reads it.
+    pub fn with_realtime(mut self, realtime: bool) -> Replay {
+        self.realtime = realtime;
+        self
+    }
+
+    /// Returns the capture header.
+    pub fn header(&self) -> &CaptureHeader {
+        &self.header
+    }
+
+    /// Returns the address of the simulated control block.
+    pub fn control_block(&self) -> u32 {
+        self.header.control_block
+    }
+
+    /// Returns `true` if all recorded data has been placed in the channel buffers.
+    pub fn is_finished(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Creates an [`Rtt`] instance that reads from this replay.
+    ///
+    /// To check whether the replay has finished while using the channels, share it with
+    /// [`Rtt::attach_region`] and [`ScanRegion::Exact`] instead.
+    pub fn into_rtt(self) -> Result<Rtt, Error> {
+        let ptr = self.control_block();
+
+        Rtt::attach_region(Arc::new(Mutex::new(self)), &ScanRegion::Exact(ptr))
+    }
+
+    /// Moves recorded data into the buffers as time passes and space becomes available.
+    fn advance(&mut self) {
+        let elapsed = self.start.get_or_insert_with(Instant::now).elapsed();
+
+        // Consume everything written by the host
+        for chan in &self.down {
+            let write = get_u32(&self.image, chan.offset + Channel::O_WRITE);
+            put_u32(&mut self.image, chan.offset + Channel::O_READ, write);
+        }
+
+        while let Some(chunk) = self.chunks.front_mut() {
+            if self.realtime && chunk.time > elapsed {
+                break;
+            }
+
+            let chan = match self.up.iter().find(|c| c.number == chunk.channel) {
+                Some(chan) => chan,
+                None => {
+                    self.chunks.pop_front();
+                    continue;
+                }
+            };
+
+            let written = push(&mut self.image, chan, &chunk.data);
+            chunk.data.drain(..written);
+
+            if !chunk.data.is_empty() {
+                // The buffer is full, keep the order of data by waiting for the host to read it
+                break;
+            }
+
+            self.chunks.pop_front();
+        }
+    }
+
+    fn range(&self, address: u32, len: usize) -> Result<std::ops::Range<usize>, Error> {
+        let start = address.wrapping_sub(self.header.control_block) as usize;
+
+        match start.checked_add(len) {
+            Some(end) if end <= self.image.len() => Ok(start..end),
+            _ => Err(Error::InvalidAddress(address)),
+        }
+    }
+}
+
+impl Memory for Replay {
+    fn read(&mut self, address: u32, data: &mut [u8]) -> Result<(), Error> {
+        self.advance();
+
+        let range = self.range(address, data.len())?;
+        data.copy_from_slice(&self.image[range]);
+
+        Ok(())
+    }
+
+    fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        let range = self.range(address, data.len())?;
+        self.image[range].copy_from_slice(data);
+
+        self.advance();
+
+        Ok(())
+    }
+
+    fn regions(&self) -> Vec<Region> {
+        vec![Region {
+            kind: RegionKind::Ram,
+            range: self.header.control_block..addr(&self.header, self.image.len()),
+        }]
+    }
+}
+
+/// Writes as much of `data` into the ring buffer of `chan` as fits and returns the number of bytes
+/// written.
+fn push(image: &mut [u8], chan: &ReplayChannel, mut data: &[u8]) -> usize {
+    let mut write = get_u32(image, chan.offset + Channel::O_WRITE) as usize;
+    let read = get_u32(image, chan.offset + Channel::O_READ) as usize;
+    let mut total = 0;
+
+    while !data.is_empty() {
+        let free = if read > write {
+            read - write - 1
+        } else if read == 0 {
+            chan.size - write - 1
+        } else {
+            chan.size - write
+        };
+
+        let count = free.min(data.len());
+        if count == 0 {
+            break;
+        }
+
+        let start = chan.buffer + write;
+        image[start..start + count].copy_from_slice(&data[..count]);
+
+        total += count;
+        write += count;
+        if write >= chan.size {
+            write = 0;
+        }
+
+        data = &data[count..];
+    }
+
+    put_u32(image, chan.offset + Channel::O_WRITE, write as u32);
+
+    total
+}
+
+fn addr(header: &CaptureHeader, offset: usize) -> u32 {
+    header.control_block.wrapping_add(offset as u32)
+}
+
+fn get_u32(image: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&image[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn put_u32(image: &mut [u8], offset: usize, value: u32) {
+    image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Reads `len` bytes, allocating only as much as is actually read so that a corrupted length does
+/// not cause a huge allocation.
+fn read_bytes(reader: impl Read, len: usize) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(len as u64).read_to_end(&mut data)?;
+
+    if data.len() < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(data)
+}
+
+fn read_u8(mut reader: impl Read) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16(mut reader: impl Read) -> io::Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32(mut reader: impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(mut reader: impl Read) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
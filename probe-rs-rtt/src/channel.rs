@@ -1,10 +1,9 @@
-use probe_rs::{config::MemoryRegion, MemoryInterface, Session};
 use scroll::{Pread, LE};
 use std::cmp::min;
 use std::io;
 use std::sync::{Arc, Mutex};
 
-use crate::Error;
+use crate::{Error, Memory, Region, RegionKind};
 
 /// Trait for channel information shared between up and down channels.
 pub trait RttChannel {
@@ -21,7 +20,7 @@ pub trait RttChannel {
 
 #[derive(Debug)]
 pub(crate) struct Channel {
-    session: Arc<Mutex<Session>>,
+    memory: Arc<Mutex<dyn Memory>>,
     number: usize,
     ptr: u32,
     name: Option<String>,
@@ -47,17 +46,17 @@ impl Channel {
     pub(crate) const SIZE: usize = 24;
 
     // Offsets of fields in target memory in bytes
-    const O_NAME: usize = 0;
-    const O_BUFFER_PTR: usize = 4;
-    const O_SIZE: usize = 8;
-    const O_WRITE: usize = 12;
-    const O_READ: usize = 16;
-    const O_FLAGS: usize = 20;
+    pub(crate) const O_NAME: usize = 0;
+    pub(crate) const O_BUFFER_PTR: usize = 4;
+    pub(crate) const O_SIZE: usize = 8;
+    pub(crate) const O_WRITE: usize = 12;
+    pub(crate) const O_READ: usize = 16;
+    pub(crate) const O_FLAGS: usize = 20;
 
     pub(crate) fn from(
-        session: &Arc<Mutex<Session>>,
+        memory: &Arc<Mutex<dyn Memory>>,
         number: usize,
-        memory_map: &[MemoryRegion],
+        regions: &[Region],
         ptr: u32,
         mem: &[u8],
     ) -> Result<Option<Channel>, Error> {
@@ -72,11 +71,11 @@ impl Channel {
         let name = if name_ptr == 0 {
             None
         } else {
-            read_c_string(&mut session.lock().unwrap(), memory_map, name_ptr)?
+            read_c_string(&mut *memory.lock().unwrap(), regions, name_ptr)?
         };
 
         Ok(Some(Channel {
-            session: Arc::clone(session),
+            memory: Arc::clone(memory),
             number,
             ptr,
             name,
@@ -95,10 +94,9 @@ impl Channel {
 
     fn read_pointers(&self, dir: &'static str) -> Result<(u32, u32), Error> {
         let mut block = [0u32; 2];
-        self.session
+        self.memory
             .lock()
             .unwrap()
-            .core(0)?
             .read_32(self.ptr + Self::O_WRITE as u32, block.as_mut())?;
 
         let write: u32 = block[0];
@@ -125,6 +123,19 @@ impl Channel {
 
         Ok((write, read))
     }
+
+    /// Reads the entire ring buffer along with the write and read pointers.
+    pub(crate) fn read_buffer(&self, dir: &'static str) -> Result<(Vec<u8>, u32, u32), Error> {
+        let (write, read) = self.read_pointers(dir)?;
+
+        let mut buf = vec![0u8; self.size as usize];
+        self.memory
+            .lock()
+            .unwrap()
+            .read(self.buffer_ptr, &mut buf)?;
+
+        Ok((buf, write, read))
+    }
 }
 
 /// RTT up (target to host) channel.
@@ -152,9 +163,12 @@ impl UpChannel {
     ///
     /// See [`ChannelMode`] for more information on what the modes mean.
     pub fn mode(&self) -> Result<ChannelMode, Error> {
-        let mut lock = self.0.session.lock().unwrap();
-        let mut core = lock.core(0)?;
-        let flags = core.read_word_32(self.0.ptr + Channel::O_FLAGS as u32)?;
+        let flags = self
+            .0
+            .memory
+            .lock()
+            .unwrap()
+            .read_word_32(self.0.ptr + Channel::O_FLAGS as u32)?;
 
         match flags & 0x3 {
             0 => Ok(ChannelMode::NoBlockSkip),
@@ -170,13 +184,12 @@ impl UpChannel {
     ///
     /// See [`ChannelMode`] for more information on what the modes mean.
     pub fn set_mode(&self, mode: ChannelMode) -> Result<(), Error> {
-        let mut lock = self.0.session.lock().unwrap();
-        let mut core = lock.core(0)?;
+        let mut memory = self.0.memory.lock().unwrap();
 
-        let flags = core.read_word_32(self.0.ptr + Channel::O_FLAGS as u32)?;
+        let flags = memory.read_word_32(self.0.ptr + Channel::O_FLAGS as u32)?;
 
         let new_flags = (flags & !3) | (mode as u32);
-        core.write_word_32(self.0.ptr + Channel::O_FLAGS as u32, new_flags)?;
+        memory.write_word_32(self.0.ptr + Channel::O_FLAGS as u32, new_flags)?;
 
         Ok(())
     }
@@ -193,9 +206,11 @@ impl UpChannel {
                 break;
             }
 
-            let mut lock = self.0.session.lock().unwrap();
-            let mut core = lock.core(0)?;
-            core.read(self.0.buffer_ptr + read, &mut buf[..count])?;
+            self.0
+                .memory
+                .lock()
+                .unwrap()
+                .read(self.0.buffer_ptr + read, &mut buf[..count])?;
 
             total += count;
             read += count as u32;
@@ -221,9 +236,11 @@ impl UpChannel {
 
         if total > 0 {
             // Write read pointer back to target if something was read
-            let mut lock = self.0.session.lock().unwrap();
-            let mut core = lock.core(0)?;
-            core.write_word_32(self.0.ptr + Channel::O_READ as u32, read)?;
+            self.0
+                .memory
+                .lock()
+                .unwrap()
+                .write_word_32(self.0.ptr + Channel::O_READ as u32, read)?;
         }
 
         Ok(total)
@@ -310,9 +327,11 @@ impl DownChannel {
                 break;
             }
 
-            let mut lock = self.0.session.lock().unwrap();
-            let mut core = lock.core(0)?;
-            core.write_8(self.0.buffer_ptr + write, &buf[..count])?;
+            self.0
+                .memory
+                .lock()
+                .unwrap()
+                .write(self.0.buffer_ptr + write, &buf[..count])?;
 
             total += count;
             write += count as u32;
@@ -327,9 +346,11 @@ impl DownChannel {
 
         // Write write pointer back to target
 
-        let mut lock = self.0.session.lock().unwrap();
-        let mut core = lock.core(0)?;
-        core.write_word_32(self.0.ptr + Channel::O_WRITE as u32, write)?;
+        self.0
+            .memory
+            .lock()
+            .unwrap()
+            .write_word_32(self.0.ptr + Channel::O_WRITE as u32, write)?;
 
         Ok(total)
     }
@@ -372,29 +393,31 @@ impl io::Write for DownChannel {
 
 /// Reads a null-terminated string from target memory. Lossy UTF-8 decoding is used.
 fn read_c_string(
-    session: &mut Session,
-    memory_map: &[MemoryRegion],
+    memory: &mut dyn Memory,
+    regions: &[Region],
     ptr: u32,
 ) -> Result<Option<String>, Error> {
     // Find out which memory range contains the pointer
-    let range = memory_map
-        .iter()
-        .filter_map(|r| match r {
-            MemoryRegion::Nvm(r) => Some(&r.range),
-            MemoryRegion::Ram(r) => Some(&r.range),
-            _ => None,
-        })
-        .find(|r| r.contains(&ptr));
-
-    // If the pointer is not within any valid range, return None.
-    let range = match range {
-        Some(r) => r,
+    let range = regions.iter().map(|r| &r.range).find(|r| r.contains(&ptr));
+
+    // Read up to 128 bytes not going past the end of the region. If no non-volatile memory is
+    // known, as with remote backends that were only told where the RAM is, names in flash are not
+    // covered by any region, so try reading anyway. Otherwise the pointer is invalid, return None.
+    let len = match range {
+        Some(r) => min(128, (r.end - ptr) as usize),
+        None if !regions.iter().any(|r| r.kind == RegionKind::Nvm) => 128,
         None => return Ok(None),
     };
 
-    // Read up to 128 bytes not going past the end of the region
-    let mut bytes = vec![0u8; min(128, (range.end - ptr) as usize)];
-    session.core(0)?.read_8(ptr, bytes.as_mut())?;
+    let mut bytes = vec![0u8; len];
+    if let Err(err) = memory.read(ptr, bytes.as_mut()) {
+        if range.is_some() {
+            return Err(err);
+        }
+
+        log::debug!("Could not read channel name at 0x{:08x}: {}", ptr, err);
+        return Ok(None);
+    }
 
     // If the bytes read contain a null, return the preceding part as a string, otherwise None.
     Ok(bytes
@@ -405,7 +428,7 @@ fn read_c_string(
 
 /// Specifies what to do when a channel doesn't have enough buffer space for a complete write on the
 /// target side.
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
 #[repr(u32)]
 pub enum ChannelMode {
     /// Skip writing the data completely if it doesn't fit in its entirety.
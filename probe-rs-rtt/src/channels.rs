@@ -29,6 +29,17 @@ impl<T: RttChannel> Channels<T> {
         self.0.remove(&number)
     }
 
+    /// Removes the first channel with the specified name from the list and returns it.
+    pub fn take_by_name(&mut self, name: &str) -> Option<T> {
+        let number = self
+            .0
+            .iter()
+            .find(|(_, chan)| chan.name() == Some(name))
+            .map(|(&number, _)| number)?;
+
+        self.0.remove(&number)
+    }
+
     /// Gets and iterator over the channels on the list, sorted by number.
     pub fn iter(&self) -> Iter<'_, T> {
         Iter(self.0.iter())
@@ -0,0 +1,599 @@
+//! Sharing one RTT session between several local processes.
+//!
+//! Only one process can own a probe. [`Server`] holds the channels of an [`Rtt`] instance and
+//! serves them over a Unix domain socket, and [`Client`] connects to it, so that e.g. a logger, a
+//! test runner and a debugger can all use RTT on the same target at the same time.
+//!
+//! ## Protocol
+//!
+//! Messages use the [`rpc`](crate::rpc) frame format. Clients send requests with the method numbers
+//! below. All integers are little-endian.
+//!
+//! ```text
+//! method  name         request payload               response payload
+//!      1  list         (empty)                       status: i32, count: u32, channels: [channel]
+//!      2  subscribe    channel: u32                  status: i32
+//!      3  unsubscribe  channel: u32                  status: i32
+//!      4  write        channel: u32, data: [u8]      status: i32, written: u32
+//!      5  get mode     channel: u32                  status: i32, mode: u32
+//!      6  set mode     channel: u32, mode: u32       status: i32
+//! ```
+//!
+//! `status` is zero on success or a negated errno value such as `-2` (`ENOENT`) on failure, in
+//! which case the rest of the response is omitted. Subscriptions and modes refer to up channels,
+//! writes to down channels. Channels in the list are encoded as in [capture](crate::capture) files.
+//!
+//! Data read from an up channel is sent to every subscribed client as a notification with method 1
+//! and the payload `channel: u32, data: [u8]`. Up channels without subscribers are not read, so
+//! their data stays buffered on the target until a client subscribes.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use probe_rs_rtt::daemon::Client;
+//!
+//! let mut client = Client::connect("/tmp/rtt.sock")?;
+//! client.subscribe(0)?;
+//!
+//! loop {
+//!     if let Some((_, data)) = client.read(None)? {
+//!         print!("{}", String::from_utf8_lossy(&data));
+//!     }
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use scroll::{Pread, LE};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::capture::{ChannelInfo, Direction};
+use crate::rpc::{Frame, FrameKind};
+use crate::{ChannelMode, DownChannel, Error, Rtt, UpChannel};
+
+// Method numbers
+const M_LIST: u16 = 1;
+const M_SUBSCRIBE: u16 = 2;
+const M_UNSUBSCRIBE: u16 = 3;
+const M_WRITE: u16 = 4;
+const M_GET_MODE: u16 = 5;
+const M_SET_MODE: u16 = 6;
+
+// Notification method numbers
+const N_DATA: u16 = 1;
+
+// errno values used in responses
+const ENOENT: i32 = 2;
+const EIO: i32 = 5;
+const EINVAL: i32 = 22;
+const ENOSYS: i32 = 38;
+
+/// Serves the channels of an [`Rtt`] instance to [`Client`]s over a Unix domain socket.
+///
+/// See the [module documentation](self) for the protocol.
+#[derive(Debug)]
+pub struct Server {
+    listener: UnixListener,
+    path: PathBuf,
+    up: BTreeMap<usize, UpChannel>,
+    down: BTreeMap<usize, DownChannel>,
+    connections: Vec<Connection>,
+}
+
+#[derive(Debug)]
+struct Connection {
+    stream: UnixStream,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    subscriptions: BTreeSet<usize>,
+}
+
+impl Server {
+    /// Maximum amount of data buffered for a client before it is disconnected for being too slow.
+    pub const MAX_PENDING: usize = 1024 * 1024;
+
+    /// Creates a socket at `path` and takes all remaining channels out of `rtt` to serve them.
+    ///
+    /// A stale socket left behind at `path` is replaced, but binding fails if another server is
+    /// still listening on it. Only the current user can connect to the socket, as clients can
+    /// write to the down channels of the target.
+    pub fn bind(path: impl AsRef<Path>, rtt: &mut Rtt) -> Result<Server, Error> {
+        let path = path.as_ref();
+
+        if fs::symlink_metadata(path)
+            .map(|m| m.file_type().is_socket())
+            .unwrap_or(false)
+        {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("Another server is listening on {}", path.display()),
+                )
+                .into());
+            }
+
+            fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+        Ok(Server {
+            listener,
+            path: path.to_path_buf(),
+            up: rtt.up_channels().drain().map(|c| (c.number(), c)).collect(),
+            down: rtt
+                .down_channels()
+                .drain()
+                .map(|c| (c.number(), c))
+                .collect(),
+            connections: Vec::new(),
+        })
+    }
+
+    /// Returns the path of the socket.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the number of connected clients.
+    pub fn clients(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Accepts new clients, answers their requests and forwards up channel data to subscribers
+    /// without blocking. Errors from individual clients only disconnect the client, while errors
+    /// reading from the target are returned.
+    ///
+    /// Returns the number of bytes moved, so that a loop calling this can sleep when it is zero
+    /// instead of spinning.
+    pub fn poll(&mut self) -> Result<usize, Error> {
+        self.accept()?;
+
+        let mut buf = [0u8; 1024];
+        let mut moved = 0;
+
+        for (&number, up) in &self.up {
+            let subscribed = self
+                .connections
+                .iter()
+                .any(|c| c.subscriptions.contains(&number));
+
+            if !subscribed {
+                continue;
+            }
+
+            let count = up.read(&mut buf)?;
+            if count == 0 {
+                continue;
+            }
+
+            moved += count;
+
+            let mut payload = (number as u32).to_le_bytes().to_vec();
+            payload.extend_from_slice(&buf[..count]);
+
+            let frame = Frame {
+                kind: FrameKind::Notification,
+                method: N_DATA,
+                id: 0,
+                payload,
+            }
+            .encode();
+
+            for conn in &mut self.connections {
+                if conn.subscriptions.contains(&number) {
+                    conn.output.extend_from_slice(&frame);
+                }
+            }
+        }
+
+        let mut i = 0;
+        while i < self.connections.len() {
+            match self.service(i) {
+                Ok(Some(count)) => {
+                    moved += count;
+                    i += 1;
+                }
+                Ok(None) => {
+                    self.connections.remove(i);
+                }
+                Err(err) => {
+                    log::debug!("Disconnecting daemon client: {}", err);
+                    self.connections.remove(i);
+                }
+            }
+        }
+
+        Ok(moved)
+    }
+
+    fn accept(&mut self) -> Result<(), Error> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+
+                    self.connections.push(Connection {
+                        stream,
+                        input: Vec::new(),
+                        output: Vec::new(),
+                        subscriptions: BTreeSet::new(),
+                    });
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Handles input from and writes output to a client. Returns the number of bytes moved, or
+    /// `None` if the client has disconnected.
+    fn service(&mut self, index: usize) -> Result<Option<usize>, Error> {
+        let Server {
+            up,
+            down,
+            connections,
+            ..
+        } = self;
+        let conn = &mut connections[index];
+
+        let mut buf = [0u8; 4096];
+        let mut moved = 0;
+        loop {
+            match conn.stream.read(&mut buf) {
+                Ok(0) => return Ok(None),
+                Ok(count) => {
+                    conn.input.extend_from_slice(&buf[..count]);
+                    moved += count;
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        while let Some((frame, len)) = Frame::decode(&conn.input)? {
+            conn.input.drain(..len);
+
+            if frame.kind != FrameKind::Request {
+                continue;
+            }
+
+            let payload = match handle(up, down, &mut conn.subscriptions, &frame) {
+                Ok(data) => {
+                    let mut payload = 0i32.to_le_bytes().to_vec();
+                    payload.extend_from_slice(&data);
+                    payload
+                }
+                Err(errno) => {
+                    log::debug!(
+                        "Daemon request {} failed with errno {}",
+                        frame.method,
+                        errno
+                    );
+                    (-errno).to_le_bytes().to_vec()
+                }
+            };
+
+            conn.output.extend_from_slice(
+                &Frame {
+                    kind: FrameKind::Response,
+                    method: frame.method,
+                    id: frame.id,
+                    payload,
+                }
+                .encode(),
+            );
+        }
+
+        while !conn.output.is_empty() {
+            match conn.stream.write(&conn.output) {
+                Ok(count) => {
+                    conn.output.drain(..count);
+                    moved += count;
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        if conn.output.len() > Self::MAX_PENDING {
+            return Err(Error::Protocol(String::from(
+                "Client is not reading data fast enough",
+            )));
+        }
+
+        Ok(Some(moved))
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+/// Handles a request and returns the response data following the status.
+fn handle(
+    up: &BTreeMap<usize, UpChannel>,
+    down: &BTreeMap<usize, DownChannel>,
+    subscriptions: &mut BTreeSet<usize>,
+    frame: &Frame,
+) -> Result<Vec<u8>, i32> {
+    let args = &frame.payload[..];
+
+    let channel = || -> Result<usize, i32> {
+        args.pread_with::<u32>(0, LE)
+            .map(|n| n as usize)
+            .map_err(|_| EINVAL)
+    };
+
+    let up_channel = || -> Result<&UpChannel, i32> { up.get(&channel()?).ok_or(ENOENT) };
+
+    match frame.method {
+        M_LIST => {
+            let mut data = ((up.len() + down.len()) as u32).to_le_bytes().to_vec();
+
+            for chan in up.values() {
+                ChannelInfo::from_channel(Direction::Up, chan).encode(&mut data);
+            }
+
+            for chan in down.values() {
+                ChannelInfo::from_channel(Direction::Down, chan).encode(&mut data);
+            }
+
+            Ok(data)
+        }
+        M_SUBSCRIBE => {
+            up_channel()?;
+            subscriptions.insert(channel()?);
+
+            Ok(Vec::new())
+        }
+        M_UNSUBSCRIBE => {
+            subscriptions.remove(&channel()?);
+
+            Ok(Vec::new())
+        }
+        M_WRITE => {
+            let chan = down.get(&channel()?).ok_or(ENOENT)?;
+            let count = chan.write(&args[4..]).map_err(target_error)?;
+
+            Ok((count as u32).to_le_bytes().to_vec())
+        }
+        M_GET_MODE => {
+            let mode = up_channel()?.mode().map_err(target_error)?;
+
+            Ok((mode as u32).to_le_bytes().to_vec())
+        }
+        M_SET_MODE => {
+            let mode = args
+                .pread_with::<u32>(4, LE)
+                .ok()
+                .and_then(mode_from_u32)
+                .ok_or(EINVAL)?;
+
+            up_channel()?.set_mode(mode).map_err(target_error)?;
+
+            Ok(Vec::new())
+        }
+        _ => Err(ENOSYS),
+    }
+}
+
+fn target_error(err: Error) -> i32 {
+    log::warn!("Error accessing target for daemon client: {}", err);
+    EIO
+}
+
+fn mode_from_u32(value: u32) -> Option<ChannelMode> {
+    match value {
+        0 => Some(ChannelMode::NoBlockSkip),
+        1 => Some(ChannelMode::NoBlockTrim),
+        2 => Some(ChannelMode::BlockIfFull),
+        _ => None,
+    }
+}
+
+/// A connection to a [`Server`].
+///
+/// Requests block until the server responds or the timeout expires. Data from subscribed channels
+/// that arrives in the meantime is queued and returned by [`read`](Client::read).
+#[derive(Debug)]
+pub struct Client {
+    stream: UnixStream,
+    input: Vec<u8>,
+    next_id: u32,
+    data: VecDeque<(usize, Vec<u8>)>,
+    timeout: Duration,
+}
+
+impl Client {
+    /// Connects to the server listening at `path`.
+    pub fn connect(path: impl AsRef<Path>) -> Result<Client, Error> {
+        Ok(Client {
+            stream: UnixStream::connect(path)?,
+            input: Vec::new(),
+            next_id: 1,
+            data: VecDeque::new(),
+            timeout: Duration::from_secs(5),
+        })
+    }
+
+    /// Sets how long requests wait for a response. The default is 5 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Returns the channels served by the server.
+    pub fn channels(&mut self) -> Result<Vec<ChannelInfo>, Error> {
+        let data = self.call(M_LIST, Vec::new())?;
+        let count = data
+            .pread_with::<u32>(0, LE)
+            .map_err(|_| Error::Protocol(String::from("Truncated channel list")))?;
+
+        let mut reader = &data[4..];
+        (0..count)
+            .map(|_| ChannelInfo::read_from(&mut reader))
+            .collect()
+    }
+
+    /// Starts receiving data from an up channel.
+    pub fn subscribe(&mut self, channel: usize) -> Result<(), Error> {
+        self.call(M_SUBSCRIBE, (channel as u32).to_le_bytes().to_vec())?;
+
+        Ok(())
+    }
+
+    /// Stops receiving data from an up channel. Data that has already been received is still
+    /// returned by [`read`](Client::read).
+    pub fn unsubscribe(&mut self, channel: usize) -> Result<(), Error> {
+        self.call(M_UNSUBSCRIBE, (channel as u32).to_le_bytes().to_vec())?;
+
+        Ok(())
+    }
+
+    /// Writes to a down channel and returns the number of bytes written, which may be less than
+    /// the length of `data` if the buffer on the target is full.
+    pub fn write(&mut self, channel: usize, data: &[u8]) -> Result<usize, Error> {
+        let mut payload = (channel as u32).to_le_bytes().to_vec();
+        payload.extend_from_slice(data);
+
+        let data = self.call(M_WRITE, payload)?;
+        let count = data
+            .pread_with::<u32>(0, LE)
+            .map_err(|_| Error::Protocol(String::from("Truncated write response")))?;
+
+        Ok(count as usize)
+    }
+
+    /// Returns the mode of an up channel.
+    pub fn mode(&mut self, channel: usize) -> Result<ChannelMode, Error> {
+        let data = self.call(M_GET_MODE, (channel as u32).to_le_bytes().to_vec())?;
+
+        data.pread_with::<u32>(0, LE)
+            .ok()
+            .and_then(mode_from_u32)
+            .ok_or_else(|| Error::Protocol(String::from("Invalid mode in response")))
+    }
+
+    /// Changes the mode of an up channel.
+    pub fn set_mode(&mut self, channel: usize, mode: ChannelMode) -> Result<(), Error> {
+        let mut payload = (channel as u32).to_le_bytes().to_vec();
+        payload.extend_from_slice(&(mode as u32).to_le_bytes());
+
+        self.call(M_SET_MODE, payload)?;
+
+        Ok(())
+    }
+
+    /// Waits for data from a subscribed channel and returns the channel number and the data, or
+    /// `None` if nothing arrived within `timeout`. A timeout of `None` waits indefinitely.
+    pub fn read(&mut self, timeout: Option<Duration>) -> Result<Option<(usize, Vec<u8>)>, Error> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+
+        loop {
+            if let Some(data) = self.data.pop_front() {
+                return Ok(Some(data));
+            }
+
+            match self.receive(deadline)? {
+                Some(frame) => self.dispatch(frame),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Sends a request and waits for its response. Returns the response data following the
+    /// status.
+    fn call(&mut self, method: u16, payload: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+
+        self.stream.write_all(
+            &Frame {
+                kind: FrameKind::Request,
+                method,
+                id,
+                payload,
+            }
+            .encode(),
+        )?;
+
+        let deadline = Instant::now() + self.timeout;
+
+        loop {
+            let frame = self.receive(Some(deadline))?.ok_or(Error::Timeout)?;
+
+            if frame.kind != FrameKind::Response || frame.id != id {
+                self.dispatch(frame);
+                continue;
+            }
+
+            let status: i32 = frame
+                .payload
+                .pread_with(0, LE)
+                .map_err(|_| Error::Protocol(String::from("Truncated response")))?;
+
+            if status != 0 {
+                return Err(Error::Protocol(format!(
+                    "Daemon request {} failed with errno {}",
+                    method, -status
+                )));
+            }
+
+            return Ok(frame.payload[4..].to_vec());
+        }
+    }
+
+    /// Queues data notifications. Anything else is unexpected and ignored.
+    fn dispatch(&mut self, frame: Frame) {
+        if frame.kind == FrameKind::Notification && frame.method == N_DATA {
+            if let Ok(channel) = frame.payload.pread_with::<u32>(0, LE) {
+                self.data
+                    .push_back((channel as usize, frame.payload[4..].to_vec()));
+            }
+        }
+    }
+
+    /// Receives the next frame, or returns `None` if the deadline passes first.
+    fn receive(&mut self, deadline: Option<Instant>) -> Result<Option<Frame>, Error> {
+        let mut buf = [0u8; 4096];
+
+        loop {
+            if let Some((frame, len)) = Frame::decode(&self.input)? {
+                self.input.drain(..len);
+                return Ok(Some(frame));
+            }
+
+            let timeout = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Ok(None);
+                    }
+
+                    Some(deadline - now)
+                }
+                None => None,
+            };
+
+            self.stream.set_read_timeout(timeout)?;
+
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Err(Error::Protocol(String::from("Connection closed by daemon"))),
+                Ok(count) => self.input.extend_from_slice(&buf[..count]),
+                Err(err)
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::TimedOut => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Offline extraction of RTT buffers from memory dumps.
+//!
+//! After a crash, the last output of the target is often still sitting in the up channel buffers.
+//! [`RamDump`] runs the control block detection over a memory dump instead of a live target, and
+//! [`RamDump::extract`] recovers the contents of each up channel.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use probe_rs_rtt::{dump::RamDump, ScanRegion};
+//!
+//! let dump = RamDump::open("ram.bin", 0x2000_0000)?;
+//!
+//! let extraction = dump.extract(&ScanRegion::Ram)?;
+//!
+//! for channel in extraction.channels {
+//!     println!("{}: {}", channel.number, String::from_utf8_lossy(&channel.unread));
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::{Error, Memory, Region, RegionKind, Rtt, ScanRegion};
+
+/// A snapshot of target memory consisting of one or more segments.
+///
+/// Writes only modify the snapshot, so reading from channels works as usual.
+#[derive(Clone, Debug)]
+pub struct RamDump {
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug)]
+struct Segment {
+    kind: RegionKind,
+    base: u32,
+    data: Vec<u8>,
+}
+
+/// Data recovered from an up channel.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChannelDump {
+    /// Number of the channel.
+    pub number: usize,
+
+    /// Name of the channel, if it could be read from the dump.
+    pub name: Option<String>,
+
+    /// Data that had not yet been read by the host.
+    pub unread: Vec<u8>,
+
+    /// The entire ring buffer in chronological order, ending with the most recently written byte.
+    /// The beginning may contain stale or uninitialized data if the buffer has not wrapped around
+    /// yet.
+    pub history: Vec<u8>,
+}
+
+/// The up channel data recovered by [`RamDump::extract`].
+#[derive(Debug)]
+pub struct Extraction {
+    /// The channels whose buffers were recovered.
+    pub channels: Vec<ChannelDump>,
+
+    /// The numbers of the channels whose buffers could not be read, e.g. because they are not
+    /// contained in the dump, along with the error.
+    pub failed: Vec<(usize, Error)>,
+}
+
+impl RamDump {
+    /// Creates a dump of RAM starting at the address `base`.
+    pub fn new(base: u32, data: impl Into<Vec<u8>>) -> RamDump {
+        RamDump {
+            segments: vec![Segment {
+                kind: RegionKind::Ram,
+                base,
+                data: data.into(),
+            }],
+        }
+    }
+
+    /// Reads a raw dump of RAM starting at the address `base` from a file.
+    pub fn open(path: impl AsRef<Path>, base: u32) -> Result<RamDump, Error> {
+        Ok(Self::new(base, fs::read(path)?))
+    }
+
+    /// Adds a segment of non-volatile memory, such as the firmware image. Channel names are
+    /// usually stored in flash, so adding it allows them to be recovered. Non-volatile segments
+    /// are not scanned for the control block.
+    pub fn with_nvm(mut self, base: u32, data: impl Into<Vec<u8>>) -> RamDump {
+        self.segments.push(Segment {
+            kind: RegionKind::Nvm,
+            base,
+            data: data.into(),
+        });
+        self
+    }
+
+    /// Adds another segment of RAM.
+    pub fn with_ram(mut self, base: u32, data: impl Into<Vec<u8>>) -> RamDump {
+        self.segments.push(Segment {
+            kind: RegionKind::Ram,
+            base,
+            data: data.into(),
+        });
+        self
+    }
+
+    /// Detects the control block in the dump and returns an [`Rtt`] instance reading from it.
+    pub fn attach(self, region: &ScanRegion) -> Result<Rtt, Error> {
+        Rtt::attach_region(Arc::new(Mutex::new(self)), region)
+    }
+
+    /// Detects the control block in the dump and returns the contents of every initialized up
+    /// channel.
+    ///
+    /// Only failing to find the control block is an error. Channels whose buffers cannot be read
+    /// are reported in [`Extraction::failed`] without affecting the others.
+    pub fn extract(self, region: &ScanRegion) -> Result<Extraction, Error> {
+        let mut rtt = self.attach(region)?;
+
+        let mut extraction = Extraction {
+            channels: Vec::new(),
+            failed: Vec::new(),
+        };
+
+        for chan in rtt.up_channels().drain() {
+            let (buf, write, read) = match chan.0.read_buffer("up") {
+                Ok(buffer) => buffer,
+                Err(err) => {
+                    extraction.failed.push((chan.number(), err));
+                    continue;
+                }
+            };
+            let (write, read) = (write as usize, read as usize);
+
+            let unread = if read <= write {
+                buf[read..write].to_vec()
+            } else {
+                [&buf[read..], &buf[..write]].concat()
+            };
+
+            extraction.channels.push(ChannelDump {
+                number: chan.number(),
+                name: chan.name().map(String::from),
+                unread,
+                history: [&buf[write..], &buf[..write]].concat(),
+            });
+        }
+
+        Ok(extraction)
+    }
+
+    fn segment(&mut self, address: u32, len: usize) -> Result<(&mut Vec<u8>, usize), Error> {
+        self.segments
+            .iter_mut()
+            .find_map(|s| {
+                let start = address.checked_sub(s.base)? as usize;
+                if start.checked_add(len)? <= s.data.len() {
+                    Some((&mut s.data, start))
+                } else {
+                    None
+                }
+            })
+            .ok_or(Error::InvalidAddress(address))
+    }
+}
+
+impl Memory for RamDump {
+    fn read(&mut self, address: u32, data: &mut [u8]) -> Result<(), Error> {
+        let (segment, start) = self.segment(address, data.len())?;
+        data.copy_from_slice(&segment[start..start + data.len()]);
+
+        Ok(())
+    }
+
+    fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        let (segment, start) = self.segment(address, data.len())?;
+        segment[start..start + data.len()].copy_from_slice(data);
+
+        Ok(())
+    }
+
+    fn regions(&self) -> Vec<Region> {
+        self.segments
+            .iter()
+            .map(|s| Region {
+                kind: s.kind,
+                range: s.base..s.base.saturating_add(s.data.len() as u32),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,327 @@
+//! Host file services for the target, as a non-halting replacement for semihosting.
+//!
+//! [`FileServer`] answers requests sent by the target over an [`Rpc`] channel pair and runs them
+//! against a host directory. All paths are resolved relative to that directory and are not allowed
+//! to escape it. Symlinks in the directory are only followed if they lead to a directory within it,
+//! and files cannot be opened through a symlink at all, which on Unix is enforced when opening the
+//! file with `O_NOFOLLOW`.
+//!
+//! ## Requests
+//!
+//! Requests use the [`rpc`](crate::rpc) frame format with the method numbers below. All integers
+//! are little-endian.
+//!
+//! ```text
+//! method  name   request payload                       response payload
+//!      1  open   flags: u32, path: [u8]                status: i32, handle: u32
+//!      2  read   handle: u32, len: u32                 status: i32, data: [u8]
+//!      3  write  handle: u32, data: [u8]               status: i32, written: u32
+//!      4  close  handle: u32                           status: i32
+//!      5  seek   handle: u32, offset: i64, whence: u8  status: i32, position: u64
+//!      6  time   (empty)                               status: i32, secs: u64, nanos: u32
+//! ```
+//!
+//! `status` is zero on success or a negated errno value such as `-2` (`ENOENT`) on failure, in
+//! which case the rest of the response is omitted. The open flags are a combination of the `O_*`
+//! constants in this module, and `whence` is 0, 1 or 2 for the start of the file, the current
+//! position and the end of the file respectively.
+
+use scroll::{Pread, LE};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::rpc::{Frame, Rpc};
+use crate::Error;
+
+/// Open flag: open the file for reading.
+pub const O_READ: u32 = 1 << 0;
+
+/// Open flag: open the file for writing.
+pub const O_WRITE: u32 = 1 << 1;
+
+/// Open flag: append to the end of the file. Implies [`O_WRITE`].
+pub const O_APPEND: u32 = 1 << 2;
+
+/// Open flag: create the file if it does not exist.
+pub const O_CREATE: u32 = 1 << 3;
+
+/// Open flag: truncate the file when opening it.
+pub const O_TRUNCATE: u32 = 1 << 4;
+
+// Method numbers
+const M_OPEN: u16 = 1;
+const M_READ: u16 = 2;
+const M_WRITE: u16 = 3;
+const M_CLOSE: u16 = 4;
+const M_SEEK: u16 = 5;
+const M_TIME: u16 = 6;
+
+// errno values used in responses
+const EPERM: i32 = 1;
+const ENOENT: i32 = 2;
+const EIO: i32 = 5;
+const EBADF: i32 = 9;
+const EACCES: i32 = 13;
+const EEXIST: i32 = 17;
+const EINVAL: i32 = 22;
+const EMFILE: i32 = 24;
+const ENOSYS: i32 = 38;
+
+/// Serves file requests from the target. See the [module documentation](self) for the protocol.
+///
+/// ## Example
+///
+/// ```no_run
+/// # #[cfg(feature = "probe-rs")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::sync::{Arc, Mutex};
+/// use probe_rs::Probe;
+/// use probe_rs_rtt::{files::FileServer, rpc::Rpc, Rtt};
+///
+/// let probe = Probe::list_all()[0].open()?;
+/// let session = probe.attach("somechip")?;
+/// let mut rtt = Rtt::attach(Arc::new(Mutex::new(session)))?;
+///
+/// let up = rtt.up_channels().take_by_name("files").unwrap();
+/// let down = rtt.down_channels().take_by_name("files").unwrap();
+/// let mut server = FileServer::new(Rpc::new(up, down), "target-files")?;
+///
+/// loop {
+///     server.poll()?;
+/// }
+/// # Ok(())
+/// # }
+/// # #[cfg(not(feature = "probe-rs"))]
+/// # fn main() {}
+/// ```
+#[derive(Debug)]
+pub struct FileServer {
+    rpc: Rpc,
+    root: PathBuf,
+    files: BTreeMap<u32, File>,
+    next_handle: u32,
+}
+
+impl FileServer {
+    /// Maximum number of files the target can have open at the same time.
+    pub const MAX_OPEN_FILES: usize = 16;
+
+    /// Creates a file server that serves files from the `root` directory.
+    pub fn new(rpc: Rpc, root: impl AsRef<Path>) -> Result<FileServer, Error> {
+        Ok(FileServer {
+            rpc,
+            root: fs::canonicalize(root)?,
+            files: BTreeMap::new(),
+            next_handle: 1,
+        })
+    }
+
+    /// Returns the root directory that files are served from.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the underlying RPC endpoint. Notifications received from the target can be read
+    /// through it.
+    pub fn rpc(&mut self) -> &mut Rpc {
+        &mut self.rpc
+    }
+
+    /// Consumes the server, closing any open files, and returns the RPC endpoint.
+    pub fn into_rpc(self) -> Rpc {
+        self.rpc
+    }
+
+    /// Polls for requests from the target and handles them. Returns the number of requests that
+    /// were handled. This method does not block.
+    pub fn poll(&mut self) -> Result<usize, Error> {
+        self.rpc.poll()?;
+
+        let mut handled = 0;
+        while let Some(request) = self.rpc.next_request() {
+            let response = match self.handle(&request) {
+                Ok(mut data) => {
+                    let mut response = 0i32.to_le_bytes().to_vec();
+                    response.append(&mut data);
+                    response
+                }
+                Err(errno) => {
+                    log::debug!(
+                        "File request {} failed with errno {}",
+                        request.method,
+                        errno
+                    );
+                    (-errno).to_le_bytes().to_vec()
+                }
+            };
+
+            self.rpc.respond(&request, &response)?;
+            handled += 1;
+        }
+
+        Ok(handled)
+    }
+
+    fn handle(&mut self, request: &Frame) -> Result<Vec<u8>, i32> {
+        let args = &request.payload[..];
+
+        match request.method {
+            M_OPEN => {
+                let flags: u32 = args.pread_with(0, LE).map_err(|_| EINVAL)?;
+                let path = std::str::from_utf8(&args[4..]).map_err(|_| EINVAL)?;
+
+                self.open(path, flags).map(|h| h.to_le_bytes().to_vec())
+            }
+            M_READ => {
+                let len: u32 = args.pread_with(4, LE).map_err(|_| EINVAL)?;
+                let file = self.file(args)?;
+
+                // Leave room for the status in the response
+                let len = (len as usize).min(Frame::MAX_PAYLOAD - 4);
+                let mut data = vec![0u8; len];
+                let count = file.read(&mut data).map_err(|e| errno(&e))?;
+                data.truncate(count);
+
+                Ok(data)
+            }
+            M_WRITE => {
+                let file = self.file(args)?;
+                let count = file.write(&args[4..]).map_err(|e| errno(&e))?;
+
+                Ok((count as u32).to_le_bytes().to_vec())
+            }
+            M_CLOSE => {
+                let handle: u32 = args.pread_with(0, LE).map_err(|_| EINVAL)?;
+                self.files.remove(&handle).ok_or(EBADF)?;
+
+                Ok(Vec::new())
+            }
+            M_SEEK => {
+                let offset: i64 = args.pread_with(4, LE).map_err(|_| EINVAL)?;
+                let whence: u8 = args.pread_with(12, LE).map_err(|_| EINVAL)?;
+
+                let pos = match whence {
+                    0 if offset >= 0 => SeekFrom::Start(offset as u64),
+                    1 => SeekFrom::Current(offset),
+                    2 => SeekFrom::End(offset),
+                    _ => return Err(EINVAL),
+                };
+
+                let file = self.file(args)?;
+                let pos = file.seek(pos).map_err(|e| errno(&e))?;
+
+                Ok(pos.to_le_bytes().to_vec())
+            }
+            M_TIME => {
+                let now = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map_err(|_| EIO)?;
+
+                let mut data = now.as_secs().to_le_bytes().to_vec();
+                data.extend_from_slice(&now.subsec_nanos().to_le_bytes());
+
+                Ok(data)
+            }
+            _ => Err(ENOSYS),
+        }
+    }
+
+    fn open(&mut self, path: &str, flags: u32) -> Result<u32, i32> {
+        if self.files.len() >= Self::MAX_OPEN_FILES {
+            return Err(EMFILE);
+        }
+
+        let path = self.resolve(path)?;
+
+        let mut options = OpenOptions::new();
+        options
+            .read(flags & O_READ != 0)
+            .write(flags & (O_WRITE | O_APPEND) != 0)
+            .append(flags & O_APPEND != 0)
+            .create(flags & O_CREATE != 0)
+            .truncate(flags & O_TRUNCATE != 0);
+
+        let file = open_no_follow(&options, &path)?;
+
+        log::debug!("Target opened file {}", path.display());
+
+        let handle = self.next_handle;
+        self.next_handle = self.next_handle.wrapping_add(1).max(1);
+        self.files.insert(handle, file);
+
+        Ok(handle)
+    }
+
+    /// Looks up the file for the handle at the start of the request arguments.
+    fn file(&mut self, args: &[u8]) -> Result<&mut File, i32> {
+        let handle: u32 = args.pread_with(0, LE).map_err(|_| EINVAL)?;
+
+        self.files.get_mut(&handle).ok_or(EBADF)
+    }
+
+    /// Resolves a path from the target to a host path within the root directory.
+    fn resolve(&self, path: &str) -> Result<PathBuf, i32> {
+        let path = Path::new(path);
+
+        // Only plain relative paths are allowed, so the result cannot point outside the root.
+        if path
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(EPERM);
+        }
+
+        let path = self.root.join(path);
+
+        // Make sure a symlink in the directory does not lead out of the root either.
+        let parent = path.parent().ok_or(EINVAL)?;
+        let parent = fs::canonicalize(parent).map_err(|e| errno(&e))?;
+        if !parent.starts_with(&self.root) {
+            return Err(EPERM);
+        }
+
+        Ok(path)
+    }
+}
+
+/// Opens a file unless the last component of its path is a symlink, which could point anywhere,
+/// including to a file that does not exist yet and would be created outside the root.
+///
+/// On Unix the kernel checks this while opening, so that a symlink swapped in after resolving the
+/// path is not followed either.
+#[cfg(unix)]
+fn open_no_follow(options: &OpenOptions, path: &Path) -> Result<File, i32> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut options = options.clone();
+    options.custom_flags(libc::O_NOFOLLOW);
+
+    options.open(path).map_err(|e| match e.raw_os_error() {
+        // FreeBSD reports a symlink with EMLINK instead of ELOOP
+        Some(libc::ELOOP) | Some(libc::EMLINK) => EPERM,
+        _ => errno(&e),
+    })
+}
+
+#[cfg(not(unix))]
+fn open_no_follow(options: &OpenOptions, path: &Path) -> Result<File, i32> {
+    if matches!(fs::symlink_metadata(path), Ok(m) if m.file_type().is_symlink()) {
+        return Err(EPERM);
+    }
+
+    options.open(path).map_err(|e| errno(&e))
+}
+
+/// Maps an I/O error to an errno value.
+fn errno(err: &io::Error) -> i32 {
+    match err.kind() {
+        io::ErrorKind::NotFound => ENOENT,
+        io::ErrorKind::PermissionDenied => EACCES,
+        io::ErrorKind::AlreadyExists => EEXIST,
+        io::ErrorKind::InvalidInput => EINVAL,
+        _ => EIO,
+    }
+}
@@ -0,0 +1,362 @@
+//! Target memory access over the GDB Remote Serial Protocol.
+//!
+//! [`GdbMemory`] connects to a gdbserver, such as the one built into QEMU or a vendor debug server,
+//! and reads and writes memory with `m` and `M` packets. This allows RTT to be used without
+//! probe-rs being able to access the target directly.
+//!
+//! gdbservers halt the target when a client connects, and in the default all-stop mode only serve
+//! memory accesses while it is halted. [`GdbMemory::connect`] therefore switches to non-stop mode
+//! if the server supports it, and resumes the target. Otherwise the target is interrupted for each
+//! access and continued afterwards, which is slower and briefly stops the firmware every time.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use std::sync::{Arc, Mutex};
+//! use probe_rs_rtt::{gdb::GdbMemory, Region, RegionKind, Rtt, ScanRegion};
+//!
+//! let ram = 0x2000_0000..0x2001_0000;
+//! let gdb = GdbMemory::connect("localhost:1234")?.with_region(Region {
+//!     kind: RegionKind::Ram,
+//!     range: ram.clone(),
+//! });
+//!
+//! let mut rtt = Rtt::attach_region(Arc::new(Mutex::new(gdb)), &ScanRegion::Range(ram))?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::{Error, Memory, Region};
+
+/// Target memory accessed through a gdbserver.
+///
+/// GDB does not report the memory map of the target, so the regions to use for scanning and for
+/// reading channel names have to be specified with [`with_region`](GdbMemory::with_region).
+#[derive(Debug)]
+pub struct GdbMemory {
+    stream: BufReader<TcpStream>,
+    regions: Vec<Region>,
+    packet_size: usize,
+    non_stop: bool,
+}
+
+impl GdbMemory {
+    // Number of times a packet is resent or requested again after a checksum error
+    const MAX_RETRIES: usize = 3;
+
+    /// Connects to a gdbserver listening at the specified address and resumes the target, in
+    /// non-stop mode if the server supports it.
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<GdbMemory, Error> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+        let mut gdb = GdbMemory {
+            stream: BufReader::new(stream),
+            regions: Vec::new(),
+            packet_size: 512,
+            non_stop: false,
+        };
+
+        // Servers without non-stop support reply with an empty packet
+        gdb.non_stop = gdb.command("QNonStop:1")? == b"OK";
+
+        if gdb.non_stop {
+            let response = gdb.command("vCont;c")?;
+            if response != b"OK" {
+                return Err(Error::Protocol(format!(
+                    "Unexpected response to continue from gdbserver: {}",
+                    String::from_utf8_lossy(&response)
+                )));
+            }
+        } else {
+            gdb.send("c")?;
+        }
+
+        Ok(gdb)
+    }
+
+    /// Returns `true` if the server is in non-stop mode, so that memory is accessed while the
+    /// target keeps running.
+    pub fn is_non_stop(&self) -> bool {
+        self.non_stop
+    }
+
+    /// Adds a known memory region of the target.
+    pub fn with_region(mut self, region: Region) -> GdbMemory {
+        self.regions.push(region);
+        self
+    }
+
+    /// Sets the maximum number of bytes transferred by a single packet. The default is 512, which
+    /// should be supported by most servers.
+    pub fn with_packet_size(mut self, bytes: usize) -> GdbMemory {
+        self.packet_size = bytes.max(1);
+        self
+    }
+
+    /// Runs a memory access while the target is halted, interrupting it before and continuing it
+    /// afterwards unless the server is in non-stop mode.
+    fn halted<T>(
+        &mut self,
+        access: impl FnOnce(&mut GdbMemory) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        if self.non_stop {
+            return access(self);
+        }
+
+        self.stream.get_mut().write_all(&[0x03])?;
+
+        // Skip console output from the target until the stop reply
+        loop {
+            match self.read_packet()?.first() {
+                Some(b'S') | Some(b'T') => break,
+                Some(b'O') => {}
+                Some(b'W') | Some(b'X') => {
+                    return Err(Error::Protocol(String::from("The target has exited")))
+                }
+                _ => {
+                    return Err(Error::Protocol(String::from(
+                        "Unexpected reply to interrupt from gdbserver",
+                    )))
+                }
+            }
+        }
+
+        let result = access(self);
+        let resumed = self.send("c");
+
+        let value = result?;
+        resumed?;
+
+        Ok(value)
+    }
+
+    /// Sends a command packet and returns the response packet.
+    fn command(&mut self, packet: &str) -> Result<Vec<u8>, Error> {
+        self.send(packet)?;
+        self.read_packet()
+    }
+
+    /// Sends a packet and waits for it to be acknowledged, without reading a response.
+    fn send(&mut self, packet: &str) -> Result<(), Error> {
+        let checksum = packet.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        let frame = format!("${}#{:02x}", packet, checksum);
+
+        let mut retries = 0;
+        loop {
+            self.stream.get_mut().write_all(frame.as_bytes())?;
+
+            match self.read_byte()? {
+                b'+' => break,
+                b'-' if retries < Self::MAX_RETRIES => retries += 1,
+                b => {
+                    return Err(Error::Protocol(format!(
+                        "Unexpected acknowledgement {:?} from gdbserver",
+                        b as char
+                    )))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the next packet from the server, acknowledging it.
+    fn read_packet(&mut self) -> Result<Vec<u8>, Error> {
+        let mut retries = 0;
+
+        loop {
+            // Skip anything preceding the start of a packet, e.g. stray acknowledgements
+            let mut discarded = Vec::new();
+            self.stream.read_until(b'$', &mut discarded)?;
+            if discarded.last() != Some(&b'$') {
+                return Err(Error::Protocol(String::from(
+                    "Connection closed by gdbserver",
+                )));
+            }
+
+            let mut data = Vec::new();
+            self.stream.read_until(b'#', &mut data)?;
+            if data.pop() != Some(b'#') {
+                return Err(Error::Protocol(String::from(
+                    "Connection closed by gdbserver",
+                )));
+            }
+
+            let mut checksum = [0u8; 2];
+            self.stream.read_exact(&mut checksum)?;
+
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok());
+            let actual = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+
+            if expected != Some(actual) {
+                if retries == Self::MAX_RETRIES {
+                    return Err(Error::Protocol(String::from(
+                        "Repeated checksum mismatches in packets from gdbserver",
+                    )));
+                }
+
+                log::debug!("Checksum mismatch in packet from gdbserver, requesting resend");
+                self.stream.get_mut().write_all(b"-")?;
+                retries += 1;
+                continue;
+            }
+
+            self.stream.get_mut().write_all(b"+")?;
+
+            return Ok(decode_run_length(&data));
+        }
+    }
+
+    /// Reads memory with a single `m` packet.
+    fn read_memory_packet(&mut self, address: u32, data: &mut [u8]) -> Result<(), Error> {
+        let response = self.command(&format!("m{:x},{:x}", address, data.len()))?;
+        check_error(&response, address)?;
+
+        if response.len() != data.len() * 2 {
+            return Err(Error::Protocol(format!(
+                "gdbserver returned {} bytes while {} were requested",
+                response.len() / 2,
+                data.len()
+            )));
+        }
+
+        for (b, hex) in data.iter_mut().zip(response.chunks(2)) {
+            *b = std::str::from_utf8(hex)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+                .ok_or_else(|| Error::Protocol(String::from("Invalid hex data from gdbserver")))?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes memory with a single `M` packet.
+    fn write_memory_packet(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        let mut packet = format!("M{:x},{:x}:", address, data.len());
+        for b in data {
+            write!(packet, "{:02x}", b).unwrap();
+        }
+
+        let response = self.command(&packet)?;
+        check_error(&response, address)?;
+
+        if response != b"OK" {
+            return Err(Error::Protocol(format!(
+                "Unexpected response to memory write from gdbserver: {}",
+                String::from_utf8_lossy(&response)
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> Result<u8, Error> {
+        let mut b = [0u8];
+        self.stream.read_exact(&mut b)?;
+
+        Ok(b[0])
+    }
+}
+
+impl Memory for GdbMemory {
+    fn read(&mut self, mut address: u32, data: &mut [u8]) -> Result<(), Error> {
+        let packet_size = self.packet_size;
+
+        self.halted(|gdb| {
+            for chunk in data.chunks_mut(packet_size) {
+                gdb.read_memory_packet(address, chunk)?;
+
+                address = address.wrapping_add(chunk.len() as u32);
+            }
+
+            Ok(())
+        })
+    }
+
+    fn write(&mut self, mut address: u32, data: &[u8]) -> Result<(), Error> {
+        let packet_size = self.packet_size;
+
+        self.halted(|gdb| {
+            for chunk in data.chunks(packet_size) {
+                gdb.write_memory_packet(address, chunk)?;
+
+                address = address.wrapping_add(chunk.len() as u32);
+            }
+
+            Ok(())
+        })
+    }
+
+    fn read_32(&mut self, mut address: u32, data: &mut [u32]) -> Result<(), Error> {
+        // Keep each packet a whole number of words so that no word is split between two accesses
+        let words = (self.packet_size / 4).max(1);
+        let mut bytes = vec![0u8; words * 4];
+
+        self.halted(|gdb| {
+            for chunk in data.chunks_mut(words) {
+                let bytes = &mut bytes[..chunk.len() * 4];
+                gdb.read_memory_packet(address, bytes)?;
+
+                for (word, bytes) in chunk.iter_mut().zip(bytes.chunks_exact(4)) {
+                    *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                }
+
+                address = address.wrapping_add(bytes.len() as u32);
+            }
+
+            Ok(())
+        })
+    }
+
+    fn write_word_32(&mut self, address: u32, value: u32) -> Result<(), Error> {
+        // gdbservers such as OpenOCD and pyOCD perform an aligned 4 byte write as a single 32-bit
+        // access, so the word is always sent in one packet whatever the packet size.
+        self.halted(|gdb| gdb.write_memory_packet(address, &value.to_le_bytes()))
+    }
+
+    fn regions(&self) -> Vec<Region> {
+        self.regions.clone()
+    }
+}
+
+/// Checks for an `Exx` error response.
+fn check_error(response: &[u8], address: u32) -> Result<(), Error> {
+    if response.len() == 3 && response[0] == b'E' {
+        Err(Error::InvalidAddress(address))
+    } else if response.is_empty() {
+        Err(Error::Protocol(String::from(
+            "gdbserver does not support memory access",
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Expands run-length encoded packet data, where `x*n` stands for `x` followed by `n - 29`
+/// repetitions of it.
+fn decode_run_length(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut iter = data.iter();
+
+    while let Some(&b) = iter.next() {
+        if b == b'*' {
+            if let (Some(&prev), Some(&count)) = (out.last(), iter.next()) {
+                for _ in 0..count.saturating_sub(29) {
+                    out.push(prev);
+                }
+            }
+        } else {
+            out.push(b);
+        }
+    }
+
+    out
+}
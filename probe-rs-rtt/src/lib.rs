@@ -8,9 +8,17 @@
 //! This crate enables you to read and write via RTT channels. It's also used as a building-block
 //! for probe-rs debugging tools.
 //!
+//! ## Features
+//!
+//! The `probe-rs` feature, which is enabled by default, implements [`Memory`] for probe-rs
+//! sessions. Without it, the crate does not depend on probe-rs or libusb, and can still be used
+//! with memory dumps, capture files and the other [`Memory`] implementations.
+//!
 //! ## Example
 //!
 //! ```no_run
+//! # #[cfg(feature = "probe-rs")]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
 //! use std::sync::{Arc, Mutex};
 //! use probe_rs::Probe;
 //! use probe_rs_rtt::Rtt;
@@ -35,7 +43,10 @@
 //!     output.write(b"Hello, computer!\n")?;
 //! }
 //!
-//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "probe-rs"))]
+//! # fn main() {}
 //! ```
 
 use thiserror::Error;
@@ -43,9 +54,29 @@ use thiserror::Error;
 mod channel;
 pub use channel::*;
 
+pub mod capture;
+
 pub mod channels;
 pub use channels::Channels;
 
+#[cfg(unix)]
+pub mod daemon;
+
+pub mod dump;
+
+pub mod files;
+
+pub mod gdb;
+
+pub mod logger;
+
+mod memory;
+pub use memory::*;
+
+pub mod openocd;
+
+pub mod rpc;
+
 mod rtt;
 pub use rtt::*;
 
@@ -68,7 +99,25 @@ pub enum Error {
     #[error("Control block corrupted: {0}")]
     ControlBlockCorrupted(String),
 
+    /// The memory at the address cannot be accessed.
+    #[error("Memory at 0x{0:08x} cannot be accessed.")]
+    InvalidAddress(u32),
+
+    /// Timed out waiting for a response from the target.
+    #[error("Timed out waiting for a response from the target.")]
+    Timeout,
+
+    /// Data received from the target does not follow the expected protocol. The data contains a
+    /// detailed error.
+    #[error("Protocol error: {0}")]
+    Protocol(String),
+
+    /// Wraps host I/O errors.
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
     /// Wraps errors propagated up from probe-rs.
+    #[cfg(feature = "probe-rs")]
     #[error("Error communicating with probe: {0}")]
     Probe(#[from] probe_rs::Error),
 }
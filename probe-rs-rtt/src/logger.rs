@@ -0,0 +1,251 @@
+//! Forwarding of target log output into the host logging ecosystem.
+//!
+//! [`LogSink`] reads an [`UpChannel`], splits the data into lines and emits each line as a
+//! [`log::Record`], or as a `tracing` event when the `tracing` feature is enabled. Common level
+//! prefixes such as `ERROR`, `WARN:`, `[INFO]` or `0.123456 DEBUG` (as printed by defmt) are
+//! recognized and stripped; lines without a recognized level use a configurable default.
+
+use crate::{Error, UpChannel};
+
+/// Where a [`LogSink`] sends its output.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Backend {
+    /// Emit [`log::Record`]s through the global logger.
+    Log,
+
+    /// Emit `tracing` events. Since `tracing` targets must be static, events use the target
+    /// `probe_rs_rtt::target` and carry the configured target in the `rtt_target` field.
+    #[cfg(feature = "tracing")]
+    Tracing,
+}
+
+/// Reads lines from an up channel and forwards them to the host logger.
+///
+/// Records are emitted under the target `target::<channel name>` by default, or
+/// `target::<channel number>` for unnamed channels. The channel number is also attached to each
+/// record as the `channel` key-value pair, or the `channel` field of `tracing` events.
+///
+/// ## Example
+///
+/// ```no_run
+/// # #[cfg(feature = "probe-rs")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::sync::{Arc, Mutex};
+/// use probe_rs::Probe;
+/// use probe_rs_rtt::{logger::LogSink, Rtt};
+///
+/// let probe = Probe::list_all()[0].open()?;
+/// let session = probe.attach("somechip")?;
+/// let mut rtt = Rtt::attach(Arc::new(Mutex::new(session)))?;
+///
+/// let mut sink = LogSink::new(rtt.up_channels().take(0).unwrap())
+///     .with_default_level(log::Level::Debug);
+///
+/// loop {
+///     sink.poll()?;
+/// }
+/// # Ok(())
+/// # }
+/// # #[cfg(not(feature = "probe-rs"))]
+/// # fn main() {}
+/// ```
+#[derive(Debug)]
+pub struct LogSink {
+    channel: UpChannel,
+    target: String,
+    default_level: log::Level,
+    backend: Backend,
+    line: Vec<u8>,
+}
+
+impl LogSink {
+    /// Lines longer than this are split to avoid unbounded buffering.
+    pub const MAX_LINE_LENGTH: usize = 4096;
+
+    /// Creates a sink that reads from the specified channel.
+    pub fn new(channel: UpChannel) -> LogSink {
+        let target = match channel.name() {
+            Some(name) => format!("target::{}", name),
+            None => format!("target::{}", channel.number()),
+        };
+
+        LogSink {
+            channel,
+            target,
+            default_level: log::Level::Info,
+            backend: Backend::Log,
+            line: Vec::new(),
+        }
+    }
+
+    /// Sets the target that records are emitted under.
+    pub fn with_target(mut self, target: impl Into<String>) -> LogSink {
+        self.target = target.into();
+        self
+    }
+
+    /// Sets the level used for lines that do not start with a recognized level prefix. The
+    /// default is [`log::Level::Info`].
+    pub fn with_default_level(mut self, level: log::Level) -> LogSink {
+        self.default_level = level;
+        self
+    }
+
+    /// Sets where records are sent. The default is [`Backend::Log`].
+    pub fn with_backend(mut self, backend: Backend) -> LogSink {
+        self.backend = backend;
+        self
+    }
+
+    /// Returns the target that records are emitted under.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// Returns the channel being read.
+    pub fn channel(&self) -> &UpChannel {
+        &self.channel
+    }
+
+    /// Consumes the sink and returns the channel. A partially received line is discarded.
+    pub fn into_channel(self) -> UpChannel {
+        self.channel
+    }
+
+    /// Reads all available data from the channel and emits any complete lines. Returns the number
+    /// of lines emitted. This method does not block.
+    pub fn poll(&mut self) -> Result<usize, Error> {
+        let mut buf = [0u8; 1024];
+        let mut lines = 0;
+
+        loop {
+            let count = self.channel.read(&mut buf)?;
+            if count == 0 {
+                break;
+            }
+
+            for &b in &buf[..count] {
+                if b == b'\n' {
+                    self.emit_line();
+                    lines += 1;
+                } else {
+                    self.line.push(b);
+
+                    if self.line.len() >= Self::MAX_LINE_LENGTH {
+                        self.emit_line();
+                        lines += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(lines)
+    }
+
+    /// Emits a partially received line, if there is one.
+    pub fn flush(&mut self) {
+        if !self.line.is_empty() {
+            self.emit_line();
+        }
+    }
+
+    fn emit_line(&mut self) {
+        let line = String::from_utf8_lossy(&self.line).into_owned();
+        self.line.clear();
+
+        let line = line.trim_end_matches('\r');
+        let (level, message) = parse_level(line).unwrap_or((self.default_level, line));
+
+        match self.backend {
+            Backend::Log => log::logger().log(
+                &log::Record::builder()
+                    .level(level)
+                    .target(&self.target)
+                    .key_values(&[("channel", self.channel.number())])
+                    .args(format_args!("{}", message))
+                    .build(),
+            ),
+            #[cfg(feature = "tracing")]
+            Backend::Tracing => {
+                emit_tracing_event(level, &self.target, self.channel.number(), message)
+            }
+        }
+    }
+}
+
+/// Characters that separate a level prefix from the message.
+const SEPARATORS: [char; 4] = [' ', ':', '-', '\t'];
+
+/// Recognizes a log level prefix at the start of a line.
+///
+/// Returns the level and the rest of the line with the prefix and following separators removed,
+/// or `None` if the line does not start with a level. An optional leading timestamp consisting of
+/// digits, `.` and `:` is skipped, and the level may be enclosed in `[]` or `<>`.
+pub fn parse_level(line: &str) -> Option<(log::Level, &str)> {
+    let mut rest = line.trim_start();
+
+    // Skip a leading numeric timestamp, e.g. "0.001234" or "12:34:56.789"
+    let timestamp_len = rest
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == ':'))
+        .unwrap_or(rest.len());
+    if timestamp_len > 0 && rest[timestamp_len..].starts_with(' ') {
+        rest = rest[timestamp_len..].trim_start();
+    }
+
+    let (word, after) = match rest.chars().next() {
+        Some(open @ '[') | Some(open @ '<') => {
+            let close = if open == '[' { ']' } else { '>' };
+            let end = rest.find(close)?;
+            (rest[1..end].trim().to_ascii_uppercase(), &rest[end + 1..])
+        }
+        _ => {
+            // Bare words must be upper case so that ordinary sentences are not matched
+            let end = rest
+                .find(|c: char| !c.is_ascii_uppercase())
+                .unwrap_or(rest.len());
+            (rest[..end].to_string(), &rest[end..])
+        }
+    };
+
+    let level = match word.as_str() {
+        "ERROR" | "ERR" => log::Level::Error,
+        "WARN" | "WARNING" => log::Level::Warn,
+        "INFO" => log::Level::Info,
+        "DEBUG" | "DBG" => log::Level::Debug,
+        "TRACE" => log::Level::Trace,
+        _ => return None,
+    };
+
+    // The level must be followed by a separator, so that e.g. "INFORMATION" is not mistaken for one.
+    if !after.is_empty() && !after.starts_with(SEPARATORS) {
+        return None;
+    }
+
+    let message = after.trim_start_matches(SEPARATORS);
+
+    Some((level, message))
+}
+
+#[cfg(feature = "tracing")]
+fn emit_tracing_event(level: log::Level, target: &str, channel: usize, message: &str) {
+    macro_rules! event {
+        ($level:expr) => {
+            tracing::event!(
+                target: "probe_rs_rtt::target",
+                $level,
+                rtt_target = target,
+                channel,
+                "{}",
+                message
+            )
+        };
+    }
+
+    match level {
+        log::Level::Error => event!(tracing::Level::ERROR),
+        log::Level::Warn => event!(tracing::Level::WARN),
+        log::Level::Info => event!(tracing::Level::INFO),
+        log::Level::Debug => event!(tracing::Level::DEBUG),
+        log::Level::Trace => event!(tracing::Level::TRACE),
+    }
+}
@@ -0,0 +1,120 @@
+#[cfg(feature = "probe-rs")]
+use probe_rs::{config::MemoryRegion, MemoryInterface, Session};
+use std::fmt;
+use std::ops::Range;
+
+use crate::Error;
+
+/// Access to target memory.
+///
+/// RTT only needs to read and write target memory, so anything implementing this trait can be used
+/// with [`Rtt`](crate::Rtt). With the `probe-rs` feature, it is implemented for probe-rs `Session`s,
+/// which access the memory of core 0.
+pub trait Memory: fmt::Debug {
+    /// Reads bytes from target memory.
+    fn read(&mut self, address: u32, data: &mut [u8]) -> Result<(), Error>;
+
+    /// Writes bytes to target memory.
+    fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Error>;
+
+    /// Reads 32-bit words from target memory. Implementations should use 32-bit accesses so that
+    /// the values cannot be torn by the target updating them concurrently.
+    fn read_32(&mut self, address: u32, data: &mut [u32]) -> Result<(), Error> {
+        let mut bytes = vec![0u8; data.len() * 4];
+        self.read(address, &mut bytes)?;
+
+        for (word, bytes) in data.iter_mut().zip(bytes.chunks_exact(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        Ok(())
+    }
+
+    /// Reads a single 32-bit word from target memory.
+    fn read_word_32(&mut self, address: u32) -> Result<u32, Error> {
+        let mut word = [0u32];
+        self.read_32(address, &mut word)?;
+
+        Ok(word[0])
+    }
+
+    /// Writes a single 32-bit word to target memory. RTT uses this to update the offsets and flags
+    /// of channels, which the target accesses at the same time, so implementations should use a
+    /// single 32-bit access if the memory supports it. The default implementation writes the bytes
+    /// with [`write`](Memory::write), which may not be a single access.
+    fn write_word_32(&mut self, address: u32, value: u32) -> Result<(), Error> {
+        self.write(address, &value.to_le_bytes())
+    }
+
+    /// Returns the known memory regions of the target. Used when scanning for the control block
+    /// and for bounding reads of channel names.
+    fn regions(&self) -> Vec<Region>;
+}
+
+/// The kind of a memory [`Region`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RegionKind {
+    /// RAM, which is scanned for the control block.
+    Ram,
+
+    /// Non-volatile memory, where e.g. channel names may be stored.
+    Nvm,
+}
+
+/// A region of target memory.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Region {
+    /// The kind of memory.
+    pub kind: RegionKind,
+
+    /// The address range of the region.
+    pub range: Range<u32>,
+}
+
+#[cfg(feature = "probe-rs")]
+impl Memory for Session {
+    fn read(&mut self, address: u32, data: &mut [u8]) -> Result<(), Error> {
+        self.core(0)?.read(address, data)?;
+
+        Ok(())
+    }
+
+    fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        self.core(0)?.write_8(address, data)?;
+
+        Ok(())
+    }
+
+    fn read_32(&mut self, address: u32, data: &mut [u32]) -> Result<(), Error> {
+        self.core(0)?.read_32(address, data)?;
+
+        Ok(())
+    }
+
+    fn read_word_32(&mut self, address: u32) -> Result<u32, Error> {
+        Ok(self.core(0)?.read_word_32(address)?)
+    }
+
+    fn write_word_32(&mut self, address: u32, value: u32) -> Result<(), Error> {
+        self.core(0)?.write_word_32(address, value)?;
+
+        Ok(())
+    }
+
+    fn regions(&self) -> Vec<Region> {
+        self.memory_map()
+            .iter()
+            .filter_map(|r| match r {
+                MemoryRegion::Ram(r) => Some(Region {
+                    kind: RegionKind::Ram,
+                    range: r.range.clone(),
+                }),
+                MemoryRegion::Nvm(r) => Some(Region {
+                    kind: RegionKind::Nvm,
+                    range: r.range.clone(),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+}
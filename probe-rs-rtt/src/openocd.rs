@@ -0,0 +1,219 @@
+//! Target memory access over the OpenOCD TCL RPC interface.
+//!
+//! [`OpenOcdMemory`] connects to the TCL server of a running OpenOCD instance (port 6666 by
+//! default) and reads and writes memory with the `read_memory` and `write_memory` commands. This
+//! allows RTT to be used with targets and debug adapters that are only supported by OpenOCD.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use std::sync::{Arc, Mutex};
+//! use probe_rs_rtt::{openocd::OpenOcdMemory, Region, RegionKind, Rtt, ScanRegion};
+//!
+//! let ram = 0x2000_0000..0x2001_0000;
+//! let openocd = OpenOcdMemory::connect("localhost:6666")?.with_region(Region {
+//!     kind: RegionKind::Ram,
+//!     range: ram.clone(),
+//! });
+//!
+//! let mut rtt = Rtt::attach_region(Arc::new(Mutex::new(openocd)), &ScanRegion::Range(ram))?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::{Error, Memory, Region};
+
+// Terminates commands and responses on the TCL RPC connection
+const TERMINATOR: u8 = 0x1a;
+
+/// Target memory accessed through the TCL RPC server of OpenOCD.
+///
+/// OpenOCD does not report the memory map of the target over this interface, so the regions to
+/// use for scanning and for reading channel names have to be specified with
+/// [`with_region`](OpenOcdMemory::with_region).
+#[derive(Debug)]
+pub struct OpenOcdMemory {
+    stream: BufReader<TcpStream>,
+    regions: Vec<Region>,
+    chunk_size: usize,
+}
+
+impl OpenOcdMemory {
+    /// Connects to an OpenOCD TCL server listening at the specified address.
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<OpenOcdMemory, Error> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+        Ok(OpenOcdMemory {
+            stream: BufReader::new(stream),
+            regions: Vec::new(),
+            chunk_size: 1024,
+        })
+    }
+
+    /// Adds a known memory region of the target.
+    pub fn with_region(mut self, region: Region) -> OpenOcdMemory {
+        self.regions.push(region);
+        self
+    }
+
+    /// Sets the maximum number of bytes transferred by a single command. The default is 1024.
+    pub fn with_chunk_size(mut self, bytes: usize) -> OpenOcdMemory {
+        // Keep chunks word aligned so that 32-bit reads are never split
+        self.chunk_size = (bytes / 4).max(1) * 4;
+        self
+    }
+
+    /// Runs a TCL command and returns its result. Errors raised by the command are returned as
+    /// `Error::Protocol`.
+    pub fn command(&mut self, command: &str) -> Result<String, Error> {
+        self.eval(command)?
+            .map_err(|message| Error::Protocol(format!("OpenOCD command failed: {}", message)))
+    }
+
+    /// Runs a TCL command, returning either its result or the error message raised by it.
+    fn eval(&mut self, command: &str) -> Result<Result<String, String>, Error> {
+        let wrapped = format!(
+            "if {{[catch {{{}}} r]}} {{concat ERR $r}} else {{concat OK $r}}{}",
+            command, TERMINATOR as char
+        );
+
+        self.stream.get_mut().write_all(wrapped.as_bytes())?;
+
+        let mut response = Vec::new();
+        self.stream.read_until(TERMINATOR, &mut response)?;
+        if response.pop() != Some(TERMINATOR) {
+            return Err(Error::Protocol(String::from(
+                "Connection closed by OpenOCD",
+            )));
+        }
+
+        let response = String::from_utf8_lossy(&response);
+        let response = response.trim();
+
+        if let Some(result) = response.strip_prefix("OK") {
+            Ok(Ok(result.trim_start().to_string()))
+        } else if let Some(message) = response.strip_prefix("ERR") {
+            Ok(Err(message.trim_start().to_string()))
+        } else {
+            Err(Error::Protocol(format!(
+                "Unexpected response from OpenOCD: {}",
+                response
+            )))
+        }
+    }
+
+    /// Runs a memory access command, treating errors as an inaccessible address.
+    fn memory_command(&mut self, command: &str, address: u32) -> Result<String, Error> {
+        self.eval(command)?.map_err(|message| {
+            log::debug!("OpenOCD memory access failed: {}", message);
+            Error::InvalidAddress(address)
+        })
+    }
+
+    /// Reads `count` values of `width` bits each.
+    fn read_memory(&mut self, address: u32, width: u32, count: usize) -> Result<Vec<u32>, Error> {
+        let result = self.memory_command(
+            &format!("read_memory 0x{:08x} {} {}", address, width, count),
+            address,
+        )?;
+
+        let values = result
+            .split_whitespace()
+            .map(parse_value)
+            .collect::<Option<Vec<u32>>>()
+            .ok_or_else(|| {
+                Error::Protocol(format!("Invalid memory data from OpenOCD: {}", result))
+            })?;
+
+        if values.len() != count {
+            return Err(Error::Protocol(format!(
+                "OpenOCD returned {} values while {} were requested",
+                values.len(),
+                count
+            )));
+        }
+
+        Ok(values)
+    }
+
+    /// Writes values of `width` bits each.
+    fn write_memory(
+        &mut self,
+        address: u32,
+        width: u32,
+        values: impl Iterator<Item = u32>,
+    ) -> Result<(), Error> {
+        let mut command = format!("write_memory 0x{:08x} {} {{", address, width);
+        for (i, value) in values.enumerate() {
+            if i > 0 {
+                command.push(' ');
+            }
+
+            write!(command, "0x{:x}", value).unwrap();
+        }
+        command.push('}');
+
+        self.memory_command(&command, address)?;
+
+        Ok(())
+    }
+}
+
+impl Memory for OpenOcdMemory {
+    fn read(&mut self, mut address: u32, data: &mut [u8]) -> Result<(), Error> {
+        for chunk in data.chunks_mut(self.chunk_size) {
+            let values = self.read_memory(address, 8, chunk.len())?;
+
+            for (b, value) in chunk.iter_mut().zip(values) {
+                *b = value as u8;
+            }
+
+            address = address.wrapping_add(chunk.len() as u32);
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, mut address: u32, data: &[u8]) -> Result<(), Error> {
+        for chunk in data.chunks(self.chunk_size) {
+            self.write_memory(address, 8, chunk.iter().map(|&b| b as u32))?;
+
+            address = address.wrapping_add(chunk.len() as u32);
+        }
+
+        Ok(())
+    }
+
+    fn read_32(&mut self, mut address: u32, data: &mut [u32]) -> Result<(), Error> {
+        for chunk in data.chunks_mut(self.chunk_size / 4) {
+            let values = self.read_memory(address, 32, chunk.len())?;
+            chunk.copy_from_slice(&values);
+
+            address = address.wrapping_add(chunk.len() as u32 * 4);
+        }
+
+        Ok(())
+    }
+
+    fn write_word_32(&mut self, address: u32, value: u32) -> Result<(), Error> {
+        self.write_memory(address, 32, std::iter::once(value))
+    }
+
+    fn regions(&self) -> Vec<Region> {
+        self.regions.clone()
+    }
+}
+
+/// Parses a value as returned by `read_memory`, which is hexadecimal with a `0x` prefix.
+fn parse_value(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
@@ -0,0 +1,416 @@
+//! Request/response RPC over a pair of RTT channels.
+//!
+//! [`Rpc`] pairs an [`UpChannel`] with a [`DownChannel`] and exchanges framed messages over them.
+//! Requests sent by the host are tagged with an ID, and responses from the target are matched back
+//! to their request by that ID. Unsolicited messages from the target are queued separately as
+//! notifications, and the target can also send requests of its own to the host.
+//!
+//! ## Frame format
+//!
+//! Every message is a frame consisting of a 12 byte header followed by the payload. All fields are
+//! little-endian.
+//!
+//! ```text
+//! offset  size  field
+//!      0     1  kind (1 = request, 2 = response, 3 = notification)
+//!      1     1  reserved, must be zero
+//!      2     2  method number
+//!      4     4  request ID (echoed back in the response, zero for notifications)
+//!      8     4  payload length in bytes
+//!     12     n  payload
+//! ```
+//!
+//! The target should write each frame with a single RTT write so that a frame is never partially
+//! skipped by [`ChannelMode::NoBlockSkip`](crate::ChannelMode::NoBlockSkip).
+
+use scroll::{Pread, LE};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{DownChannel, Error, UpChannel};
+
+/// The kind of a [`Frame`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum FrameKind {
+    /// A request that expects a response with the same ID.
+    Request = 1,
+
+    /// A response to a request.
+    Response = 2,
+
+    /// A message that does not expect a response.
+    Notification = 3,
+}
+
+impl FrameKind {
+    fn from_u8(value: u8) -> Option<FrameKind> {
+        match value {
+            1 => Some(FrameKind::Request),
+            2 => Some(FrameKind::Response),
+            3 => Some(FrameKind::Notification),
+            _ => None,
+        }
+    }
+}
+
+/// A single RPC message.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Frame {
+    /// The kind of the frame.
+    pub kind: FrameKind,
+
+    /// Application defined method number.
+    pub method: u16,
+
+    /// Request ID. Responses carry the ID of the request they answer.
+    pub id: u32,
+
+    /// Application defined payload.
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Size of the frame header in bytes.
+    pub const HEADER_SIZE: usize = 12;
+
+    /// Maximum payload size accepted when decoding. Anything larger is treated as corruption.
+    pub const MAX_PAYLOAD: usize = 64 * 1024;
+
+    // Offsets of fields in the header in bytes
+    const O_KIND: usize = 0;
+    const O_RESERVED: usize = 1;
+    const O_METHOD: usize = 2;
+    const O_ID: usize = 4;
+    const O_LEN: usize = 8;
+
+    /// Encodes the frame into bytes.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::HEADER_SIZE + self.payload.len());
+
+        buf.push(self.kind as u8);
+        buf.push(0);
+        buf.extend_from_slice(&self.method.to_le_bytes());
+        buf.extend_from_slice(&self.id.to_le_bytes());
+        buf.extend_from_slice(&(self.payload.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.payload);
+
+        buf
+    }
+
+    /// Tries to decode a frame from the start of `buf`.
+    ///
+    /// Returns the frame and the number of bytes it occupied, or `None` if `buf` does not contain a
+    /// complete frame yet.
+    pub fn decode(buf: &[u8]) -> Result<Option<(Frame, usize)>, Error> {
+        if buf.len() < Self::HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let kind = FrameKind::from_u8(buf[Self::O_KIND])
+            .ok_or_else(|| Error::Protocol(format!("Invalid frame kind {}", buf[Self::O_KIND])))?;
+
+        if buf[Self::O_RESERVED] != 0 {
+            return Err(Error::Protocol(format!(
+                "Invalid reserved frame header byte {}",
+                buf[Self::O_RESERVED]
+            )));
+        }
+
+        let method: u16 = buf.pread_with(Self::O_METHOD, LE).unwrap();
+        let id: u32 = buf.pread_with(Self::O_ID, LE).unwrap();
+        let len = buf.pread_with::<u32>(Self::O_LEN, LE).unwrap() as usize;
+
+        if len > Self::MAX_PAYLOAD {
+            return Err(Error::Protocol(format!(
+                "Frame payload length {} exceeds maximum of {}",
+                len,
+                Self::MAX_PAYLOAD
+            )));
+        }
+
+        let end = Self::HEADER_SIZE + len;
+        if buf.len() < end {
+            return Ok(None);
+        }
+
+        Ok(Some((
+            Frame {
+                kind,
+                method,
+                id,
+                payload: buf[Self::HEADER_SIZE..end].to_vec(),
+            },
+            end,
+        )))
+    }
+}
+
+/// Request/response RPC over an up/down channel pair.
+///
+/// See the [module documentation](self) for the frame format.
+///
+/// ## Example
+///
+/// ```no_run
+/// # #[cfg(feature = "probe-rs")]
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::sync::{Arc, Mutex};
+/// use probe_rs::Probe;
+/// use probe_rs_rtt::{rpc::Rpc, Rtt};
+///
+/// let probe = Probe::list_all()[0].open()?;
+/// let session = probe.attach("somechip")?;
+/// let mut rtt = Rtt::attach(Arc::new(Mutex::new(session)))?;
+///
+/// let up = rtt.up_channels().take_by_name("rpc").unwrap();
+/// let down = rtt.down_channels().take_by_name("rpc").unwrap();
+/// let mut rpc = Rpc::new(up, down);
+///
+/// let version = rpc.call(1, &[])?;
+/// println!("Firmware version: {:?}", version);
+/// # Ok(())
+/// # }
+/// # #[cfg(not(feature = "probe-rs"))]
+/// # fn main() {}
+/// ```
+#[derive(Debug)]
+pub struct Rpc {
+    up: UpChannel,
+    down: DownChannel,
+    timeout: Duration,
+    next_id: u32,
+    rx: Vec<u8>,
+    tx: Vec<u8>,
+    pending: BTreeSet<u32>,
+    responses: BTreeMap<u32, Frame>,
+    notifications: VecDeque<Frame>,
+    requests: VecDeque<Frame>,
+}
+
+impl Rpc {
+    // Time to wait between polls while blocking
+    const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+    /// Creates an RPC endpoint communicating over the specified channels.
+    pub fn new(up: UpChannel, down: DownChannel) -> Rpc {
+        Rpc {
+            up,
+            down,
+            timeout: Duration::from_secs(1),
+            next_id: 1,
+            rx: Vec::new(),
+            tx: Vec::new(),
+            pending: BTreeSet::new(),
+            responses: BTreeMap::new(),
+            notifications: VecDeque::new(),
+            requests: VecDeque::new(),
+        }
+    }
+
+    /// Returns the up channel used for receiving.
+    pub fn up_channel(&self) -> &UpChannel {
+        &self.up
+    }
+
+    /// Returns the down channel used for sending.
+    pub fn down_channel(&self) -> &DownChannel {
+        &self.down
+    }
+
+    /// Consumes the endpoint and returns the channels. Any buffered data is lost.
+    pub fn into_channels(self) -> (UpChannel, DownChannel) {
+        (self.up, self.down)
+    }
+
+    /// Returns the timeout used by blocking calls.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Sets the timeout used by blocking calls. The default is one second.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Sends a request and blocks until the matching response arrives or the timeout expires.
+    ///
+    /// Notifications and requests from the target that arrive while waiting are queued and can be
+    /// retrieved with [`next_notification`](Rpc::next_notification) and
+    /// [`next_request`](Rpc::next_request).
+    ///
+    /// If the call times out before any of the request has been written to the down channel, such
+    /// as when the target is not reading it, the request is dropped. Otherwise the target still
+    /// receives it, and its response is discarded when it arrives.
+    pub fn call(&mut self, method: u16, args: &[u8]) -> Result<Vec<u8>, Error> {
+        let id = self.start(method, args)?;
+        let deadline = Instant::now() + self.timeout;
+
+        loop {
+            if let Some(response) = self.poll_response(id)? {
+                return Ok(response);
+            }
+
+            if Instant::now() >= deadline {
+                self.pending.remove(&id);
+
+                // The request is the last data queued, and the unsent data is at the end of it
+                let len = Frame::HEADER_SIZE + args.len();
+                if self.tx.len() >= len {
+                    self.tx.truncate(self.tx.len() - len);
+                }
+
+                return Err(Error::Timeout);
+            }
+
+            thread::sleep(Self::POLL_INTERVAL);
+        }
+    }
+
+    /// Sends a request without waiting for the response and returns its ID.
+    ///
+    /// Use [`poll_response`](Rpc::poll_response) to check for the response. This can be used to
+    /// drive calls from an event loop or an async task instead of blocking.
+    pub fn start(&mut self, method: u16, args: &[u8]) -> Result<u32, Error> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+
+        self.queue(&Frame {
+            kind: FrameKind::Request,
+            method,
+            id,
+            payload: args.to_vec(),
+        });
+        self.pending.insert(id);
+
+        self.flush()?;
+
+        Ok(id)
+    }
+
+    /// Polls the channels and returns the response for the request with the specified ID if it has
+    /// arrived.
+    pub fn poll_response(&mut self, id: u32) -> Result<Option<Vec<u8>>, Error> {
+        self.poll()?;
+
+        Ok(self.responses.remove(&id).map(|f| f.payload))
+    }
+
+    /// Stops waiting for the response to the request with the specified ID. A response arriving
+    /// later is discarded.
+    pub fn cancel(&mut self, id: u32) {
+        self.pending.remove(&id);
+        self.responses.remove(&id);
+    }
+
+    /// Sends a notification to the target.
+    pub fn notify(&mut self, method: u16, payload: &[u8]) -> Result<(), Error> {
+        self.queue(&Frame {
+            kind: FrameKind::Notification,
+            method,
+            id: 0,
+            payload: payload.to_vec(),
+        });
+
+        self.flush()
+    }
+
+    /// Sends a response to a request received from the target.
+    pub fn respond(&mut self, request: &Frame, payload: &[u8]) -> Result<(), Error> {
+        self.queue(&Frame {
+            kind: FrameKind::Response,
+            method: request.method,
+            id: request.id,
+            payload: payload.to_vec(),
+        });
+
+        self.flush()
+    }
+
+    /// Returns the next notification received from the target, if any.
+    ///
+    /// This does not poll the channels, call [`poll`](Rpc::poll) first to receive new data.
+    pub fn next_notification(&mut self) -> Option<Frame> {
+        self.notifications.pop_front()
+    }
+
+    /// Returns the next request received from the target, if any. Answer it with
+    /// [`respond`](Rpc::respond).
+    ///
+    /// This does not poll the channels, call [`poll`](Rpc::poll) first to receive new data.
+    pub fn next_request(&mut self) -> Option<Frame> {
+        self.requests.pop_front()
+    }
+
+    /// Writes out pending data and reads and dispatches any frames received from the target. This
+    /// method does not block.
+    pub fn poll(&mut self) -> Result<(), Error> {
+        self.flush()?;
+
+        let mut buf = [0u8; 1024];
+        loop {
+            let count = self.up.read(&mut buf)?;
+            if count == 0 {
+                break;
+            }
+
+            self.rx.extend_from_slice(&buf[..count]);
+        }
+
+        let mut consumed = 0;
+        let result = loop {
+            match Frame::decode(&self.rx[consumed..]) {
+                Ok(Some((frame, len))) => {
+                    consumed += len;
+                    self.dispatch(frame);
+                }
+                Ok(None) => break Ok(()),
+                Err(err) => {
+                    // The stream cannot be resynchronized, so discard everything received so far.
+                    consumed = self.rx.len();
+                    break Err(err);
+                }
+            }
+        };
+
+        self.rx.drain(..consumed);
+
+        result
+    }
+
+    fn dispatch(&mut self, frame: Frame) {
+        match frame.kind {
+            FrameKind::Response => {
+                if self.pending.remove(&frame.id) {
+                    self.responses.insert(frame.id, frame);
+                } else {
+                    log::warn!(
+                        "Discarding response with unknown ID {} on up channel {}",
+                        frame.id,
+                        self.up.number()
+                    );
+                }
+            }
+            FrameKind::Notification => self.notifications.push_back(frame),
+            FrameKind::Request => self.requests.push_back(frame),
+        }
+    }
+
+    fn queue(&mut self, frame: &Frame) {
+        self.tx.extend_from_slice(&frame.encode());
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        while !self.tx.is_empty() {
+            let count = self.down.write(&self.tx)?;
+            if count == 0 {
+                break;
+            }
+
+            self.tx.drain(..count);
+        }
+
+        Ok(())
+    }
+}
@@ -1,4 +1,3 @@
-use probe_rs::{config::MemoryRegion, MemoryInterface, Session};
 use scroll::{Pread, LE};
 use std::borrow::Cow;
 use std::collections::BTreeMap;
@@ -6,11 +5,12 @@ use std::ops::Range;
 use std::sync::{Arc, Mutex};
 
 use crate::channel::*;
-use crate::{Channels, Error};
+use crate::{Channels, Error, Memory, Region, RegionKind};
 
 /// The RTT interface.
 ///
-/// Use [`Rtt::attach`] to attach to a probe-rs `Core` and detect channels.
+/// Use [`Rtt::attach`] to attach to a probe-rs `Session`, or any other [`Memory`], and detect
+/// channels.
 #[derive(Debug)]
 pub struct Rtt {
     ptr: u32,
@@ -32,20 +32,20 @@ pub struct Rtt {
 // }
 
 impl Rtt {
-    const RTT_ID: [u8; 16] = *b"SEGGER RTT\0\0\0\0\0\0";
+    pub(crate) const RTT_ID: [u8; 16] = *b"SEGGER RTT\0\0\0\0\0\0";
 
     // Minimum size of the ControlBlock struct in target memory in bytes with empty arrays
     const MIN_SIZE: usize = Self::O_CHANNEL_ARRAYS;
 
     // Offsets of fields in target memory in bytes
     const O_ID: usize = 0;
-    const O_MAX_UP_CHANNELS: usize = 16;
-    const O_MAX_DOWN_CHANNELS: usize = 20;
-    const O_CHANNEL_ARRAYS: usize = 24;
+    pub(crate) const O_MAX_UP_CHANNELS: usize = 16;
+    pub(crate) const O_MAX_DOWN_CHANNELS: usize = 20;
+    pub(crate) const O_CHANNEL_ARRAYS: usize = 24;
 
     fn from(
-        session: Arc<Mutex<Session>>,
-        memory_map: &[MemoryRegion],
+        memory: Arc<Mutex<dyn Memory>>,
+        regions: &[Region],
         // Pointer from which to scan
         ptr: u32,
         // Memory contents read in advance, starting from ptr
@@ -56,9 +56,7 @@ impl Rtt {
             None => {
                 // If memory wasn't passed in, read the minimum header size
                 let mut mem = vec![0u8; Self::MIN_SIZE];
-                let mut lock = session.lock().unwrap();
-                let mut core = lock.core(0)?;
-                core.read_8(ptr, &mut mem)?;
+                memory.lock().unwrap().read(ptr, &mut mem)?;
                 Cow::Owned(mem)
             }
         };
@@ -92,9 +90,7 @@ impl Rtt {
         if let Cow::Owned(mem) = &mut mem {
             // If memory wasn't passed in, read the rest of the control block
             mem.resize(cb_len, 0);
-            let mut lock = session.lock().unwrap();
-            let mut core = lock.core(0)?;
-            core.read_8(
+            memory.lock().unwrap().read(
                 ptr + Self::MIN_SIZE as u32,
                 &mut mem[Self::MIN_SIZE..cb_len],
             )?;
@@ -113,7 +109,7 @@ impl Rtt {
             let offset = Self::O_CHANNEL_ARRAYS + i * Channel::SIZE;
 
             if let Some(chan) =
-                Channel::from(&session, i, memory_map, ptr + offset as u32, &mem[offset..])?
+                Channel::from(&memory, i, regions, ptr + offset as u32, &mem[offset..])?
             {
                 up_channels.insert(i, UpChannel(chan));
             } else {
@@ -126,7 +122,7 @@ impl Rtt {
                 Self::O_CHANNEL_ARRAYS + (max_up_channels * Channel::SIZE) + i * Channel::SIZE;
 
             if let Some(chan) =
-                Channel::from(&session, i, memory_map, ptr + offset as u32, &mem[offset..])?
+                Channel::from(&memory, i, regions, ptr + offset as u32, &mem[offset..])?
             {
                 down_channels.insert(i, DownChannel(chan));
             } else {
@@ -144,36 +140,37 @@ impl Rtt {
     /// Attempts to detect an RTT control block anywhere in the target RAM and returns an instance
     /// if a valid control block was found.
     ///
-    /// `core` can be e.g. an owned `Core` or a shared `Rc<Core>`. The session is only borrowed
-    /// temporarily during detection.
-    pub fn attach(session: Arc<Mutex<Session>>) -> Result<Rtt, Error> {
-        Self::attach_region(session, &Default::default())
+    /// `memory` is usually a probe-rs `Session`. The lock is only held temporarily during each
+    /// memory access, so the session can still be used for other things.
+    pub fn attach<M: Memory + 'static>(memory: Arc<Mutex<M>>) -> Result<Rtt, Error> {
+        Self::attach_region(memory, &Default::default())
     }
 
     /// Attempts to detect an RTT control block in the specified RAM region(s) and returns an
     /// instance if a valid control block was found.
     ///
-    /// `core` can be e.g. an owned `Core` or a shared `Rc<Core>`. The session is only borrowed
-    /// temporarily during detection.
-    pub fn attach_region(session: Arc<Mutex<Session>>, region: &ScanRegion) -> Result<Rtt, Error> {
-        let memory_map: &[MemoryRegion] = &session.lock().unwrap().memory_map().to_vec();
+    /// `memory` is usually a probe-rs `Session`. The lock is only held temporarily during each
+    /// memory access, so the session can still be used for other things.
+    pub fn attach_region<M: Memory + 'static>(
+        memory: Arc<Mutex<M>>,
+        region: &ScanRegion,
+    ) -> Result<Rtt, Error> {
+        let memory: Arc<Mutex<dyn Memory>> = memory;
+        let regions: &[Region] = &memory.lock().unwrap().regions();
 
         let ranges: Vec<Range<u32>> = match region {
             ScanRegion::Exact(addr) => {
                 log::debug!("Scanning at exact address: 0x{:X}", addr);
 
-                return Rtt::from(session, memory_map, *addr, None)?
-                    .ok_or(Error::ControlBlockNotFound);
+                return Rtt::from(memory, regions, *addr, None)?.ok_or(Error::ControlBlockNotFound);
             }
             ScanRegion::Ram => {
                 log::debug!("Scanning RAM");
 
-                memory_map
+                regions
                     .iter()
-                    .filter_map(|r| match r {
-                        MemoryRegion::Ram(r) => Some(r.range.clone()),
-                        _ => None,
-                    })
+                    .filter(|r| r.kind == RegionKind::Ram)
+                    .map(|r| r.range.clone())
                     .collect()
             }
             ScanRegion::Range(region) => {
@@ -192,16 +189,12 @@ impl Rtt {
             }
 
             mem.resize(range.len(), 0);
-            {
-                let mut lock = session.lock().unwrap();
-                let mut core = lock.core(0)?;
-                core.read_8(range.start, mem.as_mut())?;
-            }
+            memory.lock().unwrap().read(range.start, mem.as_mut())?;
 
             for offset in 0..(mem.len() - Self::MIN_SIZE) {
                 if let Some(rtt) = Rtt::from(
-                    session.clone(),
-                    memory_map,
+                    memory.clone(),
+                    regions,
                     range.start + offset as u32,
                     Some(&mem[offset..]),
                 )? {
@@ -246,8 +239,9 @@ impl Rtt {
 /// Used to specify which memory regions to scan for the RTT control block.
 #[derive(Clone, Debug)]
 pub enum ScanRegion {
-    /// Scans all RAM regions known to probe-rs. This is the default and should always work, however
-    /// if your device has a lot of RAM, scanning all of it is slow.
+    /// Scans all RAM regions known to the [`Memory`] implementation, i.e. probe-rs. This is the
+    /// default and should always work, however if your device has a lot of RAM, scanning all of it
+    /// is slow.
     Ram,
 
     /// Limit scanning to these memory addresses in target memory. It is up to the user to ensure
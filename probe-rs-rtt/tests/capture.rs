@@ -0,0 +1,172 @@
+//! Tests for recording sessions and replaying the capture files.
+
+mod common;
+
+use common::*;
+use probe_rs_rtt::capture::{
+    CaptureReader, Direction, Recorder, Replay, MAX_IMAGE_SIZE, MAX_RECORD_LEN,
+};
+use probe_rs_rtt::{Error, Rtt};
+use std::sync::{Arc, Mutex};
+
+/// Records a session where the target prints `UP_DATA` and more, and the host sends a command.
+fn record() -> Vec<u8> {
+    let ram = SharedRam::new();
+    let mut rtt = Rtt::attach(Arc::new(Mutex::new(ram.clone()))).unwrap();
+    let mut recorder = Recorder::new(Vec::new(), &mut rtt).unwrap();
+
+    let up = rtt.up_channels().take(0).unwrap();
+    let down = rtt.down_channels().take(0).unwrap();
+    let mut buf = [0u8; 64];
+
+    let count = up.read(&mut buf).unwrap();
+    recorder.record_up(&up, &buf[..count]).unwrap();
+
+    let count = down.write(b"go\n").unwrap();
+    recorder.record_down(&down, &b"go\n"[..count]).unwrap();
+
+    ram.target_write(b"done\n");
+    let count = up.read(&mut buf).unwrap();
+    recorder.record_up(&up, &buf[..count]).unwrap();
+
+    // Nothing is recorded for empty reads
+    recorder.record_up(&up, &[]).unwrap();
+
+    recorder.into_inner()
+}
+
+#[test]
+fn reads_recorded_chunks() {
+    let capture = record();
+    let reader = CaptureReader::new(&capture[..]).unwrap();
+
+    let header = reader.header();
+    assert_eq!(header.control_block, RAM_BASE + CONTROL_BLOCK as u32);
+
+    let up = header.channel(Direction::Up, 0).unwrap();
+    assert_eq!(up.name.as_deref(), Some("Terminal"));
+    assert_eq!(up.buffer_size, 64);
+    assert!(header.channel(Direction::Down, 0).is_some());
+
+    let chunks: Vec<_> = reader
+        .map(|chunk| {
+            let chunk = chunk.unwrap();
+            (chunk.direction, chunk.channel, chunk.data)
+        })
+        .collect();
+
+    assert_eq!(
+        chunks,
+        vec![
+            (Direction::Up, 0, UP_DATA.to_vec()),
+            (Direction::Down, 0, b"go\n".to_vec()),
+            (Direction::Up, 0, b"done\n".to_vec()),
+        ]
+    );
+}
+
+#[test]
+fn replays_up_channel_data() {
+    let capture = record();
+    let mut rtt = Replay::from_reader(&capture[..])
+        .unwrap()
+        .into_rtt()
+        .unwrap();
+
+    assert_eq!(rtt.ptr(), RAM_BASE + CONTROL_BLOCK as u32);
+
+    let up = rtt.up_channels().take(0).unwrap();
+    let down = rtt.down_channels().take(0).unwrap();
+    assert_eq!(up.name(), Some("Terminal"));
+
+    // Writes to the down channel are accepted and discarded
+    assert_eq!(down.write(b"ignored").unwrap(), 7);
+
+    let mut received = Vec::new();
+    let mut buf = [0u8; 16];
+    loop {
+        let count = up.read(&mut buf).unwrap();
+        if count == 0 {
+            break;
+        }
+
+        received.extend_from_slice(&buf[..count]);
+    }
+
+    let mut expected = UP_DATA.to_vec();
+    expected.extend_from_slice(b"done\n");
+    assert_eq!(received, expected);
+}
+
+#[test]
+fn ignores_truncated_record() {
+    let mut capture = record();
+    capture.truncate(capture.len() - 2);
+
+    let chunks = CaptureReader::new(&capture[..])
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+    assert_eq!(chunks.len(), 2);
+}
+
+#[test]
+fn rejects_oversized_record() {
+    let ram = SharedRam::new();
+    let mut rtt = Rtt::attach(Arc::new(Mutex::new(ram))).unwrap();
+    let mut capture = Recorder::new(Vec::new(), &mut rtt).unwrap().into_inner();
+
+    // A record claiming more data than any channel buffer can hold
+    capture.extend_from_slice(&0u64.to_le_bytes());
+    capture.push(Direction::Up as u8);
+    capture.extend_from_slice(&0u32.to_le_bytes());
+    capture.extend_from_slice(&(MAX_RECORD_LEN as u32 + 1).to_le_bytes());
+
+    let mut reader = CaptureReader::new(&capture[..]).unwrap();
+    assert!(matches!(reader.next_chunk(), Err(Error::Protocol(_))));
+}
+
+/// Returns a capture header with channels of the specified direction, number and buffer size.
+fn header(channels: &[(u8, u32, usize)]) -> Vec<u8> {
+    let mut buf = b"RTTCAP\0\0".to_vec();
+    buf.extend_from_slice(&1u32.to_le_bytes());
+    buf.extend_from_slice(&0u64.to_le_bytes());
+    buf.extend_from_slice(&RAM_BASE.to_le_bytes());
+    buf.extend_from_slice(&(channels.len() as u32).to_le_bytes());
+
+    for &(direction, number, size) in channels {
+        buf.push(direction);
+        buf.extend_from_slice(&number.to_le_bytes());
+        buf.extend_from_slice(&(size as u32).to_le_bytes());
+        buf.extend_from_slice(&0xffffu16.to_le_bytes());
+    }
+
+    buf
+}
+
+#[test]
+fn rejects_malformed_header() {
+    let reject = |channels: &[(u8, u32, usize)]| {
+        matches!(
+            Replay::from_reader(&header(channels)[..]),
+            Err(Error::Protocol(_))
+        )
+    };
+
+    assert!(Replay::from_reader(&header(&[(0, 0, 64), (1, 0, 64), (0, 254, 64)])[..]).is_ok());
+
+    assert!(reject(&[(0, 0x7fff_ffff, 64)]));
+    assert!(reject(&[(1, 255, 64)]));
+    assert!(reject(&[(0, 1, 64), (1, 1, 64), (0, 1, 64)]));
+    assert!(reject(&[(2, 0, 64)]));
+
+    let size = MAX_IMAGE_SIZE / 4;
+    assert!(reject(&[
+        (0, 0, size),
+        (0, 1, size),
+        (0, 2, size),
+        (0, 3, size),
+        (0, 4, 1)
+    ]));
+}
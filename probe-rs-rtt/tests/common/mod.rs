@@ -0,0 +1,143 @@
+//! Shared helpers for tests that simulate a target.
+
+#![allow(dead_code)]
+
+use probe_rs_rtt::{Error, Memory, Region, RegionKind};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+pub mod remote;
+
+/// Start address of the simulated RAM.
+pub const RAM_BASE: u32 = 0x2000_0000;
+
+/// Size of the simulated RAM.
+pub const RAM_SIZE: usize = 4096;
+
+/// Offset of the control block in RAM.
+pub const CONTROL_BLOCK: usize = 0x100;
+
+/// Offset of the up channel buffer in RAM.
+pub const UP_BUFFER: usize = 0x400;
+
+/// Offset of the down channel buffer in RAM.
+pub const DOWN_BUFFER: usize = 0x800;
+
+/// Data waiting in the up channel.
+pub const UP_DATA: &[u8] = b"Hello from target\n";
+
+/// Returns the address range of the simulated RAM.
+pub fn ram_range() -> Range<u32> {
+    RAM_BASE..RAM_BASE + RAM_SIZE as u32
+}
+
+/// Returns a RAM image containing a control block with one up and one down channel, both named
+/// "Terminal". The up channel contains `UP_DATA`.
+pub fn target_ram() -> Vec<u8> {
+    let mut ram = vec![0u8; RAM_SIZE];
+
+    let put = |ram: &mut Vec<u8>, offset: usize, value: u32| {
+        ram[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    };
+
+    ram[CONTROL_BLOCK..CONTROL_BLOCK + 16].copy_from_slice(b"SEGGER RTT\0\0\0\0\0\0");
+    put(&mut ram, CONTROL_BLOCK + 16, 1);
+    put(&mut ram, CONTROL_BLOCK + 20, 1);
+
+    let name = 0x200;
+    ram[name..name + 9].copy_from_slice(b"Terminal\0");
+
+    let up = CONTROL_BLOCK + 24;
+    put(&mut ram, up, RAM_BASE + name as u32);
+    put(&mut ram, up + 4, RAM_BASE + UP_BUFFER as u32);
+    put(&mut ram, up + 8, 64);
+    put(&mut ram, up + 12, UP_DATA.len() as u32);
+    ram[UP_BUFFER..UP_BUFFER + UP_DATA.len()].copy_from_slice(UP_DATA);
+
+    let down = up + 24;
+    put(&mut ram, down, RAM_BASE + name as u32);
+    put(&mut ram, down + 4, RAM_BASE + DOWN_BUFFER as u32);
+    put(&mut ram, down + 8, 64);
+
+    ram
+}
+
+/// Simulated target memory that can be inspected while it is in use.
+#[derive(Clone, Debug)]
+pub struct SharedRam(pub Arc<Mutex<Vec<u8>>>);
+
+impl SharedRam {
+    pub fn new() -> SharedRam {
+        SharedRam(Arc::new(Mutex::new(target_ram())))
+    }
+
+    /// Appends data to the up channel like the target would.
+    pub fn target_write(&self, data: &[u8]) {
+        let mut ram = self.0.lock().unwrap();
+
+        let pos = CONTROL_BLOCK + 24 + 12;
+        let write = u32::from_le_bytes([ram[pos], ram[pos + 1], ram[pos + 2], ram[pos + 3]]);
+
+        let mut write = write as usize;
+        for &b in data {
+            ram[UP_BUFFER + write] = b;
+            write = (write + 1) % 64;
+        }
+
+        ram[pos..pos + 4].copy_from_slice(&(write as u32).to_le_bytes());
+    }
+
+    /// Reads everything written to the down channel like the target would.
+    pub fn target_read(&self) -> Vec<u8> {
+        let mut ram = self.0.lock().unwrap();
+
+        let pos = CONTROL_BLOCK + 48 + 12;
+        let word = |ram: &[u8], pos: usize| {
+            u32::from_le_bytes([ram[pos], ram[pos + 1], ram[pos + 2], ram[pos + 3]]) as usize
+        };
+        let write = word(&ram, pos);
+        let mut read = word(&ram, pos + 4);
+
+        let mut data = Vec::new();
+        while read != write {
+            data.push(ram[DOWN_BUFFER + read]);
+            read = (read + 1) % 64;
+        }
+
+        ram[pos + 4..pos + 8].copy_from_slice(&(read as u32).to_le_bytes());
+
+        data
+    }
+
+    fn offset(&self, address: u32, len: usize) -> Result<usize, Error> {
+        let start = address.wrapping_sub(RAM_BASE) as usize;
+        if start + len <= RAM_SIZE {
+            Ok(start)
+        } else {
+            Err(Error::InvalidAddress(address))
+        }
+    }
+}
+
+impl Memory for SharedRam {
+    fn read(&mut self, address: u32, data: &mut [u8]) -> Result<(), Error> {
+        let start = self.offset(address, data.len())?;
+        data.copy_from_slice(&self.0.lock().unwrap()[start..start + data.len()]);
+
+        Ok(())
+    }
+
+    fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        let start = self.offset(address, data.len())?;
+        self.0.lock().unwrap()[start..start + data.len()].copy_from_slice(data);
+
+        Ok(())
+    }
+
+    fn regions(&self) -> Vec<Region> {
+        vec![Region {
+            kind: RegionKind::Ram,
+            range: ram_range(),
+        }]
+    }
+}
@@ -0,0 +1,44 @@
+//! Scenarios shared by the tests of the remote memory backends.
+//!
+//! Each scenario takes a function that starts a server for the backend serving the specified RAM,
+//! connects to it and attaches to RTT.
+
+use super::*;
+use probe_rs_rtt::Rtt;
+
+/// Simulated target RAM served by a test server.
+pub type Ram = Arc<Mutex<Vec<u8>>>;
+
+pub fn finds_control_block(attach: impl Fn(&Ram) -> Rtt) {
+    let ram = Arc::new(Mutex::new(target_ram()));
+    let mut rtt = attach(&ram);
+
+    assert_eq!(rtt.ptr(), RAM_BASE + CONTROL_BLOCK as u32);
+
+    let up = rtt.up_channels().take(0).unwrap();
+    assert_eq!(up.name(), Some("Terminal"));
+    assert_eq!(up.buffer_size(), 64);
+}
+
+pub fn reads_up_channel(attach: impl Fn(&Ram) -> Rtt) {
+    let ram = Arc::new(Mutex::new(target_ram()));
+    let mut rtt = attach(&ram);
+
+    let up = rtt.up_channels().take(0).unwrap();
+    let mut buf = [0u8; 64];
+    let count = up.read(&mut buf).unwrap();
+
+    assert_eq!(&buf[..count], UP_DATA);
+    assert_eq!(up.read(&mut buf).unwrap(), 0);
+}
+
+pub fn writes_down_channel(attach: impl Fn(&Ram) -> Rtt) {
+    let ram = Arc::new(Mutex::new(target_ram()));
+    let mut rtt = attach(&ram);
+
+    let down = rtt.down_channels().take(0).unwrap();
+    assert_eq!(down.write(b"ping\n").unwrap(), 5);
+
+    let ram = ram.lock().unwrap();
+    assert_eq!(&ram[DOWN_BUFFER..DOWN_BUFFER + 5], b"ping\n");
+}
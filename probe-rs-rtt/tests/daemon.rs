@@ -0,0 +1,126 @@
+//! Tests for sharing a session through the daemon.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::*;
+use probe_rs_rtt::capture::Direction;
+use probe_rs_rtt::daemon::{Client, Server};
+use probe_rs_rtt::{ChannelMode, Rtt};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("probe-rs-rtt-{}-{}.sock", name, std::process::id()))
+}
+
+/// Runs `client` in a separate thread while polling a server for `ram` until it finishes.
+fn with_server<T: Send + 'static>(
+    name: &str,
+    ram: &SharedRam,
+    client: impl FnOnce(PathBuf) -> T + Send + 'static,
+) -> T {
+    let path = socket_path(name);
+    let mut rtt = Rtt::attach(Arc::new(Mutex::new(ram.clone()))).unwrap();
+    let mut server = Server::bind(&path, &mut rtt).unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || tx.send(client(path)).unwrap());
+
+    loop {
+        server.poll().unwrap();
+
+        match rx.try_recv() {
+            Ok(result) => return result,
+            Err(mpsc::TryRecvError::Empty) => thread::sleep(Duration::from_millis(1)),
+            Err(mpsc::TryRecvError::Disconnected) => panic!("Client thread failed"),
+        }
+    }
+}
+
+#[test]
+fn lists_channels() {
+    let channels = with_server("list", &SharedRam::new(), |path| {
+        Client::connect(path).unwrap().channels().unwrap()
+    });
+
+    assert_eq!(channels.len(), 2);
+    assert_eq!(channels[0].direction, Direction::Up);
+    assert_eq!(channels[0].name.as_deref(), Some("Terminal"));
+    assert_eq!(channels[1].direction, Direction::Down);
+    assert_eq!(channels[1].buffer_size, 64);
+}
+
+#[test]
+fn restricts_socket_to_user() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = socket_path("permissions");
+    let mut rtt = Rtt::attach(Arc::new(Mutex::new(SharedRam::new()))).unwrap();
+    let server = Server::bind(&path, &mut rtt).unwrap();
+
+    let mode = std::fs::metadata(server.path())
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+#[test]
+fn broadcasts_to_subscribers() {
+    let ram = SharedRam::new();
+    let target = ram.clone();
+
+    let (first, second) = with_server("subscribe", &ram, move |path| {
+        let timeout = Some(Duration::from_secs(5));
+
+        let mut first = Client::connect(&path).unwrap();
+        first.subscribe(0).unwrap();
+        assert_eq!(first.read(timeout).unwrap().unwrap(), (0, UP_DATA.to_vec()));
+
+        let mut second = Client::connect(&path).unwrap();
+        second.subscribe(0).unwrap();
+
+        target.target_write(b"more\n");
+
+        (
+            first.read(timeout).unwrap().unwrap(),
+            second.read(timeout).unwrap().unwrap(),
+        )
+    });
+
+    assert_eq!(first, (0, b"more\n".to_vec()));
+    assert_eq!(second, (0, b"more\n".to_vec()));
+}
+
+#[test]
+fn writes_down_channel() {
+    let ram = SharedRam::new();
+
+    let count = with_server("write", &ram, |path| {
+        Client::connect(path).unwrap().write(0, b"ping\n").unwrap()
+    });
+
+    assert_eq!(count, 5);
+    assert_eq!(
+        &ram.0.lock().unwrap()[DOWN_BUFFER..DOWN_BUFFER + 5],
+        b"ping\n"
+    );
+}
+
+#[test]
+fn changes_modes() {
+    let mode = with_server("mode", &SharedRam::new(), |path| {
+        let mut client = Client::connect(path).unwrap();
+        client.set_mode(0, ChannelMode::BlockIfFull).unwrap();
+
+        assert!(client.mode(1).is_err());
+
+        client.mode(0).unwrap()
+    });
+
+    assert_eq!(mode, ChannelMode::BlockIfFull);
+}
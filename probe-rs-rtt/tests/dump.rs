@@ -0,0 +1,89 @@
+//! Tests for extracting up channel data from RAM dumps.
+
+mod common;
+
+use common::*;
+use probe_rs_rtt::dump::RamDump;
+use probe_rs_rtt::{Error, ScanRegion};
+
+/// Contents of a wrapped 16 byte ring buffer. The oldest byte is `a` at the write offset.
+const WRAPPED: &[u8; 16] = b"ghijklmnopabcdef";
+const WRITE: u32 = 10;
+const READ: u32 = 13;
+
+fn put(ram: &mut [u8], offset: usize, value: u32) {
+    ram[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Returns a RAM image with two up channels: channel 0 has a wrapped buffer and channel 1 has its
+/// buffer outside of the dump.
+fn dump_ram() -> Vec<u8> {
+    let mut ram = vec![0u8; RAM_SIZE];
+
+    ram[CONTROL_BLOCK..CONTROL_BLOCK + 16].copy_from_slice(b"SEGGER RTT\0\0\0\0\0\0");
+    put(&mut ram, CONTROL_BLOCK + 16, 2);
+    put(&mut ram, CONTROL_BLOCK + 20, 0);
+
+    let wrapped = CONTROL_BLOCK + 24;
+    put(&mut ram, wrapped + 4, RAM_BASE + UP_BUFFER as u32);
+    put(&mut ram, wrapped + 8, WRAPPED.len() as u32);
+    put(&mut ram, wrapped + 12, WRITE);
+    put(&mut ram, wrapped + 16, READ);
+    ram[UP_BUFFER..UP_BUFFER + WRAPPED.len()].copy_from_slice(WRAPPED);
+
+    let outside = wrapped + 24;
+    put(&mut ram, outside + 4, RAM_BASE + RAM_SIZE as u32 + 0x1000);
+    put(&mut ram, outside + 8, 64);
+
+    ram
+}
+
+#[test]
+fn extracts_wrapped_buffer() {
+    let extraction = RamDump::new(RAM_BASE, dump_ram())
+        .extract(&ScanRegion::Ram)
+        .unwrap();
+
+    assert_eq!(extraction.channels.len(), 1);
+
+    let chan = &extraction.channels[0];
+    assert_eq!(chan.number, 0);
+    assert_eq!(chan.name, None);
+    assert_eq!(chan.unread, b"defghijklmnop");
+    assert_eq!(chan.history, b"abcdefghijklmnop");
+}
+
+#[test]
+fn reports_channel_outside_dump() {
+    let extraction = RamDump::new(RAM_BASE, dump_ram())
+        .extract(&ScanRegion::Ram)
+        .unwrap();
+
+    assert_eq!(extraction.failed.len(), 1);
+
+    let (number, err) = &extraction.failed[0];
+    assert_eq!(*number, 1);
+    assert!(matches!(err, Error::InvalidAddress(_)));
+}
+
+#[test]
+fn extracts_unwrapped_buffer_and_names_from_nvm() {
+    let mut ram = target_ram();
+
+    // Move the name out of RAM into a separate flash segment
+    let flash_base = 0x0800_0000;
+    put(&mut ram, CONTROL_BLOCK + 24, flash_base);
+    ram[0x200..0x209].fill(0);
+
+    let extraction = RamDump::new(RAM_BASE, ram)
+        .with_nvm(flash_base, &b"Terminal\0"[..])
+        .extract(&ScanRegion::Ram)
+        .unwrap();
+
+    assert!(extraction.failed.is_empty());
+
+    let chan = &extraction.channels[0];
+    assert_eq!(chan.name.as_deref(), Some("Terminal"));
+    assert_eq!(chan.unread, UP_DATA);
+    assert_eq!(&chan.history[chan.history.len() - UP_DATA.len()..], UP_DATA);
+}
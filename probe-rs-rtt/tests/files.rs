@@ -0,0 +1,151 @@
+//! Tests for resolving paths requested by the target within the root directory.
+
+mod common;
+
+use common::*;
+use probe_rs_rtt::files::{FileServer, O_CREATE, O_READ, O_WRITE};
+use probe_rs_rtt::rpc::{Frame, FrameKind, Rpc};
+use probe_rs_rtt::Rtt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+const EPERM: i32 = 1;
+const ENOENT: i32 = 2;
+
+/// A directory containing the `root` served to the target and an `outside` directory next to it.
+struct Sandbox {
+    dir: PathBuf,
+}
+
+impl Sandbox {
+    fn new(name: &str) -> Sandbox {
+        let dir = std::env::temp_dir().join(format!(
+            "probe-rs-rtt-files-{}-{}",
+            name,
+            std::process::id()
+        ));
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("root")).unwrap();
+        fs::create_dir_all(dir.join("outside")).unwrap();
+
+        fs::write(dir.join("root").join("inside.txt"), b"inside").unwrap();
+        fs::write(dir.join("outside").join("secret.txt"), b"secret").unwrap();
+
+        Sandbox { dir }
+    }
+
+    fn root(&self) -> PathBuf {
+        self.dir.join("root")
+    }
+
+    fn outside(&self) -> PathBuf {
+        self.dir.join("outside")
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Sends an open request for `path` like the target would and returns the response status.
+fn open(sandbox: &Sandbox, path: &str, flags: u32) -> i32 {
+    let ram = SharedRam::new();
+    let mut rtt = Rtt::attach(Arc::new(Mutex::new(ram.clone()))).unwrap();
+
+    let up = rtt.up_channels().take(0).unwrap();
+    let down = rtt.down_channels().take(0).unwrap();
+
+    let mut buf = [0u8; 64];
+    while up.read(&mut buf).unwrap() > 0 {}
+
+    let mut server = FileServer::new(Rpc::new(up, down), sandbox.root()).unwrap();
+
+    let mut payload = flags.to_le_bytes().to_vec();
+    payload.extend_from_slice(path.as_bytes());
+
+    let request = Frame {
+        kind: FrameKind::Request,
+        method: 1,
+        id: 1,
+        payload,
+    };
+    ram.target_write(&request.encode());
+
+    assert_eq!(server.poll().unwrap(), 1);
+
+    let (response, _) = Frame::decode(&ram.target_read()).unwrap().unwrap();
+    assert_eq!((response.kind, response.id), (FrameKind::Response, 1));
+
+    i32::from_le_bytes([
+        response.payload[0],
+        response.payload[1],
+        response.payload[2],
+        response.payload[3],
+    ])
+}
+
+#[test]
+fn opens_file_in_root() {
+    let sandbox = Sandbox::new("inside");
+
+    assert_eq!(open(&sandbox, "inside.txt", O_READ), 0);
+    assert_eq!(open(&sandbox, "./inside.txt", O_READ), 0);
+    assert_eq!(open(&sandbox, "missing.txt", O_READ), -ENOENT);
+}
+
+#[test]
+fn rejects_parent_directory() {
+    let sandbox = Sandbox::new("parent");
+
+    assert_eq!(open(&sandbox, "../outside/secret.txt", O_READ), -EPERM);
+    assert_eq!(
+        open(&sandbox, "sub/../../outside/secret.txt", O_READ),
+        -EPERM
+    );
+}
+
+#[test]
+fn rejects_absolute_path() {
+    let sandbox = Sandbox::new("absolute");
+
+    // Kept short to fit in the channel buffer
+    assert_eq!(open(&sandbox, "/etc/passwd", O_READ), -EPERM);
+}
+
+#[cfg(unix)]
+#[test]
+fn rejects_symlink_to_outside_directory() {
+    let sandbox = Sandbox::new("dir-link");
+    std::os::unix::fs::symlink(sandbox.outside(), sandbox.root().join("link")).unwrap();
+
+    assert_eq!(open(&sandbox, "link/secret.txt", O_READ), -EPERM);
+    assert_eq!(open(&sandbox, "link/new.txt", O_WRITE | O_CREATE), -EPERM);
+    assert!(!sandbox.outside().join("new.txt").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn rejects_symlink_to_file() {
+    let sandbox = Sandbox::new("file-link");
+    let root = sandbox.root();
+    std::os::unix::fs::symlink(sandbox.outside().join("secret.txt"), root.join("link")).unwrap();
+    std::os::unix::fs::symlink(root.join("inside.txt"), root.join("inner")).unwrap();
+
+    assert_eq!(open(&sandbox, "link", O_READ), -EPERM);
+    assert_eq!(open(&sandbox, "inner", O_READ), -EPERM);
+}
+
+#[cfg(unix)]
+#[test]
+fn rejects_dangling_symlink() {
+    let sandbox = Sandbox::new("dangling");
+    let target = sandbox.outside().join("created.txt");
+    std::os::unix::fs::symlink(&target, sandbox.root().join("dangling")).unwrap();
+
+    assert_eq!(open(&sandbox, "dangling", O_WRITE | O_CREATE), -EPERM);
+    assert!(!target.exists());
+}
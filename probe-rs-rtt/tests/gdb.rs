@@ -0,0 +1,309 @@
+//! Tests for the GDB Remote Serial Protocol backend against a local stub server.
+
+mod common;
+
+use common::*;
+use probe_rs_rtt::{gdb::GdbMemory, ChannelMode, Region, RegionKind, Rtt, ScanRegion};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Packets received by the server, with interrupts recorded as `^C`.
+type Packets = Arc<Mutex<Vec<String>>>;
+
+/// Starts a minimal gdbserver serving `ram` and returns its address. Like real servers, it halts
+/// the target when the client connects and panics on memory accesses while the target is running,
+/// unless `non_stop` is set and the client switches to non-stop mode.
+fn start_server(ram: Arc<Mutex<Vec<u8>>>, packets: Packets, non_stop: bool) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        serve(stream, ram, packets, non_stop);
+    });
+
+    addr
+}
+
+fn serve(stream: TcpStream, ram: Arc<Mutex<Vec<u8>>>, packets: Packets, non_stop: bool) {
+    stream.set_nodelay(true).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+
+    let mut running = false;
+    let mut non_stop_mode = false;
+
+    let mut reply = |response: &str| {
+        let checksum = response.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(writer, "${}#{:02x}", response, checksum).unwrap();
+    };
+
+    loop {
+        let mut byte = [0u8];
+        if reader.read(&mut byte).unwrap() == 0 {
+            return;
+        }
+
+        match byte[0] {
+            0x03 => {
+                assert!(running, "Interrupted while halted");
+                running = false;
+                packets.lock().unwrap().push(String::from("^C"));
+                reply("S02");
+                continue;
+            }
+            b'$' => {}
+            // Acknowledgements
+            _ => continue,
+        }
+
+        let mut packet = Vec::new();
+        reader.read_until(b'#', &mut packet).unwrap();
+        packet.pop();
+        let mut checksum = [0u8; 2];
+        reader.read_exact(&mut checksum).unwrap();
+
+        // Logged before acknowledging so that packets without a response are seen by the client
+        let packet = String::from_utf8(packet).unwrap();
+        packets.lock().unwrap().push(packet.clone());
+        reader.get_mut().write_all(b"+").unwrap();
+
+        match packet.as_str() {
+            "QNonStop:1" if non_stop => {
+                non_stop_mode = true;
+                reply("OK");
+            }
+            "vCont;c" if non_stop_mode => {
+                running = true;
+                reply("OK");
+            }
+            "c" => {
+                assert!(!running, "Continued while running");
+                running = true;
+            }
+            _ => {
+                assert!(
+                    non_stop_mode || !running,
+                    "Memory accessed while running: {}",
+                    packet
+                );
+                reply(&handle(&packet, &mut ram.lock().unwrap()));
+            }
+        }
+    }
+}
+
+fn handle(packet: &str, ram: &mut [u8]) -> String {
+    let (command, args) = packet.split_at(1);
+    let (range, data) = match args.find(':') {
+        Some(i) => (&args[..i], &args[i + 1..]),
+        None => (args, ""),
+    };
+
+    let mut parts = range.split(',');
+    let (addr, len) = match (parts.next(), parts.next()) {
+        (Some(addr), Some(len)) => (
+            u32::from_str_radix(addr, 16).unwrap(),
+            usize::from_str_radix(len, 16).unwrap(),
+        ),
+        _ => return String::new(),
+    };
+
+    let start = addr.wrapping_sub(RAM_BASE) as usize;
+    if start + len > ram.len() {
+        return String::from("E01");
+    }
+
+    match command {
+        "m" => run_length_encode(
+            &ram[start..start + len]
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>(),
+        ),
+        "M" => {
+            for i in 0..len {
+                ram[start + i] = u8::from_str_radix(&data[i * 2..i * 2 + 2], 16).unwrap();
+            }
+
+            String::from("OK")
+        }
+        _ => String::new(),
+    }
+}
+
+/// Encodes runs of 21 identical characters as `x*1` to exercise run-length decoding.
+fn run_length_encode(data: &str) -> String {
+    let bytes = data.as_bytes();
+    let mut out = String::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i..].len() >= 21 && bytes[i..i + 21].iter().all(|&b| b == bytes[i]) {
+            out.push(bytes[i] as char);
+            out.push('*');
+            out.push((20 + 29) as u8 as char);
+            i += 21;
+        } else {
+            out.push(bytes[i] as char);
+            i += 1;
+        }
+    }
+
+    out
+}
+
+fn attach(ram: &remote::Ram) -> Rtt {
+    attach_with(ram, 100, Packets::default(), false)
+}
+
+fn attach_non_stop(ram: &remote::Ram) -> Rtt {
+    attach_with(ram, 100, Packets::default(), true)
+}
+
+fn attach_with(
+    ram: &Arc<Mutex<Vec<u8>>>,
+    packet_size: usize,
+    packets: Packets,
+    non_stop: bool,
+) -> Rtt {
+    let gdb = GdbMemory::connect(start_server(ram.clone(), packets, non_stop))
+        .unwrap()
+        .with_packet_size(packet_size)
+        .with_region(Region {
+            kind: RegionKind::Ram,
+            range: ram_range(),
+        });
+
+    assert_eq!(gdb.is_non_stop(), non_stop);
+
+    Rtt::attach_region(Arc::new(Mutex::new(gdb)), &ScanRegion::Range(ram_range())).unwrap()
+}
+
+#[test]
+fn finds_control_block() {
+    remote::finds_control_block(attach);
+}
+
+#[test]
+fn reads_up_channel() {
+    remote::reads_up_channel(attach);
+}
+
+#[test]
+fn writes_down_channel() {
+    remote::writes_down_channel(attach);
+}
+
+#[test]
+fn finds_control_block_in_non_stop_mode() {
+    remote::finds_control_block(attach_non_stop);
+}
+
+#[test]
+fn reads_up_channel_in_non_stop_mode() {
+    remote::reads_up_channel(attach_non_stop);
+}
+
+#[test]
+fn writes_down_channel_in_non_stop_mode() {
+    remote::writes_down_channel(attach_non_stop);
+}
+
+#[test]
+fn interrupts_target_around_accesses_in_all_stop_mode() {
+    let ram = Arc::new(Mutex::new(target_ram()));
+    let packets = Packets::default();
+    let mut rtt = attach_with(&ram, 100, packets.clone(), false);
+
+    let up = rtt.up_channels().take(0).unwrap();
+    packets.lock().unwrap().clear();
+
+    up.mode().unwrap();
+
+    let flags = RAM_BASE + CONTROL_BLOCK as u32 + 24 + 20;
+    assert_eq!(
+        *packets.lock().unwrap(),
+        ["^C", &format!("m{:x},4", flags), "c"]
+    );
+}
+
+#[test]
+fn resumes_target_in_non_stop_mode() {
+    let ram = Arc::new(Mutex::new(target_ram()));
+    let packets = Packets::default();
+    let mut rtt = attach_with(&ram, 100, packets.clone(), true);
+
+    rtt.up_channels().take(0).unwrap().mode().unwrap();
+
+    let packets = packets.lock().unwrap();
+    assert_eq!(packets[..2], ["QNonStop:1", "vCont;c"]);
+    assert!(!packets.iter().any(|p| p == "^C" || p == "c"));
+}
+
+#[test]
+fn gives_up_on_repeated_checksum_errors() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0u8; 64];
+
+        // Acknowledge each request and answer it with a corrupted packet
+        while stream.read(&mut buf).unwrap_or(0) > 0 {
+            if buf[0] == b'$' {
+                stream.write_all(b"+$OK#00").unwrap();
+            } else if buf[0] == b'-' {
+                stream.write_all(b"$OK#00").unwrap();
+            }
+        }
+    });
+
+    assert!(matches!(
+        GdbMemory::connect(addr),
+        Err(probe_rs_rtt::Error::Protocol(_))
+    ));
+}
+
+#[test]
+fn writes_words_in_one_packet() {
+    let ram = Arc::new(Mutex::new(target_ram()));
+    let packets = Packets::default();
+    let mut rtt = attach_with(&ram, 2, packets.clone(), false);
+
+    let up = rtt.up_channels().take(0).unwrap();
+    packets.lock().unwrap().clear();
+
+    up.set_mode(ChannelMode::BlockIfFull).unwrap();
+    assert_eq!(up.mode().unwrap(), ChannelMode::BlockIfFull);
+
+    let flags = RAM_BASE + CONTROL_BLOCK as u32 + 24 + 20;
+    let packets = packets.lock().unwrap();
+
+    // The flags are read in one packet even though the packet size is smaller than a word
+    assert!(packets.contains(&format!("m{:x},4", flags)));
+    assert!(packets.contains(&format!("M{:x},4:02000000", flags)));
+}
+
+#[test]
+fn reads_names_outside_regions() {
+    let ram = Arc::new(Mutex::new(target_ram()));
+
+    // Only the control block is known, like when the name is in flash
+    let control_block = RAM_BASE + CONTROL_BLOCK as u32;
+    let range = control_block..control_block + 0x80;
+
+    let gdb = GdbMemory::connect(start_server(ram, Packets::default(), false))
+        .unwrap()
+        .with_region(Region {
+            kind: RegionKind::Ram,
+            range: range.clone(),
+        });
+
+    let mut rtt = Rtt::attach_region(Arc::new(Mutex::new(gdb)), &ScanRegion::Range(range)).unwrap();
+
+    assert_eq!(rtt.up_channels().take(0).unwrap().name(), Some("Terminal"));
+}
@@ -0,0 +1,123 @@
+//! Tests for recognizing level prefixes and forwarding lines to the logger.
+
+mod common;
+
+use common::*;
+use log::kv::Key;
+use log::{Level, Log, Metadata, Record};
+use probe_rs_rtt::logger::{parse_level, LogSink};
+use probe_rs_rtt::Rtt;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn parses_each_level() {
+    assert_eq!(parse_level("ERROR failed"), Some((Level::Error, "failed")));
+    assert_eq!(parse_level("ERR failed"), Some((Level::Error, "failed")));
+    assert_eq!(parse_level("WARN low"), Some((Level::Warn, "low")));
+    assert_eq!(parse_level("WARNING low"), Some((Level::Warn, "low")));
+    assert_eq!(parse_level("INFO up"), Some((Level::Info, "up")));
+    assert_eq!(parse_level("DEBUG x=1"), Some((Level::Debug, "x=1")));
+    assert_eq!(parse_level("DBG x=1"), Some((Level::Debug, "x=1")));
+    assert_eq!(parse_level("TRACE enter"), Some((Level::Trace, "enter")));
+}
+
+#[test]
+fn parses_prefix_forms() {
+    assert_eq!(parse_level("WARN: low"), Some((Level::Warn, "low")));
+    assert_eq!(parse_level("WARN - low"), Some((Level::Warn, "low")));
+    assert_eq!(parse_level("WARN\tlow"), Some((Level::Warn, "low")));
+    assert_eq!(parse_level("[info] up"), Some((Level::Info, "up")));
+    assert_eq!(
+        parse_level("<Error>: failed"),
+        Some((Level::Error, "failed"))
+    );
+    assert_eq!(parse_level("  INFO up"), Some((Level::Info, "up")));
+    assert_eq!(parse_level("INFO"), Some((Level::Info, "")));
+}
+
+#[test]
+fn skips_timestamps() {
+    assert_eq!(parse_level("0.001234 DEBUG x"), Some((Level::Debug, "x")));
+    assert_eq!(
+        parse_level("12:34:56.789 [WARN] y"),
+        Some((Level::Warn, "y"))
+    );
+}
+
+#[test]
+fn ignores_unprefixed_lines() {
+    assert_eq!(parse_level(""), None);
+    assert_eq!(parse_level("Hello from target"), None);
+    assert_eq!(parse_level("info without brackets"), None);
+    assert_eq!(parse_level("INFORMATION follows"), None);
+    assert_eq!(parse_level("[unknown] level"), None);
+    assert_eq!(parse_level("1234"), None);
+}
+
+/// Collects the records emitted for target output, ignoring those of the library itself.
+struct Collector(Mutex<Vec<Emitted>>);
+
+/// Level, target, message and channel of a record.
+type Emitted = (Level, String, String, Option<u64>);
+
+impl Log for Collector {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target().starts_with("target::")
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let channel = record
+            .key_values()
+            .get(Key::from("channel"))
+            .and_then(|v| v.to_u64());
+
+        self.0.lock().unwrap().push((
+            record.level(),
+            record.target().to_string(),
+            record.args().to_string(),
+            channel,
+        ));
+    }
+
+    fn flush(&self) {}
+}
+
+static COLLECTOR: Collector = Collector(Mutex::new(Vec::new()));
+
+#[test]
+fn emits_records_with_channel() {
+    log::set_logger(&COLLECTOR).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let ram = SharedRam::new();
+    let mut rtt = Rtt::attach(Arc::new(Mutex::new(ram.clone()))).unwrap();
+    let mut sink =
+        LogSink::new(rtt.up_channels().take(0).unwrap()).with_default_level(Level::Debug);
+
+    ram.target_write(b"WARN: low\r\npartial");
+    assert_eq!(sink.poll().unwrap(), 2);
+    sink.flush();
+
+    let records = COLLECTOR.0.lock().unwrap();
+    let record = |level, text: &str| {
+        (
+            level,
+            "target::Terminal".to_string(),
+            text.to_string(),
+            Some(0),
+        )
+    };
+
+    assert_eq!(
+        *records,
+        vec![
+            record(Level::Debug, "Hello from target"),
+            record(Level::Warn, "low"),
+            record(Level::Debug, "partial"),
+        ]
+    );
+}
@@ -0,0 +1,150 @@
+//! Tests for the OpenOCD TCL RPC backend against a local fake server.
+
+mod common;
+
+use common::*;
+use probe_rs_rtt::{openocd::OpenOcdMemory, Error, Region, RegionKind, Rtt, ScanRegion};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Starts a fake OpenOCD TCL server serving `ram` and returns its address.
+fn start_server(ram: Arc<Mutex<Vec<u8>>>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        serve(stream, ram);
+    });
+
+    addr
+}
+
+fn serve(stream: TcpStream, ram: Arc<Mutex<Vec<u8>>>) {
+    stream.set_nodelay(true).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+
+    loop {
+        let mut command = Vec::new();
+        if reader.read_until(0x1a, &mut command).unwrap() == 0 {
+            return;
+        }
+        command.pop();
+
+        // Only the command wrapped in `catch` is interpreted
+        let command = String::from_utf8(command).unwrap();
+        let start = command.find("catch {").unwrap() + 7;
+        let end = command.rfind("} r]").unwrap();
+
+        let response = match eval(&command[start..end], &mut ram.lock().unwrap()) {
+            Ok(result) => format!("OK {}", result),
+            Err(message) => format!("ERR {}", message),
+        };
+
+        writer.write_all(response.trim_end().as_bytes()).unwrap();
+        writer.write_all(&[0x1a]).unwrap();
+    }
+}
+
+fn eval(command: &str, ram: &mut [u8]) -> Result<String, String> {
+    let args: Vec<&str> = command
+        .split(|c: char| c.is_whitespace() || c == '{' || c == '}')
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    let number = |s: &str| u32::from_str_radix(s.trim_start_matches("0x"), 16).unwrap();
+
+    match args.as_slice() {
+        ["read_memory", addr, width, count] => {
+            let bytes = width.parse::<usize>().unwrap() / 8;
+            let count: usize = count.parse().unwrap();
+            let start = location(ram, number(addr), bytes * count)?;
+
+            Ok(ram[start..start + bytes * count]
+                .chunks(bytes)
+                .map(|chunk| {
+                    let value = chunk
+                        .iter()
+                        .rev()
+                        .fold(0u32, |value, &b| value << 8 | b as u32);
+                    format!("0x{:x}", value)
+                })
+                .collect::<Vec<_>>()
+                .join(" "))
+        }
+        ["write_memory", addr, width, values @ ..] => {
+            let bytes = width.parse::<usize>().unwrap() / 8;
+            let start = location(ram, number(addr), bytes * values.len())?;
+
+            for (i, value) in values.iter().enumerate() {
+                let value = number(value).to_le_bytes();
+                ram[start + i * bytes..start + (i + 1) * bytes].copy_from_slice(&value[..bytes]);
+            }
+
+            Ok(String::new())
+        }
+        _ => Err(format!("invalid command name \"{}\"", args[0])),
+    }
+}
+
+fn location(ram: &[u8], address: u32, len: usize) -> Result<usize, String> {
+    let start = address.wrapping_sub(RAM_BASE) as usize;
+    if start + len <= ram.len() {
+        Ok(start)
+    } else {
+        Err(format!("Failed to read memory at 0x{:08x}", address))
+    }
+}
+
+fn connect(ram: &Arc<Mutex<Vec<u8>>>) -> OpenOcdMemory {
+    OpenOcdMemory::connect(start_server(ram.clone()))
+        .unwrap()
+        .with_chunk_size(100)
+        .with_region(Region {
+            kind: RegionKind::Ram,
+            range: ram_range(),
+        })
+}
+
+fn attach(ram: &remote::Ram) -> Rtt {
+    Rtt::attach_region(
+        Arc::new(Mutex::new(connect(ram))),
+        &ScanRegion::Range(ram_range()),
+    )
+    .unwrap()
+}
+
+#[test]
+fn finds_control_block() {
+    remote::finds_control_block(attach);
+}
+
+#[test]
+fn reads_up_channel() {
+    remote::reads_up_channel(attach);
+}
+
+#[test]
+fn writes_down_channel() {
+    remote::writes_down_channel(attach);
+}
+
+#[test]
+fn reports_command_errors() {
+    let ram = Arc::new(Mutex::new(target_ram()));
+    let mut openocd = connect(&ram);
+
+    assert!(matches!(
+        openocd.command("reset halt"),
+        Err(Error::Protocol(_))
+    ));
+
+    let mut buf = [0u8; 4];
+    assert!(matches!(
+        probe_rs_rtt::Memory::read(&mut openocd, 0x1000_0000, &mut buf),
+        Err(Error::InvalidAddress(0x1000_0000))
+    ));
+}
@@ -0,0 +1,217 @@
+//! Tests for frame decoding and request/response dispatch.
+
+mod common;
+
+use common::*;
+use probe_rs_rtt::rpc::{Frame, FrameKind, Rpc};
+use probe_rs_rtt::{Error, Rtt};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn frame(kind: FrameKind, method: u16, id: u32, payload: &[u8]) -> Frame {
+    Frame {
+        kind,
+        method,
+        id,
+        payload: payload.to_vec(),
+    }
+}
+
+/// Returns an endpoint on the channels of `ram`, with the data initially in the up channel read.
+fn rpc(ram: &SharedRam) -> Rpc {
+    let mut rtt = Rtt::attach(Arc::new(Mutex::new(ram.clone()))).unwrap();
+
+    let up = rtt.up_channels().take(0).unwrap();
+    let down = rtt.down_channels().take(0).unwrap();
+
+    let mut buf = [0u8; 64];
+    while up.read(&mut buf).unwrap() > 0 {}
+
+    Rpc::new(up, down)
+}
+
+#[test]
+fn decode_round_trips_encode() {
+    let sent = frame(FrameKind::Request, 0x1234, 0xdead_beef, b"payload");
+    let mut buf = sent.encode();
+    buf.extend_from_slice(b"next");
+
+    let (decoded, len) = Frame::decode(&buf).unwrap().unwrap();
+
+    assert_eq!(decoded, sent);
+    assert_eq!(len, Frame::HEADER_SIZE + 7);
+}
+
+#[test]
+fn decode_waits_for_complete_frame() {
+    let buf = frame(FrameKind::Notification, 1, 0, b"payload").encode();
+
+    assert!(Frame::decode(&[]).unwrap().is_none());
+    assert!(Frame::decode(&buf[..Frame::HEADER_SIZE - 1])
+        .unwrap()
+        .is_none());
+    assert!(Frame::decode(&buf[..Frame::HEADER_SIZE]).unwrap().is_none());
+    assert!(Frame::decode(&buf[..buf.len() - 1]).unwrap().is_none());
+    assert!(Frame::decode(&buf).unwrap().is_some());
+}
+
+#[test]
+fn decode_rejects_invalid_kind() {
+    let mut buf = frame(FrameKind::Request, 1, 1, b"").encode();
+    buf[0] = 4;
+
+    assert!(matches!(Frame::decode(&buf), Err(Error::Protocol(_))));
+}
+
+#[test]
+fn decode_rejects_reserved_byte() {
+    let mut buf = frame(FrameKind::Request, 1, 1, b"").encode();
+    buf[1] = 1;
+
+    assert!(matches!(Frame::decode(&buf), Err(Error::Protocol(_))));
+}
+
+#[test]
+fn decode_rejects_oversized_payload() {
+    let mut buf = frame(FrameKind::Response, 1, 1, b"").encode();
+
+    buf[8..12].copy_from_slice(&(Frame::MAX_PAYLOAD as u32).to_le_bytes());
+    assert!(Frame::decode(&buf).unwrap().is_none());
+
+    // Rejected from the header alone, before the payload arrives
+    buf[8..12].copy_from_slice(&(Frame::MAX_PAYLOAD as u32 + 1).to_le_bytes());
+    assert!(matches!(Frame::decode(&buf), Err(Error::Protocol(_))));
+}
+
+#[test]
+fn sends_requests() {
+    let ram = SharedRam::new();
+    let mut rpc = rpc(&ram);
+
+    assert_eq!(rpc.start(7, b"args").unwrap(), 1);
+    assert_eq!(rpc.start(8, b"").unwrap(), 2);
+
+    let mut expected = frame(FrameKind::Request, 7, 1, b"args").encode();
+    expected.extend_from_slice(&frame(FrameKind::Request, 8, 2, b"").encode());
+
+    assert_eq!(ram.target_read(), expected);
+}
+
+#[test]
+fn dispatches_interleaved_frames() {
+    let ram = SharedRam::new();
+    let mut rpc = rpc(&ram);
+
+    let first = rpc.start(1, b"").unwrap();
+    let second = rpc.start(2, b"").unwrap();
+    ram.target_read();
+
+    ram.target_write(&frame(FrameKind::Notification, 10, 0, b"n1").encode());
+    ram.target_write(&frame(FrameKind::Response, 2, second, b"r2").encode());
+    rpc.poll().unwrap();
+
+    ram.target_write(&frame(FrameKind::Request, 20, 99, b"q").encode());
+    ram.target_write(&frame(FrameKind::Response, 1, first, b"r1").encode());
+    ram.target_write(&frame(FrameKind::Notification, 11, 0, b"n2").encode());
+
+    assert_eq!(rpc.poll_response(first).unwrap(), Some(b"r1".to_vec()));
+    assert_eq!(rpc.poll_response(second).unwrap(), Some(b"r2".to_vec()));
+    assert_eq!(rpc.poll_response(second).unwrap(), None);
+
+    assert_eq!(rpc.next_notification().unwrap().payload, b"n1");
+    assert_eq!(rpc.next_notification().unwrap().payload, b"n2");
+    assert!(rpc.next_notification().is_none());
+
+    let request = rpc.next_request().unwrap();
+    assert_eq!((request.method, request.id), (20, 99));
+    rpc.respond(&request, b"a").unwrap();
+
+    assert_eq!(
+        ram.target_read(),
+        frame(FrameKind::Response, 20, 99, b"a").encode()
+    );
+}
+
+#[test]
+fn reassembles_split_frames() {
+    let ram = SharedRam::new();
+    let mut rpc = rpc(&ram);
+
+    let id = rpc.start(1, b"").unwrap();
+    let response = frame(FrameKind::Response, 1, id, b"split").encode();
+
+    ram.target_write(&response[..5]);
+    assert_eq!(rpc.poll_response(id).unwrap(), None);
+
+    ram.target_write(&response[5..]);
+    assert_eq!(rpc.poll_response(id).unwrap(), Some(b"split".to_vec()));
+}
+
+#[test]
+fn discards_unknown_and_cancelled_responses() {
+    let ram = SharedRam::new();
+    let mut rpc = rpc(&ram);
+
+    let id = rpc.start(1, b"").unwrap();
+    rpc.cancel(id);
+
+    ram.target_write(&frame(FrameKind::Response, 1, id, b"late").encode());
+    ram.target_write(&frame(FrameKind::Response, 1, 1234, b"unknown").encode());
+
+    assert_eq!(rpc.poll_response(id).unwrap(), None);
+    assert_eq!(rpc.poll_response(1234).unwrap(), None);
+}
+
+#[test]
+fn recovers_after_oversized_frame() {
+    let ram = SharedRam::new();
+    let mut rpc = rpc(&ram);
+
+    let mut header = frame(FrameKind::Response, 1, 1, b"").encode();
+    header[8..12].copy_from_slice(&(Frame::MAX_PAYLOAD as u32 + 1).to_le_bytes());
+    ram.target_write(&header);
+
+    assert!(matches!(rpc.poll(), Err(Error::Protocol(_))));
+
+    // Everything received so far was discarded, so the next frame decodes again
+    ram.target_write(&frame(FrameKind::Notification, 1, 0, b"ok").encode());
+    rpc.poll().unwrap();
+
+    assert_eq!(rpc.next_notification().unwrap().payload, b"ok");
+}
+
+#[test]
+fn call_times_out() {
+    let ram = SharedRam::new();
+    let mut rpc = rpc(&ram);
+    rpc.set_timeout(Duration::from_millis(10));
+
+    assert!(matches!(rpc.call(1, b""), Err(Error::Timeout)));
+
+    // The response to the abandoned call is discarded
+    ram.target_write(&frame(FrameKind::Response, 1, 1, b"late").encode());
+    assert_eq!(rpc.poll_response(1).unwrap(), None);
+}
+
+#[test]
+fn call_timeout_drops_unsent_request() {
+    let ram = SharedRam::new();
+    let mut rpc = rpc(&ram);
+    rpc.set_timeout(Duration::from_millis(10));
+
+    // Fills the down buffer, which holds one byte less than its size
+    let fill = [0u8; 64 - 1 - Frame::HEADER_SIZE];
+    assert!(matches!(rpc.call(1, &fill), Err(Error::Timeout)));
+    assert!(matches!(rpc.call(2, b"dropped"), Err(Error::Timeout)));
+
+    assert_eq!(
+        ram.target_read(),
+        frame(FrameKind::Request, 1, 1, &fill).encode()
+    );
+
+    rpc.notify(3, b"").unwrap();
+    assert_eq!(
+        ram.target_read(),
+        frame(FrameKind::Notification, 3, 0, b"").encode()
+    );
+}
@@ -0,0 +1,244 @@
+//! Reading options from a configuration file with named profiles.
+//!
+//! The file is TOML. Its keys are the long names of the command line options, and apply unless
+//! the option is given on the command line. The tables under `profiles` hold additional options
+//! selected with `--profile`, which take precedence over the top-level ones:
+//!
+//! ```toml
+//! chip = "nRF52840_xxAA"
+//! up = [0, 1]
+//! exit-on = ["TESTS PASSED", "All done"]
+//!
+//! [profiles.devkit]
+//! probe = "1366:1015:000683123456"
+//! scan-region = "0x20000000..0x20010000"
+//! ```
+//!
+//! Flags are set with `true`. Arrays of numbers are joined with commas as for `--up`, and arrays
+//! of strings repeat the option as for `--exit-on`.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::value::Table;
+use toml::Value;
+
+/// Name of the configuration file looked for in the current directory and its parents.
+pub const FILE_NAME: &str = "rtthost.toml";
+
+/// Options that select the configuration itself, and so cannot be set in it.
+const RESERVED: &[&str] = &["config", "profile"];
+
+/// Looks for the configuration file in the current directory and its parents, so that it is
+/// found from anywhere in a project.
+pub fn find() -> Option<PathBuf> {
+    let dir = env::current_dir().ok()?;
+
+    dir.ancestors()
+        .map(|dir| dir.join(FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+/// An option set in the configuration file.
+pub struct ConfigOption {
+    /// Name of the option as used by clap, such as `scan_region`.
+    pub name: String,
+
+    /// Command line arguments that set the option, such as `--scan-region=0x20000000`.
+    pub args: Vec<String>,
+}
+
+/// Reads the options set in the file, including those of the profile if specified.
+pub fn load(path: &Path, profile: Option<&str>) -> Result<Vec<ConfigOption>, String> {
+    let contents = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let mut table: Table = toml::from_str(&contents).map_err(|err| err.to_string())?;
+
+    let profiles = match table.remove("profiles") {
+        Some(Value::Table(profiles)) => profiles,
+        Some(_) => return Err(String::from("'profiles' must be a table of profiles.")),
+        None => Table::new(),
+    };
+
+    if let Some(name) = profile {
+        match profiles.get(name) {
+            Some(Value::Table(options)) => table.extend(options.clone()),
+            Some(_) => return Err(format!("Profile '{}' must be a table.", name)),
+            None => {
+                let names: Vec<&str> = profiles.keys().map(String::as_str).collect();
+
+                return Err(if names.is_empty() {
+                    format!("No profile '{}', as no profiles are defined.", name)
+                } else {
+                    format!(
+                        "No profile '{}', expected one of: {}",
+                        name,
+                        names.join(", ")
+                    )
+                });
+            }
+        }
+    }
+
+    table
+        .into_iter()
+        .filter(|(_, value)| *value != Value::Boolean(false))
+        .map(|(key, value)| {
+            let name = key.replace('-', "_");
+
+            if RESERVED.contains(&name.as_str()) {
+                return Err(format!(
+                    "'{}' cannot be set in the configuration file.",
+                    key
+                ));
+            }
+
+            let flag = format!("--{}", key.replace('_', "-"));
+            let args = option_args(&flag, &value)
+                .ok_or_else(|| format!("Unsupported value for '{}': {}", key, value))?;
+
+            Ok(ConfigOption { name, args })
+        })
+        .collect()
+}
+
+/// Converts a value to the arguments for the option with the specified flag. Values are attached
+/// with `=` so that those starting with a hyphen are not taken for options.
+fn option_args(flag: &str, value: &Value) -> Option<Vec<String>> {
+    let arg = |value: String| format!("{}={}", flag, value);
+
+    let args = match value {
+        Value::Boolean(_) => vec![flag.to_string()],
+        Value::Array(values) if values.iter().all(Value::is_integer) => {
+            let numbers: Vec<String> = values.iter().map(Value::to_string).collect();
+            vec![arg(numbers.join(","))]
+        }
+        Value::Array(values) => values
+            .iter()
+            .map(|value| scalar(value).map(arg))
+            .collect::<Option<Vec<_>>>()?,
+        value => vec![arg(scalar(value)?)],
+    };
+
+    Some(args)
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Integer(n) => Some(n.to_string()),
+        Value::Float(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    /// Writes a configuration file and loads it, returning the arguments of each option by name.
+    fn load_str(
+        name: &str,
+        contents: &str,
+        profile: Option<&str>,
+    ) -> Result<Vec<(String, Vec<String>)>, String> {
+        let path = env::temp_dir().join(format!("rtthost-{}-{}.toml", name, process::id()));
+        fs::write(&path, contents).unwrap();
+
+        let result = load(&path, profile);
+        fs::remove_file(&path).unwrap();
+
+        let mut options: Vec<_> = result?
+            .into_iter()
+            .map(|option| (option.name, option.args))
+            .collect();
+        options.sort();
+
+        Ok(options)
+    }
+
+    fn option(name: &str, args: &[&str]) -> (String, Vec<String>) {
+        (
+            name.to_string(),
+            args.iter().map(|arg| arg.to_string()).collect(),
+        )
+    }
+
+    const CONFIG: &str = r#"
+        chip = "nRF52840_xxAA"
+        up = [0, 1]
+        exit-on = ["TESTS PASSED", "All done"]
+        reset = true
+        tui = false
+
+        [profiles.devkit]
+        chip = "STM32F429ZITx"
+        up = [2]
+        reset = false
+        speed = 4000
+    "#;
+
+    #[test]
+    fn converts_values_to_arguments() {
+        assert_eq!(
+            load_str("values", CONFIG, None).unwrap(),
+            [
+                option("chip", &["--chip=nRF52840_xxAA"]),
+                option("exit_on", &["--exit-on=TESTS PASSED", "--exit-on=All done"]),
+                option("reset", &["--reset"]),
+                option("up", &["--up=0,1"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn profile_overrides_top_level_keys() {
+        assert_eq!(
+            load_str("profile", CONFIG, Some("devkit")).unwrap(),
+            [
+                option("chip", &["--chip=STM32F429ZITx"]),
+                option("exit_on", &["--exit-on=TESTS PASSED", "--exit-on=All done"]),
+                option("speed", &["--speed=4000"]),
+                option("up", &["--up=2"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_profile() {
+        let err = load_str("unknown", CONFIG, Some("board")).unwrap_err();
+        assert!(err.contains("expected one of: devkit"), "{}", err);
+
+        let err = load_str("no-profiles", "chip = \"x\"", Some("board")).unwrap_err();
+        assert!(err.contains("no profiles are defined"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_reserved_options() {
+        assert!(load_str("reserved", "profile = \"devkit\"", None).is_err());
+        assert!(load_str(
+            "reserved-profile",
+            "[profiles.a]\nconfig = \"a.toml\"",
+            Some("a")
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn attaches_values_with_equals_sign() {
+        let args = option_args("--exit-on", &Value::String(String::from("-- FAIL --")));
+        assert_eq!(args.unwrap(), ["--exit-on=-- FAIL --"]);
+
+        let args = option_args("--scan-region", &Value::Float(1.5));
+        assert_eq!(args.unwrap(), ["--scan-region=1.5"]);
+    }
+
+    #[test]
+    fn rejects_unsupported_values() {
+        let table = Value::Table(Table::new());
+        assert!(option_args("--up", &table).is_none());
+
+        let nested = Value::Array(vec![Value::Array(Vec::new())]);
+        assert!(option_args("--up", &nested).is_none());
+    }
+}
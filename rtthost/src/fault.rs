@@ -0,0 +1,224 @@
+//! Detecting when the target core stops running, such as when the firmware crashes.
+
+use probe_rs::{Core, CoreStatus, HaltReason, MemoryInterface, Session};
+use std::fmt;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Exit code used by `--exit-on-halt`.
+pub const HALT_EXIT_CODE: i32 = 3;
+
+// Fault status and address registers of ARMv7-M and ARMv8-M Mainline
+const CFSR: u32 = 0xE000_ED28;
+const HFSR: u32 = 0xE000_ED2C;
+const MMFAR: u32 = 0xE000_ED34;
+const BFAR: u32 = 0xE000_ED38;
+
+const CFSR_BITS: &[(u32, &str)] = &[
+    (0, "IACCVIOL"),
+    (1, "DACCVIOL"),
+    (3, "MUNSTKERR"),
+    (4, "MSTKERR"),
+    (5, "MLSPERR"),
+    (7, "MMARVALID"),
+    (8, "IBUSERR"),
+    (9, "PRECISERR"),
+    (10, "IMPRECISERR"),
+    (11, "UNSTKERR"),
+    (12, "STKERR"),
+    (13, "LSPERR"),
+    (15, "BFARVALID"),
+    (16, "UNDEFINSTR"),
+    (17, "INVSTATE"),
+    (18, "INVPC"),
+    (19, "NOCP"),
+    (20, "STKOF"),
+    (24, "UNALIGNED"),
+    (25, "DIVBYZERO"),
+];
+
+const HFSR_BITS: &[(u32, &str)] = &[(1, "VECTTBL"), (30, "FORCED"), (31, "DEBUGEVT")];
+
+// Register number of xPSR for Core::read_core_reg
+const XPSR: u16 = 16;
+
+/// Why the core stopped running.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    Halted(HaltReason),
+    LockedUp,
+
+    /// A HardFault was escalated while the core kept running, e.g. in a handler that loops.
+    HardFault,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Halted(reason) => write!(f, "Core halted ({:?})", reason),
+            Stop::LockedUp => write!(f, "Core locked up"),
+            Stop::HardFault => write!(f, "Core entered the HardFault handler"),
+        }
+    }
+}
+
+/// Periodically checks the status of the core.
+pub struct CoreMonitor {
+    session: Arc<Mutex<Session>>,
+    interval: Duration,
+    last_check: Instant,
+    stopped: bool,
+}
+
+impl CoreMonitor {
+    /// Creates a monitor that checks the core at the specified interval. Faults that happened
+    /// before are not reported, and their status is cleared.
+    pub fn new(
+        session: Arc<Mutex<Session>>,
+        interval: Duration,
+    ) -> Result<CoreMonitor, probe_rs::Error> {
+        clear_fault_status(&mut session.lock().unwrap().core(0)?);
+
+        Ok(CoreMonitor {
+            session,
+            interval,
+            last_check: Instant::now(),
+            stopped: false,
+        })
+    }
+
+    /// Checks the core if the interval has passed. Returns why the core stopped, only once each
+    /// time it stops.
+    pub fn poll(&mut self) -> Result<Option<Stop>, probe_rs::Error> {
+        if self.last_check.elapsed() < self.interval {
+            return Ok(None);
+        }
+
+        self.last_check = Instant::now();
+
+        let mut session = self.session.lock().unwrap();
+        let mut core = session.core(0)?;
+
+        let stop = match core.status()? {
+            CoreStatus::Halted(reason) => Some(Stop::Halted(reason)),
+            CoreStatus::LockedUp => Some(Stop::LockedUp),
+            // Cores without HFSR, such as ARMv6-M, can still be checked for halts and lockups
+            _ if core.read_word_32(HFSR).unwrap_or(0) != 0 => Some(Stop::HardFault),
+            _ => None,
+        };
+
+        let report = if self.stopped { None } else { stop };
+        self.stopped = stop.is_some();
+
+        Ok(report)
+    }
+
+    /// Describes the state of the stopped core, including its registers and the fault status
+    /// registers. A core that is still running is halted while reading them and then resumed, so
+    /// that e.g. a HardFault handler that resets the device or logs the fault continues.
+    pub fn report(&mut self, stop: Stop) -> Result<String, probe_rs::Error> {
+        let mut session = self.session.lock().unwrap();
+        let mut core = session.core(0)?;
+
+        let running = !core.core_halted()?;
+        if running {
+            core.halt(Duration::from_millis(100))?;
+        }
+
+        let report = describe(&mut core, stop);
+
+        // Otherwise the sticky status bits would hide the next fault
+        clear_fault_status(&mut core);
+
+        if running {
+            core.run()?;
+        }
+
+        report
+    }
+}
+
+/// Describes the state of a halted core.
+fn describe(core: &mut Core, stop: Stop) -> Result<String, probe_rs::Error> {
+    let mut report = stop.to_string();
+
+    let xpsr = core.read_core_reg(XPSR)?;
+    match xpsr & 0x1ff {
+        0 => {}
+        3 if stop == Stop::HardFault => {}
+        3 => report.push_str(" in the HardFault handler"),
+        exception => write!(report, " in exception {}", exception).unwrap(),
+    }
+    report.push('\n');
+
+    let pc = core.read_core_reg(core.registers().program_counter())?;
+    let lr = core.read_core_reg(core.registers().return_address())?;
+    let sp = core.read_core_reg(core.registers().stack_pointer())?;
+
+    writeln!(
+        report,
+        "  PC   0x{:08x}  LR   0x{:08x}  SP   0x{:08x}  xPSR 0x{:08x}",
+        pc, lr, sp, xpsr
+    )
+    .unwrap();
+
+    for row in 0..4u16 {
+        report.push(' ');
+
+        for number in (row * 4..row * 4 + 4).filter(|&n| n <= 12) {
+            let value = core.read_core_reg(number)?;
+            write!(report, " {:<4} 0x{:08x}", format!("R{}", number), value).unwrap();
+        }
+
+        report.push('\n');
+    }
+
+    write_fault_register(&mut report, core, "CFSR", CFSR, CFSR_BITS);
+    write_fault_register(&mut report, core, "HFSR", HFSR, HFSR_BITS);
+    write_fault_register(&mut report, core, "MMFAR", MMFAR, &[]);
+    write_fault_register(&mut report, core, "BFAR", BFAR, &[]);
+
+    Ok(report)
+}
+
+/// Clears the bits set in the fault status registers, which are cleared by writing ones to them.
+/// Cores without these registers are left alone.
+fn clear_fault_status(core: &mut Core) {
+    for &address in &[CFSR, HFSR] {
+        if let Ok(value) = core.read_word_32(address) {
+            if value != 0 {
+                let _ = core.write_word_32(address, value);
+            }
+        }
+    }
+}
+
+/// Writes the value of a fault register along with the names of the bits set in it.
+fn write_fault_register(
+    report: &mut String,
+    core: &mut Core,
+    name: &str,
+    address: u32,
+    bits: &[(u32, &str)],
+) {
+    let value = match core.read_word_32(address) {
+        Ok(value) => value,
+        Err(_) => {
+            writeln!(report, "  {:<5} unavailable", name).unwrap();
+            return;
+        }
+    };
+
+    let set: Vec<&str> = bits
+        .iter()
+        .filter(|(bit, _)| value & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect();
+
+    if set.is_empty() {
+        writeln!(report, "  {:<5} 0x{:08x}", name, value).unwrap();
+    } else {
+        writeln!(report, "  {:<5} 0x{:08x} ({})", name, value, set.join(", ")).unwrap();
+    }
+}
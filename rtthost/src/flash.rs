@@ -0,0 +1,21 @@
+//! Flashing firmware and locating its control block.
+
+use goblin::elf::Elf;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Name of the control block symbol used by the SEGGER implementation and rtt-target.
+const RTT_SYMBOL: &str = "_SEGGER_RTT";
+
+/// Returns the address of the control block from the symbol table of an ELF file, if it has one.
+pub fn rtt_symbol(path: &Path) -> io::Result<Option<u32>> {
+    let data = fs::read(path)?;
+    let elf = Elf::parse(&data).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    Ok(elf
+        .syms
+        .iter()
+        .find(|sym| elf.strtab.get_at(sym.st_name) == Some(RTT_SYMBOL))
+        .map(|sym| sym.st_value as u32))
+}
@@ -0,0 +1,704 @@
+//! Reading keyboard input for the down channel.
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use std::io::{self, stdin, stdout, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Number of lines kept in the history of the line editor.
+const HISTORY_SIZE: usize = 1000;
+
+/// Ctrl-], which exits raw mode as Ctrl-C is passed through to the target.
+const RAW_QUIT: u8 = 0x1d;
+
+/// How keyboard input is read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputMode {
+    /// Lines as entered in the terminal.
+    Cooked,
+
+    /// Lines edited in rtthost, with history.
+    Line,
+
+    /// Each keypress as it is typed.
+    Raw,
+}
+
+impl std::str::FromStr for InputMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<InputMode, &'static str> {
+        match s {
+            "cooked" => Ok(InputMode::Cooked),
+            "line" => Ok(InputMode::Line),
+            "raw" => Ok(InputMode::Raw),
+            _ => Err("Invalid input mode, expected 'cooked', 'line' or 'raw'."),
+        }
+    }
+}
+
+/// What to end each line of input with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    Cr,
+    CrLf,
+}
+
+impl std::str::FromStr for LineEnding {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<LineEnding, &'static str> {
+        match s.to_ascii_lowercase().as_str() {
+            "lf" => Ok(LineEnding::Lf),
+            "cr" => Ok(LineEnding::Cr),
+            "crlf" => Ok(LineEnding::CrLf),
+            _ => Err("Invalid line ending, expected 'lf', 'cr' or 'crlf'."),
+        }
+    }
+}
+
+impl LineEnding {
+    pub fn as_bytes(self) -> &'static [u8] {
+        match self {
+            LineEnding::Lf => b"\n",
+            LineEnding::Cr => b"\r",
+            LineEnding::CrLf => b"\r\n",
+        }
+    }
+}
+
+/// Keyboard input, read in a background thread.
+pub struct Input {
+    receiver: Receiver<Vec<u8>>,
+    quit: Arc<AtomicBool>,
+    editor: Option<Arc<Mutex<Editor>>>,
+    _terminal: Option<RawTerminal>,
+}
+
+impl Input {
+    /// Starts reading input. Line and raw mode switch the terminal to raw mode until dropped.
+    pub fn start(mode: InputMode, ending: LineEnding) -> io::Result<Input> {
+        if mode != InputMode::Cooked
+            && (!atty::is(atty::Stream::Stdin) || !atty::is(atty::Stream::Stdout))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Line and raw input require a terminal.",
+            ));
+        }
+
+        let terminal = match mode {
+            InputMode::Cooked => None,
+            _ => Some(RawTerminal::enable()?),
+        };
+
+        let (tx, rx) = channel();
+        let quit = Arc::new(AtomicBool::new(false));
+        let thread_quit = quit.clone();
+
+        let editor = match mode {
+            InputMode::Cooked => {
+                thread::spawn(move || read_cooked(tx, ending));
+                None
+            }
+            InputMode::Line => {
+                let editor = Arc::new(Mutex::new(Editor::new()));
+                let thread_editor = editor.clone();
+                thread::spawn(move || read_lines(tx, thread_quit, thread_editor, ending));
+
+                editor.lock().unwrap().show(&mut stdout())?;
+                stdout().flush()?;
+
+                Some(editor)
+            }
+            InputMode::Raw => {
+                eprintln!("Raw input, press Ctrl-] to exit.");
+                thread::spawn(move || read_raw(tx, thread_quit));
+                None
+            }
+        };
+
+        Ok(Input {
+            receiver: rx,
+            quit,
+            editor,
+            _terminal: terminal,
+        })
+    }
+
+    /// Returns the data entered since the last call, if any.
+    pub fn try_recv(&self) -> Option<Vec<u8>> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Returns true once the user has asked to exit, which is only possible in line and raw mode.
+    pub fn quit(&self) -> bool {
+        self.quit.load(Ordering::SeqCst)
+    }
+
+    /// Returns a writer for output to the terminal that keeps the line being edited after it.
+    pub fn console(&self) -> Console {
+        Console {
+            editor: self.editor.clone(),
+        }
+    }
+}
+
+impl Drop for Input {
+    fn drop(&mut self) {
+        // Leave any unfinished line on the screen along with the messages printed after it
+        if let Some(editor) = &self.editor {
+            editor.lock().unwrap().closed = true;
+        }
+    }
+}
+
+/// Writes to stdout. In line mode, the line being edited is cleared before and drawn again after
+/// each write, so that output does not mix with it.
+#[derive(Default)]
+pub struct Console {
+    editor: Option<Arc<Mutex<Editor>>>,
+}
+
+impl Write for Console {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut out = stdout();
+
+        match &self.editor {
+            Some(editor) => {
+                let mut editor = editor.lock().unwrap();
+
+                editor.hide(&mut out)?;
+                out.write_all(buf)?;
+                editor.show(&mut out)?;
+            }
+            None => out.write_all(buf)?,
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        stdout().flush()
+    }
+}
+
+/// What the line editor should do after a keypress.
+#[derive(Debug, PartialEq)]
+enum Action {
+    None,
+    Send(String),
+    Quit,
+}
+
+/// A line editor with history, drawn after the output. The line is not preceded by a prompt of
+/// its own, so that it follows any prompt printed by the target.
+struct Editor {
+    line: Vec<char>,
+    cursor: usize,
+    history: Vec<String>,
+
+    /// The index of the history entry shown, and the line that was being edited before.
+    browsing: Option<(usize, Vec<char>)>,
+
+    /// The cursor position within the line as drawn, if it is.
+    shown: Option<usize>,
+
+    closed: bool,
+}
+
+impl Editor {
+    fn new() -> Editor {
+        Editor {
+            line: Vec::new(),
+            cursor: 0,
+            history: Vec::new(),
+            browsing: None,
+            shown: None,
+            closed: false,
+        }
+    }
+
+    /// Draws the line at the cursor.
+    fn show(&mut self, out: &mut impl Write) -> io::Result<()> {
+        if self.shown.is_some() || self.closed {
+            return Ok(());
+        }
+
+        let line: String = self.line.iter().collect();
+        write!(out, "{}", line)?;
+
+        if self.cursor < self.line.len() {
+            write!(out, "\x1b[{}D", self.line.len() - self.cursor)?;
+        }
+
+        self.shown = Some(self.cursor);
+
+        Ok(())
+    }
+
+    /// Clears the line, leaving the cursor where it started. The cursor is moved relative to its
+    /// position rather than restored, so that messages printed to stderr in the meantime are
+    /// not cleared along with it.
+    fn hide(&mut self, out: &mut impl Write) -> io::Result<()> {
+        match self.shown.take() {
+            Some(0) if self.line.is_empty() => {}
+            Some(0) => write!(out, "\x1b[J")?,
+            Some(cursor) => write!(out, "\x1b[{}D\x1b[J", cursor)?,
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    fn set_line(&mut self, line: Vec<char>) {
+        self.cursor = line.len();
+        self.line = line;
+    }
+
+    fn handle(&mut self, key: KeyEvent) -> Action {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        match key.code {
+            KeyCode::Char('c') if ctrl => {
+                if self.line.is_empty() {
+                    return Action::Quit;
+                }
+
+                self.set_line(Vec::new());
+            }
+            KeyCode::Char('d') if ctrl => {
+                if self.line.is_empty() {
+                    return Action::Quit;
+                }
+
+                if self.cursor < self.line.len() {
+                    self.line.remove(self.cursor);
+                }
+            }
+            KeyCode::Char('u') if ctrl => {
+                self.line.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            KeyCode::Char('a') if ctrl => self.cursor = 0,
+            KeyCode::Char('e') if ctrl => self.cursor = self.line.len(),
+            KeyCode::Char(c) if !ctrl => {
+                self.line.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.line.remove(self.cursor);
+            }
+            KeyCode::Delete if self.cursor < self.line.len() => {
+                self.line.remove(self.cursor);
+            }
+            KeyCode::Left if self.cursor > 0 => self.cursor -= 1,
+            KeyCode::Right if self.cursor < self.line.len() => self.cursor += 1,
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.line.len(),
+            KeyCode::Up => {
+                let index = match &self.browsing {
+                    Some((0, _)) => return Action::None,
+                    Some((index, _)) => index - 1,
+                    None if self.history.is_empty() => return Action::None,
+                    None => {
+                        self.browsing = Some((0, std::mem::take(&mut self.line)));
+                        self.history.len() - 1
+                    }
+                };
+
+                self.browsing.as_mut().unwrap().0 = index;
+                self.set_line(self.history[index].chars().collect());
+
+                return Action::None;
+            }
+            KeyCode::Down => {
+                if let Some((index, editing)) = self.browsing.take() {
+                    if index + 1 < self.history.len() {
+                        self.set_line(self.history[index + 1].chars().collect());
+                        self.browsing = Some((index + 1, editing));
+                    } else {
+                        self.set_line(editing);
+                    }
+                }
+
+                return Action::None;
+            }
+            KeyCode::Enter => {
+                let line: String = self.line.drain(..).collect();
+                self.cursor = 0;
+                self.browsing = None;
+
+                if !line.is_empty() && self.history.last() != Some(&line) {
+                    if self.history.len() == HISTORY_SIZE {
+                        self.history.remove(0);
+                    }
+
+                    self.history.push(line.clone());
+                }
+
+                return Action::Send(line);
+            }
+            _ => return Action::None,
+        }
+
+        // Editing a line from the history makes it the line being edited
+        self.browsing = None;
+
+        Action::None
+    }
+}
+
+/// Reads lines as entered in the terminal, replacing their line endings.
+fn read_cooked(tx: Sender<Vec<u8>>, ending: LineEnding) {
+    let mut buf = [0u8; 1024];
+
+    loop {
+        match stdin().read(&mut buf[..]) {
+            // Input has ended, e.g. when running from CI
+            Ok(0) => break,
+            Ok(count) => {
+                let mut data = Vec::with_capacity(count);
+
+                for &b in &buf[..count] {
+                    match b {
+                        b'\n' => data.extend_from_slice(ending.as_bytes()),
+                        b => data.push(b),
+                    }
+                }
+
+                // The receiver is gone once the program is exiting
+                if tx.send(data).is_err() {
+                    break;
+                }
+            }
+            Err(err) => {
+                eprintln!("Error reading from stdin, input disabled: {}", err);
+                break;
+            }
+        }
+    }
+}
+
+/// Reads keypresses into the line editor, and sends each line once entered.
+fn read_lines(
+    tx: Sender<Vec<u8>>,
+    quit: Arc<AtomicBool>,
+    editor: Arc<Mutex<Editor>>,
+    ending: LineEnding,
+) {
+    loop {
+        let key = match event::read() {
+            Ok(Event::Key(key)) => key,
+            Ok(_) => continue,
+            Err(err) => {
+                eprintln!("Error reading from the terminal, input disabled: {}", err);
+                break;
+            }
+        };
+
+        let mut out = stdout();
+        let mut editor = editor.lock().unwrap();
+
+        if editor.hide(&mut out).is_err() {
+            break;
+        }
+
+        let data = match editor.handle(key) {
+            Action::None => None,
+            Action::Send(line) => {
+                // Leave the line on the screen as if the terminal had echoed it
+                if writeln!(out, "{}", line).is_err() {
+                    break;
+                }
+
+                let mut data = line.into_bytes();
+                data.extend_from_slice(ending.as_bytes());
+                Some(data)
+            }
+            Action::Quit => {
+                editor.closed = true;
+                quit.store(true, Ordering::SeqCst);
+                break;
+            }
+        };
+
+        if editor.show(&mut out).and_then(|_| out.flush()).is_err() {
+            break;
+        }
+
+        if let Some(data) = data {
+            if tx.send(data).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Reads keypresses as they are typed and sends them unchanged, until Ctrl-] is pressed.
+fn read_raw(tx: Sender<Vec<u8>>, quit: Arc<AtomicBool>) {
+    let mut buf = [0u8; 1024];
+
+    loop {
+        let count = match stdin().read(&mut buf[..]) {
+            Ok(0) => break,
+            Ok(count) => count,
+            Err(err) => {
+                eprintln!("Error reading from stdin, input disabled: {}", err);
+                break;
+            }
+        };
+
+        let data = &buf[..count];
+
+        match data.iter().position(|&b| b == RAW_QUIT) {
+            Some(i) => {
+                let _ = tx.send(data[..i].to_vec());
+                quit.store(true, Ordering::SeqCst);
+                break;
+            }
+            None => {
+                if tx.send(data.to_vec()).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Puts the terminal into raw mode until dropped. Output processing is left on, so that line
+/// feeds still return the carriage and output looks the same as in cooked mode.
+#[cfg(unix)]
+struct RawTerminal {
+    original: nix::sys::termios::Termios,
+}
+
+#[cfg(unix)]
+impl RawTerminal {
+    fn enable() -> io::Result<RawTerminal> {
+        use crate::pty::to_io_error;
+        use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, OutputFlags, SetArg};
+
+        let original = tcgetattr(stdin_fd()).map_err(to_io_error)?;
+
+        let mut termios = original.clone();
+        cfmakeraw(&mut termios);
+        termios.output_flags |= OutputFlags::OPOST | OutputFlags::ONLCR;
+        tcsetattr(stdin_fd(), SetArg::TCSANOW, &termios).map_err(to_io_error)?;
+
+        Ok(RawTerminal { original })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        use nix::sys::termios::{tcsetattr, SetArg};
+
+        let _ = tcsetattr(stdin_fd(), SetArg::TCSANOW, &self.original);
+    }
+}
+
+#[cfg(unix)]
+fn stdin_fd() -> std::os::unix::io::RawFd {
+    use std::os::unix::io::AsRawFd;
+
+    stdin().as_raw_fd()
+}
+
+#[cfg(not(unix))]
+struct RawTerminal;
+
+#[cfg(not(unix))]
+impl RawTerminal {
+    fn enable() -> io::Result<RawTerminal> {
+        crossterm::terminal::enable_raw_mode()
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+        Ok(RawTerminal)
+    }
+}
+
+#[cfg(not(unix))]
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = crossterm::terminal::disable_raw_mode();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn ctrl(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    fn type_str(editor: &mut Editor, s: &str) {
+        for c in s.chars() {
+            assert_eq!(editor.handle(key(KeyCode::Char(c))), Action::None);
+        }
+    }
+
+    fn enter(editor: &mut Editor, s: &str) -> Action {
+        type_str(editor, s);
+        editor.handle(key(KeyCode::Enter))
+    }
+
+    fn line(editor: &Editor) -> String {
+        editor.line.iter().collect()
+    }
+
+    #[test]
+    fn sends_entered_lines() {
+        let mut editor = Editor::new();
+
+        assert_eq!(enter(&mut editor, "help"), Action::Send("help".into()));
+        assert_eq!(line(&editor), "");
+        assert_eq!(editor.cursor, 0);
+    }
+
+    #[test]
+    fn browses_history_and_restores_edited_line() {
+        let mut editor = Editor::new();
+        enter(&mut editor, "first");
+        enter(&mut editor, "second");
+        type_str(&mut editor, "thi");
+
+        editor.handle(key(KeyCode::Up));
+        assert_eq!(line(&editor), "second");
+        assert_eq!(editor.cursor, 6);
+
+        editor.handle(key(KeyCode::Up));
+        assert_eq!(line(&editor), "first");
+
+        // The oldest entry stays put
+        editor.handle(key(KeyCode::Up));
+        assert_eq!(line(&editor), "first");
+
+        editor.handle(key(KeyCode::Down));
+        assert_eq!(line(&editor), "second");
+
+        editor.handle(key(KeyCode::Down));
+        assert_eq!(line(&editor), "thi");
+        assert_eq!(editor.cursor, 3);
+
+        editor.handle(key(KeyCode::Down));
+        assert_eq!(line(&editor), "thi");
+    }
+
+    #[test]
+    fn edits_line_from_history() {
+        let mut editor = Editor::new();
+        enter(&mut editor, "reset");
+
+        editor.handle(key(KeyCode::Up));
+        type_str(&mut editor, " now");
+
+        // The edited entry replaces the line, so Down no longer returns to the original one
+        editor.handle(key(KeyCode::Down));
+        assert_eq!(line(&editor), "reset now");
+
+        assert_eq!(
+            editor.handle(key(KeyCode::Enter)),
+            Action::Send("reset now".into())
+        );
+        assert_eq!(editor.history, ["reset", "reset now"]);
+    }
+
+    #[test]
+    fn skips_repeated_and_empty_history_entries() {
+        let mut editor = Editor::new();
+        enter(&mut editor, "status");
+        enter(&mut editor, "status");
+        enter(&mut editor, "");
+        enter(&mut editor, "reset");
+        enter(&mut editor, "status");
+
+        assert_eq!(editor.history, ["status", "reset", "status"]);
+    }
+
+    #[test]
+    fn limits_history_size() {
+        let mut editor = Editor::new();
+
+        for i in 0..=HISTORY_SIZE {
+            enter(&mut editor, &i.to_string());
+        }
+
+        assert_eq!(editor.history.len(), HISTORY_SIZE);
+        assert_eq!(editor.history[0], "1");
+    }
+
+    #[test]
+    fn ctrl_c_clears_line_then_quits() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "abc");
+
+        assert_eq!(editor.handle(ctrl('c')), Action::None);
+        assert_eq!(line(&editor), "");
+        assert_eq!(editor.cursor, 0);
+
+        assert_eq!(editor.handle(ctrl('c')), Action::Quit);
+    }
+
+    #[test]
+    fn ctrl_d_deletes_at_cursor_then_quits() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "abc");
+
+        // Nothing to delete at the end of the line
+        assert_eq!(editor.handle(ctrl('d')), Action::None);
+        assert_eq!(line(&editor), "abc");
+
+        editor.handle(key(KeyCode::Home));
+        assert_eq!(editor.handle(ctrl('d')), Action::None);
+        assert_eq!(line(&editor), "bc");
+
+        editor.handle(ctrl('d'));
+        editor.handle(ctrl('d'));
+        assert_eq!(line(&editor), "");
+
+        assert_eq!(editor.handle(ctrl('d')), Action::Quit);
+    }
+
+    #[test]
+    fn ctrl_u_deletes_before_cursor() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "abcdef");
+        editor.handle(key(KeyCode::Left));
+        editor.handle(key(KeyCode::Left));
+
+        assert_eq!(editor.handle(ctrl('u')), Action::None);
+        assert_eq!(line(&editor), "ef");
+        assert_eq!(editor.cursor, 0);
+
+        editor.handle(key(KeyCode::End));
+        assert_eq!(editor.handle(ctrl('u')), Action::None);
+        assert_eq!(line(&editor), "");
+    }
+
+    #[test]
+    fn edits_at_cursor() {
+        let mut editor = Editor::new();
+        type_str(&mut editor, "ac");
+        editor.handle(key(KeyCode::Left));
+        type_str(&mut editor, "b");
+        assert_eq!(line(&editor), "abc");
+
+        editor.handle(ctrl('a'));
+        editor.handle(key(KeyCode::Delete));
+        editor.handle(ctrl('e'));
+        editor.handle(key(KeyCode::Backspace));
+        assert_eq!(line(&editor), "b");
+        assert_eq!(editor.cursor, 1);
+    }
+}
@@ -0,0 +1,417 @@
+//! Logging the output of each up channel to its own file, with optional rotation.
+
+use chrono::Local;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Parses a size in bytes with an optional `K`, `M` or `G` suffix, such as `10M`.
+pub fn parse_size(s: &str) -> Result<u64, &'static str> {
+    let (number, multiplier) = match s.char_indices().last() {
+        Some((i, 'K')) | Some((i, 'k')) => (&s[..i], 1 << 10),
+        Some((i, 'M')) | Some((i, 'm')) => (&s[..i], 1 << 20),
+        Some((i, 'G')) | Some((i, 'g')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+
+    match number
+        .parse::<u64>()
+        .map(|number| number.checked_mul(multiplier))
+    {
+        Ok(Some(size)) if size > 0 => Ok(size),
+        _ => Err("Invalid size, expected a number of bytes with an optional K, M or G suffix."),
+    }
+}
+
+/// Parses a duration with an `s`, `m`, `h` or `d` suffix, such as `12h`.
+pub fn parse_interval(s: &str) -> Result<Duration, &'static str> {
+    let (number, seconds) = match s.char_indices().last() {
+        Some((i, 's')) => (&s[..i], 1),
+        Some((i, 'm')) => (&s[..i], 60),
+        Some((i, 'h')) => (&s[..i], 60 * 60),
+        Some((i, 'd')) => (&s[..i], 24 * 60 * 60),
+        _ => ("", 0),
+    };
+
+    match number
+        .parse::<u64>()
+        .map(|number| number.checked_mul(seconds))
+    {
+        Ok(Some(seconds)) if seconds > 0 => Ok(Duration::from_secs(seconds)),
+        _ => Err("Invalid interval, expected a number with an s, m, h or d suffix."),
+    }
+}
+
+/// When to start a new log file.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Rotation {
+    /// Rotate once a file reaches this size.
+    pub size: Option<u64>,
+
+    /// Rotate once a file has been written to for this long.
+    pub interval: Option<Duration>,
+
+    /// Compress rotated files with gzip.
+    pub gzip: bool,
+}
+
+/// Expands a path template for a channel. Supported placeholders are `{channel}`,
+/// `{channel_name}`, which falls back to the number for channels without a name or with a name
+/// that is empty, `.` or `..`, `{date}` and `{time}`.
+fn expand(template: &str, number: usize, name: Option<&str>) -> Result<PathBuf, String> {
+    let now = Local::now();
+    let mut path = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        path.push_str(&rest[..start]);
+
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("Unterminated placeholder in log path '{}'.", template))?;
+
+        match &rest[start + 1..start + end] {
+            "channel" => path.push_str(&number.to_string()),
+            // Channel names come from the target, so keep them from escaping the directory
+            "channel_name" => match name.map(|name| name.replace(&['/', '\\'][..], "_")) {
+                Some(name) if !matches!(name.as_str(), "" | "." | "..") => path.push_str(&name),
+                _ => path.push_str(&number.to_string()),
+            },
+            "date" => path.push_str(&now.format("%Y-%m-%d").to_string()),
+            "time" => path.push_str(&now.format("%H%M%S").to_string()),
+            placeholder => {
+                return Err(format!(
+                    "Unknown placeholder {{{}}} in log path, expected {{channel}}, \
+                     {{channel_name}}, {{date}} or {{time}}.",
+                    placeholder
+                ))
+            }
+        }
+
+        rest = &rest[start + end + 1..];
+    }
+
+    path.push_str(rest);
+
+    Ok(PathBuf::from(path))
+}
+
+/// The log file of a single channel.
+struct ChannelLog {
+    number: usize,
+    name: Option<String>,
+    path: PathBuf,
+    file: BufWriter<File>,
+    size: u64,
+    opened: Instant,
+    at_line_start: bool,
+}
+
+impl ChannelLog {
+    fn open(template: &str, number: usize, name: Option<String>) -> io::Result<ChannelLog> {
+        let path = expand(template, number, name.as_deref())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+        let (file, size) = open_log(&path)?;
+
+        Ok(ChannelLog {
+            number,
+            name,
+            path,
+            file,
+            size,
+            opened: Instant::now(),
+            at_line_start: true,
+        })
+    }
+
+    fn rotation_due(&self, rotation: &Rotation) -> bool {
+        matches!(rotation.size, Some(size) if self.size >= size)
+            || matches!(rotation.interval, Some(interval) if self.opened.elapsed() >= interval)
+    }
+
+    /// Closes the current file and opens a new one. If the template expands to the same path as
+    /// before, the current file is first renamed by appending a number.
+    fn rotate(&mut self, template: &str, rotation: &Rotation) -> io::Result<()> {
+        self.file.flush()?;
+
+        let path = expand(template, self.number, self.name.as_deref())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+        let rotated = if path == self.path {
+            let rotated = (1..)
+                .map(|n| numbered(&self.path, n))
+                .find(|p| !p.exists() && !gzipped(p).exists())
+                .unwrap();
+
+            fs::rename(&self.path, &rotated)?;
+            rotated
+        } else {
+            self.path.clone()
+        };
+
+        let (file, size) = open_log(&path)?;
+        self.file = file;
+        self.size = size;
+        self.path = path;
+        self.opened = Instant::now();
+
+        if rotation.gzip {
+            // Compress in the background so that reading from the target is not held up
+            thread::spawn(move || {
+                if let Err(err) = compress(&rotated) {
+                    eprintln!("Error compressing log file {}: {}", rotated.display(), err);
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8], template: &str, rotation: &Rotation) -> io::Result<()> {
+        for line in data.split_inclusive(|&b| b == b'\n') {
+            // Only rotate between lines so that no line is split across files
+            if self.at_line_start && self.rotation_due(rotation) {
+                self.rotate(template, rotation)?;
+            }
+
+            self.file.write_all(line)?;
+            self.size += line.len() as u64;
+            self.at_line_start = line.ends_with(b"\n");
+
+            if self.at_line_start {
+                self.file.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the output of several up channels to a log file each.
+pub struct ChannelLogs {
+    template: String,
+    rotation: Rotation,
+    logs: Vec<ChannelLog>,
+}
+
+impl ChannelLogs {
+    /// Opens log files for channels with the specified numbers and names. Existing files are
+    /// appended to.
+    pub fn open(
+        template: &str,
+        rotation: Rotation,
+        channels: Vec<(usize, Option<String>)>,
+    ) -> io::Result<ChannelLogs> {
+        let logs = channels
+            .into_iter()
+            .map(|(number, name)| ChannelLog::open(template, number, name))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        for (i, log) in logs.iter().enumerate() {
+            if logs[..i].iter().any(|other| other.path == log.path) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Several channels would be logged to the same file, use {channel} or \
+                     {channel_name} in the log path.",
+                ));
+            }
+        }
+
+        Ok(ChannelLogs {
+            template: template.to_string(),
+            rotation,
+            logs,
+        })
+    }
+
+    /// Returns the paths of the current log files.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.logs.iter().map(|log| log.path.as_path())
+    }
+
+    /// Writes data from the channel with the specified index.
+    pub fn write(&mut self, index: usize, data: &[u8]) -> io::Result<()> {
+        self.logs[index].write(data, &self.template, &self.rotation)
+    }
+}
+
+/// Opens a log file for appending, creating its directory if needed. Returns the file and its
+/// current size.
+fn open_log(path: &Path) -> io::Result<(BufWriter<File>, u64)> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+
+    Ok((BufWriter::new(file), size))
+}
+
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(format!(".{}", n));
+    PathBuf::from(path)
+}
+
+fn gzipped(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".gz");
+    PathBuf::from(path)
+}
+
+/// Compresses a file with gzip and removes the original.
+fn compress(path: &Path) -> io::Result<()> {
+    let mut input = File::open(path)?;
+    let mut output = GzEncoder::new(File::create(gzipped(path))?, Compression::default());
+
+    io::copy(&mut input, &mut output)?;
+    output.finish()?.sync_all()?;
+
+    fs::remove_file(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    /// A temporary directory that is removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = std::env::temp_dir().join(format!("rtthost-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&path);
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("100"), Ok(100));
+        assert_eq!(parse_size("4k"), Ok(4 << 10));
+        assert_eq!(parse_size("10M"), Ok(10 << 20));
+        assert_eq!(parse_size("2G"), Ok(2 << 30));
+
+        for invalid in &["", "0", "0K", "K", "-1", "1.5M", "10T", "20000000000G"] {
+            assert!(parse_size(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parses_intervals() {
+        assert_eq!(parse_interval("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_interval("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_interval("12h"), Ok(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(parse_interval("1d"), Ok(Duration::from_secs(24 * 60 * 60)));
+
+        for invalid in &["", "30", "0s", "s", "1.5h", "100ms", "300000000000000d"] {
+            assert!(parse_interval(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn expands_placeholders() {
+        assert_eq!(
+            expand("logs/{channel}-{channel_name}.log", 1, Some("Log")).unwrap(),
+            PathBuf::from("logs/1-Log.log")
+        );
+        assert_eq!(
+            expand("{channel_name}.log", 2, None).unwrap(),
+            PathBuf::from("2.log")
+        );
+        assert_eq!(
+            expand("{channel_name}.log", 0, Some("../a\\b")).unwrap(),
+            PathBuf::from(".._a_b.log")
+        );
+
+        for name in &["", ".", ".."] {
+            assert_eq!(
+                expand("logs/{channel_name}/out.log", 3, Some(name)).unwrap(),
+                PathBuf::from("logs/3/out.log")
+            );
+        }
+
+        let path = expand("{date}_{time}", 0, None).unwrap();
+        let path = path.to_str().unwrap();
+        assert_eq!(path.len(), "2000-01-01_000000".len());
+        assert!(path
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == '-' || c == '_'));
+    }
+
+    #[test]
+    fn rejects_invalid_placeholders() {
+        assert!(expand("{chanel}.log", 0, None).is_err());
+        assert!(expand("{channel.log", 0, None).is_err());
+    }
+
+    #[test]
+    fn rotates_by_size_between_lines() {
+        let dir = TempDir::new("rotation");
+        let template = dir.0.join("{channel}.log");
+        let template = template.to_str().unwrap();
+        let path = dir.0.join("0.log");
+
+        let rotation = Rotation {
+            size: Some(4),
+            ..Rotation::default()
+        };
+
+        let mut logs = ChannelLogs::open(template, rotation, vec![(0, None)]).unwrap();
+
+        // The size is only checked at the start of each line
+        logs.write(0, b"first").unwrap();
+        logs.write(0, b" line\nsec").unwrap();
+        logs.write(0, b"ond\nthird\n").unwrap();
+        logs.logs[0].file.flush().unwrap();
+
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+
+        assert_eq!(read(numbered(&path, 1)), "first line\n");
+        assert_eq!(read(numbered(&path, 2)), "second\n");
+        assert_eq!(read(path.clone()), "third\n");
+        assert_eq!(logs.paths().collect::<Vec<_>>(), [path.as_path()]);
+    }
+
+    #[test]
+    fn skips_numbers_of_compressed_files() {
+        let dir = TempDir::new("rotation-gz");
+        let template = dir.0.join("{channel}.log");
+        let template = template.to_str().unwrap();
+        let path = dir.0.join("0.log");
+
+        fs::create_dir_all(&dir.0).unwrap();
+        fs::write(gzipped(&numbered(&path, 1)), b"").unwrap();
+
+        let mut logs = ChannelLogs::open(template, Rotation::default(), vec![(0, None)]).unwrap();
+        logs.write(0, b"line\n").unwrap();
+        logs.logs[0].rotate(template, &Rotation::default()).unwrap();
+
+        assert_eq!(fs::read_to_string(numbered(&path, 2)).unwrap(), "line\n");
+        assert!(!numbered(&path, 1).exists());
+    }
+
+    #[test]
+    fn rejects_shared_log_file() {
+        let dir = TempDir::new("shared");
+        let template = dir.0.join("all.log");
+
+        let channels = vec![(0, None), (1, None)];
+        assert!(
+            ChannelLogs::open(template.to_str().unwrap(), Rotation::default(), channels).is_err()
+        );
+    }
+}
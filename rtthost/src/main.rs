@@ -1,18 +1,78 @@
-use probe_rs::{config::TargetSelector, DebugProbeInfo, Probe};
-use probe_rs_rtt::{Channels, Rtt, RttChannel, ScanRegion};
-use std::io::prelude::*;
-use std::io::{stdin, stdout};
-use std::sync::{
-    mpsc::{channel, Receiver},
-    Arc, Mutex,
+use chrono::Local;
+use probe_rs::flashing;
+use probe_rs::{config::TargetSelector, DebugProbeInfo, Probe, Session, WireProtocol};
+use probe_rs_rtt::capture::{Recorder, Replay};
+use probe_rs_rtt::dump::RamDump;
+use probe_rs_rtt::gdb::GdbMemory;
+use probe_rs_rtt::openocd::OpenOcdMemory;
+use probe_rs_rtt::{
+    Channels, DownChannel, Error, Memory, Region, RegionKind, Rtt, RttChannel, ScanRegion,
+    UpChannel,
 };
+use regex::Regex;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::stdout;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 
-#[derive(Debug, PartialEq, Eq)]
+mod config;
+
+#[cfg(unix)]
+mod pty;
+#[cfg(unix)]
+use pty::{ChannelPty, PtySpec};
+
+mod fault;
+use fault::CoreMonitor;
+
+mod flash;
+
+mod input;
+use input::{Input, InputMode, LineEnding};
+
+mod logfile;
+use logfile::{ChannelLogs, Rotation};
+
+mod output;
+use output::{
+    format_prefix, ChannelOutput, Color, ColorChoice, Format, JsonLines, Output, Prefix, Stamper,
+    Timestamps,
+};
+
+mod reset;
+use reset::{ModeRestorer, RestoreOnExit};
+
+mod runner;
+use runner::Completion;
+
+mod send;
+use send::FileSender;
+
+mod server;
+use server::{ChannelServer, ListenSpec, NoClientPolicy};
+
+mod ui;
+
+/// How to select the probe, parsed from `list`, `VID:PID[:SERIAL]` or a probe number or serial
+/// number.
+#[derive(Clone, Debug, PartialEq, Eq)]
 enum ProbeInfo {
-    Number(usize),
     List,
+
+    /// A probe number or serial number. Serial numbers take precedence, as some of them consist of
+    /// digits only.
+    Id(String),
+
+    Usb {
+        vendor_id: u16,
+        product_id: u16,
+        serial_number: Option<String>,
+    },
 }
 
 impl std::str::FromStr for ProbeInfo {
@@ -20,236 +80,1700 @@ impl std::str::FromStr for ProbeInfo {
 
     fn from_str(s: &str) -> Result<ProbeInfo, &'static str> {
         if s == "list" {
-            Ok(ProbeInfo::List)
-        } else if let Ok(n) = s.parse::<usize>() {
-            Ok(ProbeInfo::Number(n))
-        } else {
-            Err("Invalid probe number.")
+            return Ok(ProbeInfo::List);
+        }
+
+        let parts: Vec<&str> = s.splitn(3, ':').collect();
+
+        match parts.as_slice() {
+            [id] => Ok(ProbeInfo::Id(id.to_string())),
+            [vid, pid, serial @ ..] => Ok(ProbeInfo::Usb {
+                vendor_id: u16::from_str_radix(vid, 16).map_err(|_| "Invalid vendor ID.")?,
+                product_id: u16::from_str_radix(pid, 16).map_err(|_| "Invalid product ID.")?,
+                serial_number: serial.first().map(|s| s.to_string()),
+            }),
+            [] => Err("Invalid probe."),
+        }
+    }
+}
+
+impl std::fmt::Display for ProbeInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ProbeInfo::List => write!(f, "list"),
+            ProbeInfo::Id(id) => write!(f, "{}", id),
+            ProbeInfo::Usb {
+                vendor_id,
+                product_id,
+                serial_number,
+            } => {
+                write!(f, "{:04x}:{:04x}", vendor_id, product_id)?;
+
+                match serial_number {
+                    Some(serial) => write!(f, ":{}", serial),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl ProbeInfo {
+    /// Returns the indices of the matching probes.
+    fn find(&self, probes: &[DebugProbeInfo]) -> Vec<usize> {
+        let serial_matches =
+            |probe: &DebugProbeInfo, serial: &str| probe.serial_number.as_deref() == Some(serial);
+
+        match self {
+            ProbeInfo::List => Vec::new(),
+            ProbeInfo::Id(id) => {
+                let matches: Vec<usize> = (0..probes.len())
+                    .filter(|&i| serial_matches(&probes[i], id))
+                    .collect();
+
+                match id.parse::<usize>() {
+                    Ok(i) if matches.is_empty() && i < probes.len() => vec![i],
+                    _ => matches,
+                }
+            }
+            ProbeInfo::Usb {
+                vendor_id,
+                product_id,
+                serial_number,
+            } => (0..probes.len())
+                .filter(|&i| {
+                    let probe = &probes[i];
+
+                    probe.vendor_id == *vendor_id
+                        && probe.product_id == *product_id
+                        && serial_number
+                            .iter()
+                            .all(|serial| serial_matches(probe, serial))
+                })
+                .collect(),
+        }
+    }
+}
+
+fn parse_protocol(src: &str) -> Result<WireProtocol, &'static str> {
+    match src.to_ascii_lowercase().as_str() {
+        "swd" => Ok(WireProtocol::Swd),
+        "jtag" => Ok(WireProtocol::Jtag),
+        _ => Err("Invalid protocol, expected 'swd' or 'jtag'."),
+    }
+}
+
+/// A selection of channels, parsed from a comma separated list of numbers or `all`.
+#[derive(Debug, PartialEq, Eq)]
+enum ChannelList {
+    All,
+    Numbers(Vec<usize>),
+}
+
+impl std::str::FromStr for ChannelList {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<ChannelList, &'static str> {
+        if s == "all" {
+            return Ok(ChannelList::All);
+        }
+
+        s.split(',')
+            .map(|n| n.trim().parse())
+            .collect::<Result<Vec<_>, _>>()
+            .map(ChannelList::Numbers)
+            .map_err(|_| "Invalid channel list, expected numbers separated by commas or 'all'.")
+    }
+}
+
+impl ChannelList {
+    fn contains(&self, number: usize) -> bool {
+        match self {
+            ChannelList::All => true,
+            ChannelList::Numbers(numbers) => numbers.contains(&number),
+        }
+    }
+
+    fn first(&self) -> Option<usize> {
+        match self {
+            ChannelList::All => None,
+            ChannelList::Numbers(numbers) => numbers.first().copied(),
         }
     }
 }
 
+fn parse_address(src: &str) -> Result<u32, std::num::ParseIntError> {
+    if src.starts_with("0x") || src.starts_with("0X") {
+        u32::from_str_radix(&src[2..], 16)
+    } else {
+        src.parse()
+    }
+}
+
 fn parse_scan_region(mut src: &str) -> Result<ScanRegion, Box<dyn std::error::Error>> {
     src = src.trim();
     if src.is_empty() {
         return Ok(ScanRegion::Ram);
     }
 
-    let parts = src
-        .split("..")
-        .map(|p| {
-            if p.starts_with("0x") || p.starts_with("0X") {
-                u32::from_str_radix(&p[2..], 16)
+    let parts = src
+        .split("..")
+        .map(parse_address)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match parts.as_slice() {
+        &[addr] => Ok(ScanRegion::Exact(addr)),
+        &[start, end] => Ok(ScanRegion::Range(start..end)),
+        _ => Err("Invalid range: multiple '..'s".into()),
+    }
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "rtthost",
+    about = "Host program for debugging microcontrollers using the RTT (real-time transfer) protocol."
+)]
+struct Opts {
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Read options from the specified TOML file instead of rtthost.toml in the current directory or one of its parents. Options given on the command line take precedence."
+    )]
+    config: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "Use the options of the named profile in the configuration file, in addition to its top-level ones."
+    )]
+    profile: Option<String>,
+
+    #[structopt(
+        short,
+        long,
+        default_value = "0",
+        help = "Specify probe number, serial number, USB IDs as 'VID:PID[:SERIAL]' in hex, or 'list' to list probes."
+    )]
+    probe: ProbeInfo,
+
+    #[structopt(
+        long,
+        parse(try_from_str = parse_protocol),
+        help = "Wire protocol to use: 'swd' or 'jtag'. Defaults to what the probe prefers."
+    )]
+    protocol: Option<WireProtocol>,
+
+    #[structopt(long, help = "Debug clock speed in kHz.")]
+    speed: Option<u32>,
+
+    #[structopt(
+        long,
+        help = "Hold the target in reset while connecting, for targets that are asleep or have disabled the debug pins."
+    )]
+    connect_under_reset: bool,
+
+    #[structopt(
+        short,
+        long,
+        help = "Target chip type. Leave unspecified to auto-detect."
+    )]
+    chip: Option<String>,
+
+    #[structopt(short, long, help = "List RTT channels and exit.")]
+    list: bool,
+
+    #[structopt(
+        short,
+        long,
+        help = "Numbers of up channels to output, separated by commas, or 'all'. Defaults to 0 if it exists."
+    )]
+    up: Option<ChannelList>,
+
+    #[structopt(
+        long,
+        default_value = "text",
+        help = "Output format: 'text' for the raw data, or 'jsonl' for a JSON object per line with the host timestamp, channel number and name, and the text or base64 encoded data. Also applies to --list."
+    )]
+    format: Format,
+
+    #[structopt(
+        long,
+        help = "Add the time at which each line was received to its start in text output and log files: 'absolute' local time, 'relative' seconds since attaching, or 'delta' seconds since the previous line of the same channel. Lines are stamped with the time of the poll that delivered their first byte. Not used with --format jsonl, which always includes timestamps."
+    )]
+    timestamps: Option<Timestamps>,
+
+    #[structopt(
+        long,
+        default_value = "auto",
+        help = "Prefix for each line of output: the channel 'number', its 'name' or 'none'. 'auto' shows numbers if more than one up channel is output."
+    )]
+    prefix: Prefix,
+
+    #[structopt(
+        long,
+        default_value = "auto",
+        help = "Whether to color line prefixes: 'auto', 'always' or 'never'."
+    )]
+    color: ColorChoice,
+
+    #[structopt(
+        long,
+        use_delimiter = true,
+        help = "Colors of the line prefixes of each up channel in the order given to --up, such as 'red,bright-blue'."
+    )]
+    colors: Vec<Color>,
+
+    #[structopt(
+        long,
+        default_value = "1s",
+        parse(try_from_str = logfile::parse_interval),
+        help = "Break a partial line once it has held back the output of other up channels for the specified time, so that they can continue. With --format jsonl, write partial lines as they are after this time."
+    )]
+    stall_timeout: Duration,
+
+    #[structopt(
+        short,
+        long,
+        help = "Number of down channel for keyboard input. Defaults to 0 if it exists."
+    )]
+    down: Option<usize>,
+
+    #[structopt(
+        long,
+        default_value = "cooked",
+        help = "How to read keyboard input: 'cooked' sends lines as entered in the terminal, 'line' edits lines in rtthost with history and Ctrl-C or Ctrl-D on an empty line to exit, and 'raw' sends each keypress as typed, including Ctrl-C, until Ctrl-] is pressed."
+    )]
+    input: InputMode,
+
+    #[structopt(
+        long,
+        default_value = "lf",
+        help = "Line ending to send after each line of input, also in the --tui input line: 'lf', 'cr' or 'crlf'. Not used with --input raw."
+    )]
+    line_ending: LineEnding,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Send the contents of the file to the down channel as fast as the target reads them, printing the progress and throughput. Keyboard input is disabled."
+    )]
+    send: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "With --send, wait for the text to appear in the output of an up channel after each block, such as 'ACK'."
+    )]
+    send_ack: Option<String>,
+
+    #[structopt(
+        long,
+        default_value = "1K",
+        parse(try_from_str = logfile::parse_size),
+        help = "Size of the blocks acknowledged with --send-ack."
+    )]
+    send_block_size: u64,
+
+    #[structopt(
+        long,
+        default_value = "10s",
+        parse(try_from_str = logfile::parse_interval),
+        help = "Exit with code 1 if an acknowledgement does not appear within the specified time."
+    )]
+    send_ack_timeout: Duration,
+
+    #[structopt(
+        long,
+        default_value="",
+        parse(try_from_str=parse_scan_region),
+        help = "Memory region to scan for control block. You can specify either an exact starting address '0x1000' or a range such as '0x0000..0x1000'. Both decimal and hex are accepted.")]
+    scan_region: ScanRegion,
+
+    #[structopt(
+        long,
+        help = "Also write the output of each up channel to its own file while outputting to the terminal. The path may contain {channel}, {channel_name}, {date} and {time}, such as 'logs/{channel_name}-{date}.log'. Existing files are appended to."
+    )]
+    log: Option<String>,
+
+    #[structopt(
+        long,
+        parse(try_from_str = logfile::parse_size),
+        help = "Start a new log file once it reaches the specified size, such as '10M'. If the path expands to the same file, the previous one is renamed with a number appended."
+    )]
+    log_rotate_size: Option<u64>,
+
+    #[structopt(
+        long,
+        parse(try_from_str = logfile::parse_interval),
+        help = "Start a new log file after the specified time, such as '30m', '12h' or '1d'."
+    )]
+    log_rotate_interval: Option<std::time::Duration>,
+
+    #[structopt(long, help = "Compress rotated log files with gzip.")]
+    log_gzip: bool,
+
+    #[structopt(
+        long,
+        number_of_values = 1,
+        help = "Exit with code 0 once a line of output matches the regular expression, such as 'TESTS PASSED'. Can be specified multiple times."
+    )]
+    exit_on: Vec<Regex>,
+
+    #[structopt(
+        long,
+        number_of_values = 1,
+        help = "Exit with code 1 once a line of output matches the regular expression, such as 'panicked'. Takes precedence over --exit-on. Can be specified multiple times."
+    )]
+    fail_on: Vec<Regex>,
+
+    #[structopt(
+        long,
+        help = "Exit with the code given by a line of output containing the marker followed by a number, such as 'RTTHOST_EXIT: 3'. The marker defaults to 'RTTHOST_EXIT:'."
+    )]
+    exit_marker: Option<Option<String>>,
+
+    #[structopt(
+        long,
+        parse(try_from_str = logfile::parse_interval),
+        help = "Exit with code 124 if the run has not ended after the specified time, such as '30s' or '5m'."
+    )]
+    timeout: Option<std::time::Duration>,
+
+    #[structopt(
+        long,
+        default_value = "1000",
+        help = "Interval in milliseconds at which to check whether the core has halted, locked up or entered the HardFault handler, in which case the registers and fault status are printed once the remaining data is read. 0 disables checking. Only used with a probe."
+    )]
+    core_check_interval: u64,
+
+    #[structopt(
+        long,
+        help = "Exit with code 3 once the core has halted, locked up or entered the HardFault handler."
+    )]
+    exit_on_halt: bool,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Flash the ELF file to the target, reset it and attach to RTT at the address of its _SEGGER_RTT symbol, unless --scan-region is specified."
+    )]
+    flash: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "Reset the target and attach as soon as the firmware has created the control block, switching all up channels to BlockIfFull mode so that early output is not lost. The original modes are restored on exit."
+    )]
+    reset: bool,
+
+    #[structopt(
+        long,
+        conflicts_with = "reset",
+        help = "Like --reset, but keep the core halted while attaching and switching the modes, so that no output can be written before."
+    )]
+    reset_halt: bool,
+
+    #[structopt(
+        long,
+        help = "When the probe is unplugged or the target loses power, wait for them to come back, attach again and continue with the same channels."
+    )]
+    reconnect: bool,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Record the session to a capture file."
+    )]
+    record: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Replay a capture file instead of connecting to a probe."
+    )]
+    replay: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "Access target memory through a gdbserver at the specified address, such as 'localhost:1234', instead of a probe. Requires --scan-region. The target is resumed after connecting. Unless the server supports non-stop mode, it is briefly interrupted for each memory access."
+    )]
+    gdb: Option<String>,
+
+    #[structopt(
+        long,
+        help = "Access target memory through the TCL RPC server of OpenOCD at the specified address, such as 'localhost:6666', instead of a probe. Requires --scan-region."
+    )]
+    openocd: Option<String>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Extract up channel data from a raw RAM dump file instead of connecting to a probe."
+    )]
+    dump: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        default_value = "0x20000000",
+        parse(try_from_str = parse_address),
+        help = "Target address of the start of the RAM dump."
+    )]
+    dump_address: u32,
+
+    #[structopt(
+        long,
+        help = "Output the entire ring buffer contents of each channel from the RAM dump instead of only unread data."
+    )]
+    history: bool,
+
+    #[structopt(
+        long,
+        number_of_values = 1,
+        help = "Serve channels over TCP instead of the terminal, as JLinkRTTServer does. The address is specified as '[CHANNEL@]ADDR', where ADDR is either 'host:port' or just a port to only accept local connections, and CHANNEL is the number of the up/down channel pair to serve, defaulting to --up and --down. Can be specified multiple times."
+    )]
+    listen: Vec<ListenSpec>,
+
+    #[structopt(
+        long,
+        default_value = "buffer",
+        help = "What to do with up channel data while no client is connected: 'buffer' it up to --buffer-size, 'drop' it, or 'pause' reading from the channel."
+    )]
+    no_client: NoClientPolicy,
+
+    #[structopt(
+        long,
+        default_value = "65536",
+        help = "Size in bytes of the buffers for each TCP server or PTY."
+    )]
+    buffer_size: usize,
+
+    #[cfg(unix)]
+    #[structopt(
+        long,
+        min_values = 0,
+        number_of_values = 1,
+        help = "Expose channels as pseudo-terminals for use with serial port tools instead of the terminal. Optionally specified as '[CHANNEL@]LINK', where CHANNEL is the number of the up/down channel pair, defaulting to --up and --down, and LINK is the path of a symlink to create to the PTY, such as '/tmp/rtt-terminal'. Can be specified multiple times."
+    )]
+    pty: Option<Vec<PtySpec>>,
+
+    #[structopt(
+        long,
+        help = "Show an interactive full-screen interface with a tab for each up channel selected with --up, defaulting to all of them. Input is written to the down channel with the same number."
+    )]
+    tui: bool,
+
+    #[cfg(unix)]
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Run as a daemon serving all channels to local clients over a Unix domain socket at the specified path, so that several programs can share the session."
+    )]
+    daemon: Option<PathBuf>,
+}
+
+fn main() {
+    pretty_env_logger::init();
+
+    std::process::exit(run());
+}
+
+/// Parses the command line, filling in options it does not specify from the configuration file.
+fn parse_opts() -> Result<Opts, i32> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let matches = Opts::clap().get_matches_from(&args);
+    let opts = Opts::from_clap(&matches);
+
+    let path = match opts.config.clone().or_else(config::find) {
+        Some(path) => path,
+        None if opts.profile.is_some() => {
+            eprintln!(
+                "--profile requires a configuration file, but no {} was found.",
+                config::FILE_NAME
+            );
+            return Err(1);
+        }
+        None => return Ok(opts),
+    };
+
+    let options = match config::load(&path, opts.profile.as_deref()) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("Error reading {}: {}", path.display(), err);
+            return Err(1);
+        }
+    };
+
+    match Opts::clap().get_matches_from_safe(merge_config(args, options)) {
+        Ok(matches) => Ok(Opts::from_clap(&matches)),
+        Err(err) => {
+            // Leave out the usage, which describes the command line rather than the file
+            let message = err.message.lines().next().unwrap_or_default();
+            eprintln!(
+                "Error in {}: {}",
+                path.display(),
+                message.trim_start_matches("error: ")
+            );
+            Err(1)
+        }
+    }
+}
+
+/// Appends the arguments of the options from the configuration file to the command line, leaving
+/// out those that are given on the command line or conflict with an option given there.
+fn merge_config(args: Vec<OsString>, options: Vec<config::ConfigOption>) -> Vec<OsString> {
+    let matches = Opts::clap().get_matches_from(&args);
+    let mut merged = args.clone();
+
+    for option in options {
+        if matches.occurrences_of(&option.name) > 0 {
+            continue;
+        }
+
+        let option_args: Vec<OsString> = option.args.into_iter().map(OsString::from).collect();
+
+        let conflicts = matches!(
+            Opts::clap().get_matches_from_safe(args.iter().chain(&option_args)),
+            Err(err) if err.kind == structopt::clap::ErrorKind::ArgumentConflict
+        );
+
+        if !conflicts {
+            merged.extend(option_args);
+        }
+    }
+
+    merged
+}
+
+fn run() -> i32 {
+    let opts = match parse_opts() {
+        Ok(opts) => opts,
+        Err(code) => return code,
+    };
+
+    if let Some(path) = &opts.dump {
+        return dump(&opts, path);
+    }
+
+    let remote = opts.replay.is_some() || opts.gdb.is_some() || opts.openocd.is_some();
+
+    if (opts.reset || opts.reset_halt || opts.flash.is_some() || opts.reconnect) && remote {
+        eprintln!("--reset, --reset-halt, --flash and --reconnect can only be used with a probe.");
+        return 1;
+    }
+
+    // The probe-rs session, if any, for controlling the target
+    let mut session = None;
+
+    // Restores the channel modes changed by --reset when dropped on exit
+    let mut restorer = None;
+
+    // The probe and where to look for the control block, for --reconnect
+    let mut reconnect_to = None;
+
+    let rtt = match (&opts.replay, &opts.gdb, &opts.openocd) {
+        (Some(path), _, _) => Replay::open(path)
+            .and_then(|r| r.with_realtime(true).into_rtt())
+            .map_err(|err| {
+                eprintln!("Error replaying capture file: {}", err);
+                1
+            }),
+        (None, Some(addr), _) => attach_remote(
+            &opts,
+            "gdbserver",
+            GdbMemory::connect(addr),
+            GdbMemory::with_region,
+        ),
+        (None, None, Some(addr)) => attach_remote(
+            &opts,
+            "OpenOCD",
+            OpenOcdMemory::connect(addr),
+            OpenOcdMemory::with_region,
+        ),
+        (None, None, None) => attach_probe(&opts).map(|attachment| {
+            session = Some(attachment.session);
+            restorer = attachment.restorer.map(RestoreOnExit::new);
+            reconnect_to = Some((attachment.probe, attachment.scan_region));
+            attachment.rtt
+        }),
+    };
+
+    let mut rtt = match rtt {
+        Ok(rtt) => rtt,
+        Err(code) => return code,
+    };
+
+    if opts.list {
+        if opts.format == Format::Jsonl {
+            return list_channels_json(&mut rtt);
+        }
+
+        println!("Up channels:");
+        list_channels(rtt.up_channels());
+
+        println!("Down channels:");
+        list_channels(rtt.down_channels());
+
+        return 0;
+    }
+
+    let mut recorder = match &opts.record {
+        Some(path) => match File::create(path)
+            .map_err(Error::from)
+            .and_then(|file| Recorder::new(file, &mut rtt))
+        {
+            Ok(recorder) => Some(recorder),
+            Err(err) => {
+                eprintln!("Error creating capture file: {}", err);
+                return 1;
+            }
+        },
+        None => None,
+    };
+
+    #[cfg(unix)]
+    let pty = opts.pty.is_some();
+    #[cfg(not(unix))]
+    let pty = false;
+
+    #[cfg(unix)]
+    let daemon_mode = opts.daemon.is_some();
+    #[cfg(not(unix))]
+    let daemon_mode = false;
+
+    let terminal_options = [
+        ("--log", opts.log.is_some()),
+        ("--exit-on", !opts.exit_on.is_empty()),
+        ("--fail-on", !opts.fail_on.is_empty()),
+        ("--exit-marker", opts.exit_marker.is_some()),
+        ("--timeout", opts.timeout.is_some()),
+        ("--exit-on-halt", opts.exit_on_halt),
+        ("--reconnect", opts.reconnect),
+        ("--input", opts.input != InputMode::Cooked),
+        ("--send", opts.send.is_some()),
+    ];
+
+    if opts.tui || !opts.listen.is_empty() || pty || daemon_mode {
+        if let Some((option, _)) = terminal_options.iter().find(|(_, used)| *used) {
+            eprintln!(
+                "{} can only be used when outputting to the terminal.",
+                option
+            );
+            return 1;
+        }
+    }
+
+    #[cfg(unix)]
+    {
+        if let Some(path) = &opts.daemon {
+            if recorder.is_some() {
+                eprintln!("--record cannot be used with --daemon.");
+                return 1;
+            }
+
+            return daemon(&mut rtt, path);
+        }
+    }
+
+    if !opts.listen.is_empty() || pty {
+        return serve(&opts, &mut rtt, recorder);
+    }
+
+    if opts.tui {
+        return tui(&opts, &mut rtt, session, recorder);
+    }
+
+    let mut up_channels: Vec<UpChannel> = match &opts.up {
+        Some(ChannelList::All) => rtt.up_channels().drain().collect(),
+        Some(ChannelList::Numbers(numbers)) => {
+            let mut channels = Vec::new();
+
+            for &up in numbers {
+                match rtt.up_channels().take(up) {
+                    Some(chan) => channels.push(chan),
+                    None => {
+                        eprintln!("Error: up channel {} does not exist.", up);
+                        return 1;
+                    }
+                }
+            }
+
+            channels
+        }
+        None => rtt.up_channels().take(0).into_iter().collect(),
+    };
+
+    let mut down_channel = if let Some(down) = opts.down {
+        let chan = rtt.down_channels().take(down);
+
+        if chan.is_none() {
+            eprintln!("Error: up channel {} does not exist.", down);
+            return 1;
+        }
+
+        chan
+    } else {
+        rtt.down_channels().take(0)
+    };
+
+    eprintln!("Found control block at 0x{:08x}", rtt.ptr());
+
+    let input = match down_channel {
+        Some(_) if opts.send.is_none() => match Input::start(opts.input, opts.line_ending) {
+            Ok(input) => Some(input),
+            Err(err) => {
+                eprintln!("Error reading input: {}", err);
+                return 1;
+            }
+        },
+        _ => None,
+    };
+
+    let console = || input.as_ref().map(Input::console).unwrap_or_default();
+
+    // Taken again by number after reconnecting
+    let up_numbers: Vec<usize> = up_channels.iter().map(|chan| chan.number()).collect();
+    let down_number = down_channel.as_ref().map(|chan| chan.number());
+
+    let reconnect_to = reconnect_to.filter(|_| opts.reconnect);
+
+    let mut output: Box<dyn ChannelOutput> = match opts.format {
+        Format::Text => Box::new(Output::new(
+            console(),
+            line_prefixes(&opts, &up_channels),
+            Some(opts.stall_timeout),
+        )),
+        Format::Jsonl => Box::new(JsonLines::new(
+            console(),
+            up_channels
+                .iter()
+                .map(|chan| (chan.number(), chan.name().map(String::from)))
+                .collect(),
+            Some(opts.stall_timeout),
+        )),
+    };
+
+    let mut logs = match &opts.log {
+        Some(template) => {
+            let rotation = Rotation {
+                size: opts.log_rotate_size,
+                interval: opts.log_rotate_interval,
+                gzip: opts.log_gzip,
+            };
+
+            let channels = up_channels
+                .iter()
+                .map(|chan| (chan.number(), chan.name().map(String::from)))
+                .collect();
+
+            match ChannelLogs::open(template, rotation, channels) {
+                Ok(logs) => {
+                    for path in logs.paths() {
+                        eprintln!("Logging to {}", path.display());
+                    }
+
+                    Some(logs)
+                }
+                Err(err) => {
+                    eprintln!("Error opening log files: {}", err);
+                    return 1;
+                }
+            }
+        }
+        None => None,
+    };
+
+    let mut stamper = opts
+        .timestamps
+        .map(|mode| Stamper::new(mode, up_channels.len()));
+
+    let mut completion = Completion::new(
+        opts.exit_on.clone(),
+        opts.fail_on.clone(),
+        opts.exit_marker
+            .clone()
+            .map(|marker| marker.unwrap_or_else(|| String::from(runner::DEFAULT_EXIT_MARKER))),
+        up_channels.len(),
+    );
+
+    let deadline = opts.timeout.map(|timeout| Instant::now() + timeout);
+
+    let mut sender = match &opts.send {
+        Some(_) if down_channel.is_none() => {
+            eprintln!("--send requires a down channel.");
+            return 1;
+        }
+        Some(path) => {
+            let ack = opts.send_ack.clone().map(|text| send::Ack {
+                text,
+                block_size: opts.send_block_size as usize,
+                timeout: opts.send_ack_timeout,
+            });
+
+            match FileSender::open(path, ack, up_channels.len()) {
+                Ok(sender) => Some(sender),
+                Err(err) => {
+                    eprintln!("Error reading {}: {}", path.display(), err);
+                    return 1;
+                }
+            }
+        }
+        None => None,
+    };
+
+    let mut monitor = session.and_then(|session| core_monitor(&opts, session));
+
+    // Set once the core has stopped, to report it after the remaining data has been read
+    let mut stopped = None;
+
+    // Set when the connection is lost with --reconnect
+    let mut lost = false;
+
+    let mut up_buf = [0u8; 1024];
+    let mut down_buf = vec![];
+
+    loop {
+        if lost {
+            lost = false;
+
+            if let Err(err) = output.finish() {
+                eprintln!("Error writing to stdout: {}", err);
+                return 1;
+            }
+
+            let (probe, scan_region) = reconnect_to.as_ref().unwrap();
+
+            eprintln!("--- Connection lost, waiting for probe {} ---", probe);
+
+            // Release the probe so that it can be opened again
+            up_channels.clear();
+            down_channel = None;
+            drop(monitor.take());
+            stopped = None;
+            drop(rtt);
+
+            if let Some(restorer) = &restorer {
+                restorer.replace(None);
+            }
+
+            let session = match reconnect(&opts, probe, scan_region, deadline) {
+                Some((new_rtt, session)) => {
+                    rtt = new_rtt;
+                    session
+                }
+                None => {
+                    eprintln!(
+                        "Timed out, exiting with code {}.",
+                        runner::TIMEOUT_EXIT_CODE
+                    );
+                    return runner::TIMEOUT_EXIT_CODE;
+                }
+            };
+
+            // Block again before taking the channels, as the firmware has set up new ones
+            if let Some(restorer) = &restorer {
+                match ModeRestorer::block_if_full(session.clone(), &mut rtt) {
+                    Ok(modes) => restorer.replace(Some(modes)),
+                    Err(err) => {
+                        eprintln!("Error changing channel modes: {}", err);
+                        return 1;
+                    }
+                }
+            }
+
+            for &number in &up_numbers {
+                match rtt.up_channels().take(number) {
+                    Some(chan) => up_channels.push(chan),
+                    None => {
+                        eprintln!("Error: up channel {} no longer exists.", number);
+                        return 1;
+                    }
+                }
+            }
+
+            if let Some(number) = down_number {
+                down_channel = rtt.down_channels().take(number);
+
+                if down_channel.is_none() {
+                    eprintln!("Error: down channel {} no longer exists.", number);
+                    return 1;
+                }
+            }
+
+            monitor = core_monitor(&opts, session);
+
+            eprintln!(
+                "--- Reconnected at {}, control block at 0x{:08x} ---",
+                Local::now().format("%Y-%m-%d %H:%M:%S"),
+                rtt.ptr()
+            );
+        }
+
+        let mut received = 0;
+
+        for (index, up_channel) in up_channels.iter().enumerate() {
+            let count = match up_channel.read(up_buf.as_mut()) {
+                Ok(count) => count,
+                Err(err) => {
+                    eprintln!("\nError reading from RTT: {}", err);
+
+                    if reconnect_to.is_none() {
+                        return 1;
+                    }
+
+                    lost = true;
+                    break;
+                }
+            };
+
+            let polled = Instant::now();
+            received += count;
+
+            if let Some(recorder) = recorder.as_mut() {
+                if let Err(err) = recorder.record_up(up_channel, &up_buf[..count]) {
+                    eprintln!("\nError writing to capture file: {}", err);
+                    return 1;
+                }
+            }
+
+            let stamped;
+            let data = match stamper.as_mut() {
+                Some(stamper) if opts.format == Format::Text => {
+                    stamped = stamper.stamp(index, &up_buf[..count], polled);
+                    &stamped[..]
+                }
+                _ => &up_buf[..count],
+            };
+
+            if let Err(err) = output.write(index, data) {
+                eprintln!("Error writing to stdout: {}", err);
+                return 1;
+            }
+
+            if let Some(logs) = logs.as_mut() {
+                if let Err(err) = logs.write(index, data) {
+                    eprintln!("\nError writing to log file: {}", err);
+                    return 1;
+                }
+            }
+
+            if completion.is_active() {
+                if let Some(code) = completion.check(index, &up_buf[..count]) {
+                    return finish(output.as_mut(), code);
+                }
+            }
+
+            if let Some(sender) = sender.as_mut() {
+                sender.check(index, &up_buf[..count]);
+            }
+        }
+
+        if lost {
+            continue;
+        }
+
+        if let Err(err) = output.poll() {
+            eprintln!("Error writing to stdout: {}", err);
+            return 1;
+        }
+
+        if let (Some(stop), 0) = (stopped, received) {
+            stopped = None;
+
+            if let Err(err) = output.finish() {
+                eprintln!("Error writing to stdout: {}", err);
+                return 1;
+            }
+
+            let monitor = monitor.as_mut().unwrap();
+
+            match monitor.report(stop) {
+                Ok(report) => eprint!("\n{}", report),
+                Err(err) => eprintln!("\n{}, error reading registers: {}", stop, err),
+            }
+
+            if opts.exit_on_halt {
+                eprintln!("Exiting with code {}.", fault::HALT_EXIT_CODE);
+                return fault::HALT_EXIT_CODE;
+            }
+        }
+
+        if let Some(monitor) = monitor.as_mut() {
+            match monitor.poll() {
+                Ok(stop) => stopped = stopped.or(stop),
+                Err(err) => {
+                    eprintln!("\nError checking the core status: {}", err);
+
+                    if reconnect_to.is_none() {
+                        return 1;
+                    }
+
+                    lost = true;
+                    continue;
+                }
+            }
+        }
+
+        if matches!(deadline, Some(deadline) if Instant::now() >= deadline) {
+            eprintln!(
+                "\nTimed out, exiting with code {}.",
+                runner::TIMEOUT_EXIT_CODE
+            );
+            return finish(output.as_mut(), runner::TIMEOUT_EXIT_CODE);
+        }
+
+        if let (Some(down_channel), Some(file)) = (down_channel.as_ref(), sender.as_mut()) {
+            if let Err(err) = file.check_timeout() {
+                eprintln!("\n{}", err);
+                return finish(output.as_mut(), 1);
+            }
+
+            let written = match file.write(down_channel) {
+                Ok(written) => written,
+                Err(err) => {
+                    eprintln!("\nError writing to RTT: {}", err);
+
+                    if reconnect_to.is_none() {
+                        return 1;
+                    }
+
+                    lost = true;
+                    continue;
+                }
+            };
+
+            if let Some(recorder) = recorder.as_mut() {
+                if let Err(err) = recorder.record_down(down_channel, written) {
+                    eprintln!("\nError writing to capture file: {}", err);
+                    return 1;
+                }
+            }
+
+            if file.is_done() {
+                eprintln!("{}", file.summary());
+                sender = None;
+            }
+        }
+
+        if let (Some(down_channel), Some(input)) = (down_channel.as_ref(), &input) {
+            if let Some(bytes) = input.try_recv() {
+                down_buf.extend_from_slice(bytes.as_slice());
+            } else if input.quit() {
+                return finish(output.as_mut(), 0);
+            }
+
+            if !down_buf.is_empty() {
+                let count = match down_channel.write(down_buf.as_mut()) {
+                    Ok(count) => count,
+                    Err(err) => {
+                        eprintln!("\nError writing to RTT: {}", err);
+
+                        if reconnect_to.is_none() {
+                            return 1;
+                        }
+
+                        lost = true;
+                        continue;
+                    }
+                };
+
+                if let Some(recorder) = recorder.as_mut() {
+                    if let Err(err) = recorder.record_down(down_channel, &down_buf[..count]) {
+                        eprintln!("\nError writing to capture file: {}", err);
+                        return 1;
+                    }
+                }
+
+                if count > 0 {
+                    down_buf.drain(..count);
+                }
+            }
+        }
+    }
+}
+
+/// Creates a monitor for the core status unless disabled with --core-check-interval 0.
+fn core_monitor(opts: &Opts, session: Arc<Mutex<Session>>) -> Option<CoreMonitor> {
+    if opts.core_check_interval == 0 {
+        return None;
+    }
+
+    let interval = Duration::from_millis(opts.core_check_interval);
+
+    match CoreMonitor::new(session, interval) {
+        Ok(monitor) => Some(monitor),
+        Err(err) => {
+            eprintln!("Not checking the core status: {}", err);
+            None
+        }
+    }
+}
+
+/// Writes any output held back and returns the exit code, or 1 if writing fails.
+fn finish(output: &mut dyn ChannelOutput, code: i32) -> i32 {
+    match output.finish() {
+        Ok(()) => code,
+        Err(err) => {
+            eprintln!("Error writing to stdout: {}", err);
+            1
+        }
+    }
+}
+
+/// Returns the line prefix for each up channel.
+fn line_prefixes(opts: &Opts, channels: &[UpChannel]) -> Vec<String> {
+    let prefix = match opts.prefix {
+        Prefix::Auto if channels.len() > 1 => Prefix::Number,
+        Prefix::Auto => Prefix::None,
+        prefix => prefix,
+    };
+
+    let color = opts.color.enabled();
+
+    channels
+        .iter()
+        .enumerate()
+        .map(|(i, chan)| {
+            let label = match prefix {
+                Prefix::Auto | Prefix::None => return String::new(),
+                Prefix::Number => chan.number().to_string(),
+                Prefix::Name => chan
+                    .name()
+                    .map_or_else(|| chan.number().to_string(), String::from),
+            };
+
+            let colors = if opts.colors.is_empty() {
+                &Color::DEFAULTS[..]
             } else {
-                p.parse()
-            }
+                &opts.colors[..]
+            };
+
+            format_prefix(&label, Some(colors[i % colors.len()]).filter(|_| color))
         })
-        .collect::<Result<Vec<_>, _>>()?;
+        .collect()
+}
 
-    match parts.as_slice() {
-        &[addr] => Ok(ScanRegion::Exact(addr)),
-        &[start, end] => Ok(ScanRegion::Range(start..end)),
-        _ => Err("Invalid range: multiple '..'s".into()),
+/// Takes the up/down channel pair with the specified number, or the ones selected with --up and
+/// --down. Fails if neither exists, e.g. because it was already taken.
+fn take_pair(
+    opts: &Opts,
+    rtt: &mut Rtt,
+    channel: Option<usize>,
+) -> Result<(usize, Option<UpChannel>, Option<DownChannel>), i32> {
+    let up = channel
+        .or_else(|| opts.up.as_ref().and_then(ChannelList::first))
+        .unwrap_or(0);
+    let down = channel.or(opts.down).unwrap_or(0);
+
+    let up_channel = rtt.up_channels().take(up);
+    let down_channel = rtt.down_channels().take(down);
+
+    if up_channel.is_none() && down_channel.is_none() {
+        eprintln!("Error: channel {} does not exist or is already served.", up);
+        return Err(1);
     }
+
+    Ok((up, up_channel, down_channel))
 }
 
-#[derive(Debug, StructOpt)]
-#[structopt(
-    name = "rtthost",
-    about = "Host program for debugging microcontrollers using the RTT (real-time transfer) protocol."
-)]
-struct Opts {
-    #[structopt(
-        short,
-        long,
-        default_value = "0",
-        help = "Specify probe number or 'list' to list probes."
-    )]
-    probe: ProbeInfo,
+/// How long the serving loops sleep after a pass that moved no data, so that they do not keep a
+/// CPU core busy while idle.
+const IDLE_INTERVAL: Duration = Duration::from_millis(1);
 
-    #[structopt(
-        short,
-        long,
-        help = "Target chip type. Leave unspecified to auto-detect."
-    )]
-    chip: Option<String>,
+/// Serves channels over TCP and PTYs until an error occurs.
+fn serve(opts: &Opts, rtt: &mut Rtt, mut recorder: Option<Recorder<File>>) -> i32 {
+    let mut servers = Vec::new();
 
-    #[structopt(short, long, help = "List RTT channels and exit.")]
-    list: bool,
+    for spec in &opts.listen {
+        let (number, up, down) = match take_pair(opts, rtt, spec.channel) {
+            Ok(pair) => pair,
+            Err(code) => return code,
+        };
 
-    #[structopt(
-        short,
-        long,
-        help = "Number of up channel to output. Defaults to 0 if it exists."
-    )]
-    up: Option<usize>,
+        let server =
+            match ChannelServer::bind(&spec.addr, up, down, opts.no_client, opts.buffer_size) {
+                Ok(server) => server,
+                Err(err) => {
+                    eprintln!("Error listening on {}: {}", spec.addr, err);
+                    return 1;
+                }
+            };
 
-    #[structopt(
-        short,
-        long,
-        help = "Number of down channel for keyboard input. Defaults to 0 if it exists."
-    )]
-    down: Option<usize>,
+        eprintln!(
+            "Serving channel {} on {}",
+            number,
+            server
+                .local_addr()
+                .map_or_else(|_| spec.addr.clone(), |addr| addr.to_string())
+        );
 
-    #[structopt(
-        long,
-        default_value="",
-        parse(try_from_str=parse_scan_region),
-        help = "Memory region to scan for control block. You can specify either an exact starting address '0x1000' or a range such as '0x0000..0x1000'. Both decimal and hex are accepted.")]
-    scan_region: ScanRegion,
-}
+        servers.push(server);
+    }
 
-fn main() {
-    pretty_env_logger::init();
+    #[cfg(unix)]
+    let mut ptys = Vec::new();
 
-    std::process::exit(run());
+    #[cfg(unix)]
+    for spec in opts.pty.iter().flat_map(|specs| {
+        // A bare --pty exposes the default channels
+        if specs.is_empty() {
+            vec![PtySpec {
+                channel: None,
+                link: None,
+            }]
+        } else {
+            specs.clone()
+        }
+    }) {
+        let (number, up, down) = match take_pair(opts, rtt, spec.channel) {
+            Ok(pair) => pair,
+            Err(code) => return code,
+        };
+
+        let pty = match ChannelPty::open(spec.link.as_deref(), up, down, opts.buffer_size) {
+            Ok(pty) => pty,
+            Err(err) => {
+                eprintln!("Error creating PTY: {}", err);
+                return 1;
+            }
+        };
+
+        match &spec.link {
+            Some(link) => eprintln!(
+                "Channel {} is available at {} ({})",
+                number,
+                link.display(),
+                pty.path().display()
+            ),
+            None => eprintln!(
+                "Channel {} is available at {}",
+                number,
+                pty.path().display()
+            ),
+        }
+
+        ptys.push(pty);
+    }
+
+    eprintln!("Found control block at 0x{:08x}", rtt.ptr());
+
+    loop {
+        let result = servers.iter_mut().try_fold(0, |moved, server| {
+            server.poll(recorder.as_mut()).map(|count| moved + count)
+        });
+
+        #[cfg(unix)]
+        let result = result.and_then(|moved| {
+            ptys.iter_mut().try_fold(moved, |moved, pty| {
+                pty.poll(recorder.as_mut()).map(|count| moved + count)
+            })
+        });
+
+        match result {
+            Ok(0) => thread::sleep(IDLE_INTERVAL),
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!("\nError transferring RTT data: {}", err);
+                return 1;
+            }
+        }
+    }
 }
 
-fn run() -> i32 {
-    let opts = Opts::from_args();
+/// Runs the interactive interface until the user quits or an error occurs.
+fn tui(
+    opts: &Opts,
+    rtt: &mut Rtt,
+    session: Option<Arc<Mutex<Session>>>,
+    recorder: Option<Recorder<File>>,
+) -> i32 {
+    let numbers: Vec<usize> = rtt
+        .up_channels()
+        .iter()
+        .map(|chan| chan.number())
+        .filter(|&number| !matches!(&opts.up, Some(up) if !up.contains(number)))
+        .collect();
 
-    let probes = Probe::list_all();
+    if let Some(ChannelList::Numbers(selected)) = &opts.up {
+        if let Some(up) = selected.iter().find(|up| !numbers.contains(up)) {
+            eprintln!("Error: up channel {} does not exist.", up);
+            return 1;
+        }
+    }
 
-    if probes.len() == 0 {
-        eprintln!("No debug probes available. Make sure your probe is plugged in, supported and up-to-date.");
+    if numbers.is_empty() {
+        eprintln!("Error: no up channels to show.");
         return 1;
     }
 
-    let probe_number = match opts.probe {
-        ProbeInfo::List => {
-            list_probes(std::io::stdout(), &probes);
-            return 0;
+    let channels = numbers
+        .into_iter()
+        .filter_map(|number| {
+            let up = rtt.up_channels().take(number)?;
+            Some((up, rtt.down_channels().take(number)))
+        })
+        .collect();
+
+    let mut app = ui::App::new(rtt.ptr(), channels, session, opts.line_ending);
+
+    match app.run(recorder) {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            1
+        }
+    }
+}
+
+/// Serves all channels over a Unix domain socket until an error occurs.
+#[cfg(unix)]
+fn daemon(rtt: &mut Rtt, path: &Path) -> i32 {
+    let mut server = match probe_rs_rtt::daemon::Server::bind(path, rtt) {
+        Ok(server) => server,
+        Err(err) => {
+            eprintln!("Error creating socket {}: {}", path.display(), err);
+            return 1;
         }
-        ProbeInfo::Number(i) => i,
     };
 
-    if probe_number >= probes.len() {
-        eprintln!("Probe {} does not exist.", probe_number);
-        list_probes(std::io::stderr(), &probes);
-        return 1;
+    eprintln!("Found control block at 0x{:08x}", rtt.ptr());
+    eprintln!("Serving RTT on {}", path.display());
+
+    loop {
+        match server.poll() {
+            Ok(0) => thread::sleep(IDLE_INTERVAL),
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!("\nError transferring RTT data: {}", err);
+                return 1;
+            }
+        }
     }
+}
 
-    let probe = match probes[probe_number].open() {
-        Ok(probe) => probe,
+/// Prints the up channel data recovered from a RAM dump.
+fn dump(opts: &Opts, path: &Path) -> i32 {
+    let extraction = match RamDump::open(path, opts.dump_address)
+        .and_then(|dump| dump.extract(&opts.scan_region))
+    {
+        Ok(extraction) => extraction,
         Err(err) => {
-            eprintln!("Error opening probe: {}", err);
+            eprintln!("Error extracting RTT data from dump: {}", err);
             return 1;
         }
     };
 
-    let target_selector = opts
-        .chip
-        .clone()
-        .map(|t| TargetSelector::Unspecified(t))
-        .unwrap_or(TargetSelector::Auto);
+    for chan in extraction.channels {
+        if matches!(&opts.up, Some(up) if !up.contains(chan.number)) {
+            continue;
+        }
 
-    let session = match probe.attach(target_selector) {
-        Ok(session) => session,
-        Err(err) => {
-            eprintln!("Error creating debug session: {}", err);
+        let data = if opts.history {
+            &chan.history
+        } else {
+            &chan.unread
+        };
 
-            if opts.chip.is_none() {
-                if let probe_rs::Error::ChipNotFound(_) = err {
-                    eprintln!("Hint: Use '--chip' to specify the target chip type manually");
-                }
-            }
+        eprintln!(
+            "Up channel {} ({}): {} bytes",
+            chan.number,
+            chan.name.as_deref().unwrap_or("no name"),
+            data.len()
+        );
 
+        if let Err(err) = stdout().write_all(data) {
+            eprintln!("Error writing to stdout: {}", err);
             return 1;
         }
+    }
+
+    let mut code = 0;
+
+    for (number, err) in extraction.failed {
+        if matches!(&opts.up, Some(up) if !up.contains(number)) {
+            continue;
+        }
+
+        eprintln!("Error reading up channel {}: {}", number, err);
+        code = 1;
+    }
+
+    code
+}
+
+/// Attaches to RTT through a remote memory backend such as a gdbserver. `add_region` is used to
+/// tell the backend which memory to scan, as it cannot determine the memory map by itself. On
+/// failure, returns the exit code.
+fn attach_remote<M: Memory + 'static>(
+    opts: &Opts,
+    name: &str,
+    memory: Result<M, Error>,
+    add_region: impl FnOnce(M, Region) -> M,
+) -> Result<Rtt, i32> {
+    let memory = match memory {
+        Ok(memory) => memory,
+        Err(err) => {
+            eprintln!("Error connecting to {}: {}", name, err);
+            return Err(1);
+        }
+    };
+
+    let memory = match &opts.scan_region {
+        ScanRegion::Ram => {
+            eprintln!("--scan-region is required when connecting to {}.", name);
+            return Err(1);
+        }
+        ScanRegion::Range(range) => add_region(
+            memory,
+            Region {
+                kind: RegionKind::Ram,
+                range: range.clone(),
+            },
+        ),
+        ScanRegion::Exact(_) => memory,
     };
 
     eprintln!("Attaching to RTT...");
 
-    let mut rtt = match Rtt::attach_region(Arc::new(Mutex::new(session)), &opts.scan_region) {
-        Ok(rtt) => rtt,
+    match Rtt::attach_region(Arc::new(Mutex::new(memory)), &opts.scan_region) {
+        Ok(rtt) => Ok(rtt),
         Err(err) => {
             eprintln!("Error attaching to RTT: {}", err);
-            return 1;
+            Err(1)
         }
-    };
+    }
+}
 
-    if opts.list {
-        println!("Up channels:");
-        list_channels(rtt.up_channels());
+/// An RTT instance attached through a probe.
+struct ProbeAttachment {
+    rtt: Rtt,
 
-        println!("Down channels:");
-        list_channels(rtt.down_channels());
+    /// The session, so that the target can still be controlled.
+    session: Arc<Mutex<Session>>,
 
-        return 0;
+    /// Restores the channel modes changed by --reset.
+    restorer: Option<ModeRestorer>,
+
+    /// Selects the same probe again, by serial number if it has one.
+    probe: ProbeInfo,
+
+    /// Where the control block was found, which may differ from --scan-region after --flash.
+    scan_region: ScanRegion,
+}
+
+/// Opens the probe and attaches to RTT. On failure, returns the exit code.
+fn attach_probe(opts: &Opts) -> Result<ProbeAttachment, i32> {
+    let probes = Probe::list_all();
+
+    if probes.len() == 0 {
+        eprintln!("No debug probes available. Make sure your probe is plugged in, supported and up-to-date.");
+        return Err(1);
     }
 
-    let up_channel = if let Some(up) = opts.up {
-        let chan = rtt.up_channels().take(up);
+    if opts.probe == ProbeInfo::List {
+        list_probes(std::io::stdout(), &probes);
+        return Err(0);
+    }
 
-        if chan.is_none() {
-            eprintln!("Error: up channel {} does not exist.", up);
-            return 1;
+    let probe_number = match opts.probe.find(&probes).as_slice() {
+        [i] => *i,
+        [] => {
+            eprintln!("No probe matches '{}'.", opts.probe);
+            list_probes(std::io::stderr(), &probes);
+            return Err(1);
+        }
+        _ => {
+            eprintln!(
+                "Several probes match '{}', add the serial number to select one.",
+                opts.probe
+            );
+            list_probes(std::io::stderr(), &probes);
+            return Err(1);
         }
-
-        chan
-    } else {
-        rtt.up_channels().take(0)
     };
 
-    let down_channel = if let Some(down) = opts.down {
-        let chan = rtt.down_channels().take(down);
+    // Reconnect to the same probe after it has been unplugged, when its number may have changed
+    let probe = match &probes[probe_number].serial_number {
+        Some(serial) => ProbeInfo::Id(serial.clone()),
+        None => {
+            if opts.reconnect {
+                eprintln!(
+                    "The probe has no serial number, so --reconnect may attach to a different probe with the same USB IDs."
+                );
+            }
 
-        if chan.is_none() {
-            eprintln!("Error: up channel {} does not exist.", down);
-            return 1;
+            ProbeInfo::Usb {
+                vendor_id: probes[probe_number].vendor_id,
+                product_id: probes[probe_number].product_id,
+                serial_number: None,
+            }
         }
+    };
 
-        chan
-    } else {
-        rtt.down_channels().take(0)
+    let mut session = match open_session(opts, &probes[probe_number]) {
+        Ok(session) => session,
+        Err(err) => {
+            eprintln!("{}", err);
+            return Err(1);
+        }
     };
 
-    let stdin = down_channel.as_ref().map(|_| stdin_channel());
+    let mut scan_region = opts.scan_region.clone();
 
-    eprintln!("Found control block at 0x{:08x}", rtt.ptr());
+    if let Some(path) = &opts.flash {
+        eprintln!("Flashing {}...", path.display());
 
-    let mut up_buf = [0u8; 1024];
-    let mut down_buf = vec![];
+        if let Err(err) = flashing::download_file(&mut session, path, flashing::Format::Elf) {
+            eprintln!("Error flashing {}: {}", path.display(), err);
+            return Err(1);
+        }
 
-    loop {
-        if let Some(up_channel) = up_channel.as_ref() {
-            let count = match up_channel.read(up_buf.as_mut()) {
-                Ok(count) => count,
+        // Use the address of the control block unless told where to look
+        if matches!(scan_region, ScanRegion::Ram) {
+            match flash::rtt_symbol(path) {
+                Ok(Some(address)) => scan_region = ScanRegion::Exact(address),
+                Ok(None) => eprintln!("No _SEGGER_RTT symbol in ELF file, scanning RAM."),
                 Err(err) => {
-                    eprintln!("\nError reading from RTT: {}", err);
-                    return 1;
+                    eprintln!("Error reading symbols from {}: {}", path.display(), err);
+                    return Err(1);
                 }
-            };
+            }
+        }
+    }
+
+    let session = Arc::new(Mutex::new(session));
 
-            match stdout().write_all(&up_buf[..count]) {
-                Ok(_) => {
-                    stdout().flush().ok();
+    let mut attachment = ProbeAttachment {
+        rtt: if opts.flash.is_none() && !opts.reset && !opts.reset_halt {
+            eprintln!("Attaching to RTT...");
+
+            match Rtt::attach_region(session.clone(), &scan_region) {
+                Ok(rtt) => rtt,
+                Err(err) => {
+                    eprintln!("Error attaching to RTT: {}", err);
+                    return Err(1);
                 }
+            }
+        } else {
+            eprintln!("Resetting target and attaching to RTT...");
+
+            match reset::attach_after_reset(&session, &scan_region, opts.reset_halt) {
+                Ok(rtt) => rtt,
                 Err(err) => {
-                    eprintln!("Error writing to stdout: {}", err);
-                    return 1;
+                    eprintln!("Error attaching to RTT after reset: {}", err);
+                    return Err(1);
                 }
             }
+        },
+        session,
+        restorer: None,
+        probe,
+        scan_region,
+    };
+
+    if opts.reset || opts.reset_halt {
+        match ModeRestorer::block_if_full(attachment.session.clone(), &mut attachment.rtt) {
+            Ok(restorer) => attachment.restorer = Some(restorer),
+            Err(err) => {
+                eprintln!("Error changing channel modes: {}", err);
+                return Err(1);
+            }
         }
+    }
 
-        if let (Some(down_channel), Some(stdin)) = (down_channel.as_ref(), &stdin) {
-            if let Ok(bytes) = stdin.try_recv() {
-                down_buf.extend_from_slice(bytes.as_slice());
+    Ok(attachment)
+}
+
+/// Opens a probe with the configured protocol and speed and attaches to the target. On failure,
+/// returns the error message.
+fn open_session(opts: &Opts, info: &DebugProbeInfo) -> Result<Session, String> {
+    let mut probe = info
+        .open()
+        .map_err(|err| format!("Error opening probe: {}", err))?;
+
+    if let Some(protocol) = opts.protocol {
+        probe
+            .select_protocol(protocol)
+            .map_err(|err| format!("Error selecting protocol {:?}: {}", protocol, err))?;
+    }
+
+    if let Some(speed) = opts.speed {
+        let actual = probe
+            .set_speed(speed)
+            .map_err(|err| format!("Error setting speed: {}", err))?;
+
+        if actual != speed {
+            eprintln!(
+                "Probe does not support {} kHz, using {} kHz.",
+                speed, actual
+            );
+        }
+    }
+
+    let target_selector = opts
+        .chip
+        .clone()
+        .map(|t| TargetSelector::Unspecified(t))
+        .unwrap_or(TargetSelector::Auto);
+
+    let session = if opts.connect_under_reset {
+        probe.attach_under_reset(target_selector)
+    } else {
+        probe.attach(target_selector)
+    };
+
+    session.map_err(|err| {
+        let mut message = format!("Error creating debug session: {}", err);
+
+        if opts.chip.is_none() {
+            if let probe_rs::Error::ChipNotFound(_) = err {
+                message.push_str("\nHint: Use '--chip' to specify the target chip type manually");
             }
+        }
 
-            if !down_buf.is_empty() {
-                let count = match down_channel.write(down_buf.as_mut()) {
-                    Ok(count) => count,
-                    Err(err) => {
-                        eprintln!("\nError writing to RTT: {}", err);
-                        return 1;
-                    }
-                };
+        message
+    })
+}
 
-                if count > 0 {
-                    down_buf.drain(..count);
-                }
+/// How often to look for the probe and target while reconnecting.
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Waits until the probe is plugged in and the target is powered again, and attaches to RTT.
+/// Errors are printed once each, as the same one is usually repeated until the target is back.
+/// Returns `None` if the deadline passes first.
+fn reconnect(
+    opts: &Opts,
+    probe: &ProbeInfo,
+    scan_region: &ScanRegion,
+    deadline: Option<Instant>,
+) -> Option<(Rtt, Arc<Mutex<Session>>)> {
+    let mut last_error = String::new();
+
+    loop {
+        thread::sleep(RECONNECT_INTERVAL);
+
+        if matches!(deadline, Some(deadline) if Instant::now() >= deadline) {
+            return None;
+        }
+
+        let probes = Probe::list_all();
+
+        let info = match probe.find(&probes).as_slice() {
+            [i] => &probes[*i],
+            _ => continue,
+        };
+
+        let result = open_session(opts, info).and_then(|session| {
+            let session = Arc::new(Mutex::new(session));
+
+            Rtt::attach_region(session.clone(), scan_region)
+                .map(|rtt| (rtt, session))
+                .map_err(|err| format!("Error attaching to RTT: {}", err))
+        });
+
+        match result {
+            Ok(attachment) => return Some(attachment),
+            Err(err) if err != last_error => {
+                eprintln!("{}", err);
+                last_error = err;
             }
+            Err(_) => {}
         }
     }
 }
@@ -260,9 +1784,11 @@ fn list_probes(mut stream: impl std::io::Write, probes: &Vec<DebugProbeInfo>) {
     for (i, probe) in probes.iter().enumerate() {
         writeln!(
             stream,
-            "  {}: {} {}",
+            "  {}: {} {:04x}:{:04x} {}",
             i,
             probe.identifier,
+            probe.vendor_id,
+            probe.product_id,
             probe
                 .serial_number
                 .as_ref()
@@ -289,24 +1815,67 @@ fn list_channels(channels: &Channels<impl RttChannel>) {
     }
 }
 
-fn stdin_channel() -> Receiver<Vec<u8>> {
-    let (tx, rx) = channel();
+/// Prints a JSON object for each channel on its own line.
+fn list_channels_json(rtt: &mut Rtt) -> i32 {
+    let mut channels: Vec<_> = rtt
+        .up_channels()
+        .iter()
+        .map(|chan| {
+            serde_json::json!({
+                "direction": "up",
+                "number": chan.number(),
+                "name": chan.name(),
+                "buffer_size": chan.buffer_size(),
+                "mode": chan.mode().ok().map(|mode| format!("{:?}", mode)),
+            })
+        })
+        .collect();
 
-    thread::spawn(move || {
-        let mut buf = [0u8; 1024];
+    // Down channels have no mode, as the host never blocks
+    channels.extend(rtt.down_channels().iter().map(|chan| {
+        serde_json::json!({
+            "direction": "down",
+            "number": chan.number(),
+            "name": chan.name(),
+            "buffer_size": chan.buffer_size(),
+            "mode": null,
+        })
+    }));
 
-        loop {
-            match stdin().read(&mut buf[..]) {
-                Ok(count) => {
-                    tx.send(buf[..count].to_vec()).unwrap();
-                }
-                Err(err) => {
-                    eprintln!("Error reading from stdin, input disabled: {}", err);
-                    break;
-                }
-            }
+    for chan in channels {
+        println!("{}", chan);
+    }
+
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn option(name: &str, arg: &str) -> config::ConfigOption {
+        config::ConfigOption {
+            name: name.into(),
+            args: vec![arg.into()],
         }
-    });
+    }
+
+    #[test]
+    fn skips_config_options_conflicting_with_command_line() {
+        let args = vec![OsString::from("rtthost"), OsString::from("--reset-halt")];
+        let options = vec![option("reset", "--reset"), option("list", "--list")];
+
+        let merged = merge_config(args, options);
 
-    rx
+        assert_eq!(merged, ["rtthost", "--reset-halt", "--list"]);
+        assert!(Opts::clap().get_matches_from_safe(merged).is_ok());
+    }
+
+    #[test]
+    fn keeps_config_options_not_given_on_command_line() {
+        let args = vec![OsString::from("rtthost")];
+        let options = vec![option("reset", "--reset")];
+
+        assert_eq!(merge_config(args, options), ["rtthost", "--reset"]);
+    }
 }
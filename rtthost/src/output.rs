@@ -0,0 +1,685 @@
+//! Interleaving the output of several up channels on the terminal.
+
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use serde_json::json;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// How to format the output of up channels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// The raw data, interleaved at line boundaries.
+    Text,
+
+    /// A JSON object per line.
+    Jsonl,
+}
+
+impl std::str::FromStr for Format {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Format, &'static str> {
+        match s {
+            "text" => Ok(Format::Text),
+            "jsonl" => Ok(Format::Jsonl),
+            _ => Err("Invalid format, expected 'text' or 'jsonl'."),
+        }
+    }
+}
+
+/// Which time to add to the start of each line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timestamps {
+    /// The local wall-clock time.
+    Absolute,
+
+    /// The time since attaching, in seconds.
+    Relative,
+
+    /// The time since the previous line of the same channel started, in seconds.
+    Delta,
+}
+
+impl std::str::FromStr for Timestamps {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Timestamps, &'static str> {
+        match s {
+            "absolute" => Ok(Timestamps::Absolute),
+            "relative" => Ok(Timestamps::Relative),
+            "delta" => Ok(Timestamps::Delta),
+            _ => Err("Invalid timestamps, expected 'absolute', 'relative' or 'delta'."),
+        }
+    }
+}
+
+/// Adds timestamps to the start of each line of several channels.
+///
+/// Lines are stamped with the time of the poll that delivered their first byte, rather than the
+/// time they are written out, so that interleaving and partial lines do not skew the timing.
+pub struct Stamper {
+    mode: Timestamps,
+    start: Instant,
+    start_wall: DateTime<Local>,
+    line_starts: Vec<Option<Instant>>,
+    at_line_start: Vec<bool>,
+}
+
+impl Stamper {
+    pub fn new(mode: Timestamps, channels: usize) -> Stamper {
+        Stamper {
+            mode,
+            start: Instant::now(),
+            start_wall: Local::now(),
+            line_starts: vec![None; channels],
+            at_line_start: vec![true; channels],
+        }
+    }
+
+    /// Returns the data read from the channel with the specified index by a poll at the
+    /// specified time, with a timestamp inserted at the start of each line.
+    pub fn stamp(&mut self, index: usize, data: &[u8], polled: Instant) -> Vec<u8> {
+        let mut stamped = Vec::with_capacity(data.len());
+
+        for line in data.split_inclusive(|&b| b == b'\n') {
+            if self.at_line_start[index] {
+                let stamp = self.format(index, polled);
+                stamped.extend_from_slice(stamp.as_bytes());
+            }
+
+            stamped.extend_from_slice(line);
+            self.at_line_start[index] = line.ends_with(b"\n");
+        }
+
+        stamped
+    }
+
+    fn format(&mut self, index: usize, polled: Instant) -> String {
+        let since_start = polled.saturating_duration_since(self.start);
+
+        match self.mode {
+            Timestamps::Absolute => {
+                let time = self.start_wall
+                    + chrono::Duration::from_std(since_start)
+                        .unwrap_or_else(|_| chrono::Duration::zero());
+                format!("{} ", time.format("%Y-%m-%d %H:%M:%S%.6f"))
+            }
+            Timestamps::Relative => format!("{:12.6} ", since_start.as_secs_f64()),
+            Timestamps::Delta => {
+                let previous = self.line_starts[index].replace(polled);
+                let delta = previous.map_or(Duration::from_secs(0), |previous| {
+                    polled.saturating_duration_since(previous)
+                });
+                format!("+{:.6} ", delta.as_secs_f64())
+            }
+        }
+    }
+}
+
+/// Output for the data of several up channels, identified by their index.
+pub trait ChannelOutput {
+    /// Writes data from the channel with the specified index.
+    fn write(&mut self, index: usize, data: &[u8]) -> io::Result<()>;
+
+    /// Writes data held back for longer than the stall timeout, if any. Should be called
+    /// regularly.
+    fn poll(&mut self) -> io::Result<()>;
+
+    /// Writes all data held back, such as before exiting.
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// What to print at the start of each line to identify the channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Prefix {
+    /// The channel number if more than one channel is shown, otherwise nothing.
+    Auto,
+
+    /// Nothing.
+    None,
+
+    /// The channel number.
+    Number,
+
+    /// The channel name, or the number if the channel has no name.
+    Name,
+}
+
+impl std::str::FromStr for Prefix {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Prefix, &'static str> {
+        match s {
+            "auto" => Ok(Prefix::Auto),
+            "none" => Ok(Prefix::None),
+            "number" => Ok(Prefix::Number),
+            "name" => Ok(Prefix::Name),
+            _ => Err("Invalid prefix, expected 'auto', 'none', 'number' or 'name'."),
+        }
+    }
+}
+
+/// When to color the output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// If stdout is a terminal.
+    Auto,
+    Always,
+    Never,
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<ColorChoice, &'static str> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err("Invalid color choice, expected 'auto', 'always' or 'never'."),
+        }
+    }
+}
+
+impl ColorChoice {
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => atty::is(atty::Stream::Stdout),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+const COLOR_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+/// An ANSI terminal color, parsed from a name such as `red` or `bright-red`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Color(u8);
+
+impl Color {
+    /// Colors assigned to channels in order unless specified otherwise.
+    pub const DEFAULTS: [Color; 6] = [
+        Color(36), // cyan
+        Color(33), // yellow
+        Color(32), // green
+        Color(35), // magenta
+        Color(34), // blue
+        Color(31), // red
+    ];
+}
+
+impl std::str::FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Color, String> {
+        let (name, base) = match s.strip_prefix("bright-") {
+            Some(name) => (name, 90),
+            None => (s, 30),
+        };
+
+        COLOR_NAMES
+            .iter()
+            .position(|&n| n == name)
+            .map(|i| Color(base + i as u8))
+            .ok_or_else(|| format!("Invalid color '{}'.", s))
+    }
+}
+
+/// Formats a line prefix for a channel label.
+pub fn format_prefix(label: &str, color: Option<Color>) -> String {
+    match color {
+        Some(Color(code)) => format!("\x1b[{}m[{}]\x1b[0m ", code, label),
+        None => format!("[{}] ", label),
+    }
+}
+
+/// Most data held back for a channel, or in a partial line, before a partial line is written out
+/// regardless of the stall timeout.
+pub const MAX_HELD_BACK: usize = 1024 * 1024;
+
+/// Writes the output of several channels, interleaving them only at line boundaries.
+///
+/// Partial lines are written immediately, so that e.g. prompts are visible. Other channels then
+/// wait until the line is finished. With a stall timeout, a line that stays unfinished for longer
+/// than that while others are waiting is broken so that they can continue. It is also broken once
+/// another channel has [`MAX_HELD_BACK`] bytes waiting, so that memory use stays bounded.
+pub struct Output<W: Write> {
+    writer: W,
+    prefixes: Vec<String>,
+    stall_timeout: Option<Duration>,
+    pending: Vec<Vec<u8>>,
+    open: Option<usize>,
+    open_since: Instant,
+}
+
+impl<W: Write> Output<W> {
+    /// Creates an output for channels with the specified line prefixes, which may be empty.
+    pub fn new(writer: W, prefixes: Vec<String>, stall_timeout: Option<Duration>) -> Output<W> {
+        Output {
+            writer,
+            pending: vec![Vec::new(); prefixes.len()],
+            prefixes,
+            stall_timeout,
+            open: None,
+            open_since: Instant::now(),
+        }
+    }
+
+    /// Writes pending data of the channel owning the current line, followed by any other channels
+    /// as long as the current line is finished.
+    fn drain(&mut self) -> io::Result<()> {
+        let order = self.open.into_iter().chain(0..self.pending.len());
+
+        for index in order {
+            if matches!(self.open, Some(open) if open != index) {
+                break;
+            }
+
+            if self.pending[index].is_empty() {
+                continue;
+            }
+
+            let data = std::mem::take(&mut self.pending[index]);
+            let mut at_line_start = self.open.is_none();
+
+            for line in data.split_inclusive(|&b| b == b'\n') {
+                if at_line_start {
+                    self.writer.write_all(self.prefixes[index].as_bytes())?;
+                }
+
+                self.writer.write_all(line)?;
+                at_line_start = line.ends_with(b"\n");
+            }
+
+            if at_line_start {
+                self.open = None;
+            } else {
+                self.open = Some(index);
+                self.open_since = Instant::now();
+            }
+        }
+
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> ChannelOutput for Output<W> {
+    fn write(&mut self, index: usize, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        self.pending[index].extend_from_slice(data);
+
+        let held_back = matches!(self.open, Some(open) if open != index);
+
+        if held_back && self.pending[index].len() > MAX_HELD_BACK {
+            self.writer.write_all(b"\n")?;
+            self.open = None;
+        }
+
+        self.drain()
+    }
+
+    /// Breaks a stalled partial line if other channels are waiting.
+    fn poll(&mut self) -> io::Result<()> {
+        if let (Some(open), Some(timeout)) = (self.open, self.stall_timeout) {
+            let waiting = self
+                .pending
+                .iter()
+                .enumerate()
+                .any(|(i, p)| i != open && !p.is_empty());
+
+            if waiting && self.open_since.elapsed() >= timeout {
+                self.writer.write_all(b"\n")?;
+                self.open = None;
+
+                self.drain()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        while self.pending.iter().any(|p| !p.is_empty()) {
+            // Stop waiting for the current line to be finished
+            if self.open.take().is_some() {
+                self.writer.write_all(b"\n")?;
+            }
+
+            self.drain()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A line being received from a channel, along with the time it started.
+struct Line {
+    data: Vec<u8>,
+    started: DateTime<Utc>,
+    started_at: Instant,
+}
+
+/// Writes each line of output as a JSON object with the host time at which the line started, the
+/// channel number and name, and either the `text` or, if it is not valid UTF-8, the base64 encoded
+/// `data`. The line terminator is not included.
+///
+/// With a stall timeout, lines that stay unfinished for longer than that are written as they are,
+/// so that e.g. prompts are not held back. Otherwise they are only written once finished, or once
+/// they are longer than [`MAX_HELD_BACK`].
+pub struct JsonLines<W: Write> {
+    writer: W,
+    channels: Vec<(usize, Option<String>)>,
+    stall_timeout: Option<Duration>,
+    lines: Vec<Option<Line>>,
+}
+
+impl<W: Write> JsonLines<W> {
+    /// Creates an output for channels with the specified numbers and names.
+    pub fn new(
+        writer: W,
+        channels: Vec<(usize, Option<String>)>,
+        stall_timeout: Option<Duration>,
+    ) -> JsonLines<W> {
+        JsonLines {
+            writer,
+            lines: channels.iter().map(|_| None).collect(),
+            channels,
+            stall_timeout,
+        }
+    }
+
+    fn write_line(&mut self, index: usize) -> io::Result<()> {
+        let line = match self.lines[index].take() {
+            Some(line) => line,
+            None => return Ok(()),
+        };
+
+        let mut data = line.data.as_slice();
+        data = data.strip_suffix(b"\n").unwrap_or(data);
+        data = data.strip_suffix(b"\r").unwrap_or(data);
+
+        let (number, name) = &self.channels[index];
+
+        let mut object = json!({
+            "timestamp": line.started.to_rfc3339_opts(SecondsFormat::Micros, true),
+            "channel": number,
+            "name": name,
+        });
+
+        match std::str::from_utf8(data) {
+            Ok(text) => object["text"] = json!(text),
+            Err(_) => object["data"] = json!(base64::encode(data)),
+        }
+
+        serde_json::to_writer(&mut self.writer, &object)?;
+        self.writer.write_all(b"\n")
+    }
+}
+
+impl<W: Write> ChannelOutput for JsonLines<W> {
+    fn write(&mut self, index: usize, data: &[u8]) -> io::Result<()> {
+        for part in data.split_inclusive(|&b| b == b'\n') {
+            self.lines[index]
+                .get_or_insert_with(|| Line {
+                    data: Vec::new(),
+                    started: Utc::now(),
+                    started_at: Instant::now(),
+                })
+                .data
+                .extend_from_slice(part);
+
+            if part.ends_with(b"\n")
+                || matches!(&self.lines[index], Some(line) if line.data.len() > MAX_HELD_BACK)
+            {
+                self.write_line(index)?;
+            }
+        }
+
+        self.writer.flush()
+    }
+
+    /// Writes stalled partial lines.
+    fn poll(&mut self) -> io::Result<()> {
+        let timeout = match self.stall_timeout {
+            Some(timeout) => timeout,
+            None => return Ok(()),
+        };
+
+        for index in 0..self.lines.len() {
+            if matches!(&self.lines[index], Some(line) if line.started_at.elapsed() >= timeout) {
+                self.write_line(index)?;
+                self.writer.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        for index in 0..self.lines.len() {
+            self.write_line(index)?;
+        }
+
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn output(stall_timeout: Option<Duration>) -> Output<Vec<u8>> {
+        let prefixes = vec![String::from("[0] "), String::from("[1] ")];
+        Output::new(Vec::new(), prefixes, stall_timeout)
+    }
+
+    fn written(output: &Output<Vec<u8>>) -> &str {
+        std::str::from_utf8(&output.writer).unwrap()
+    }
+
+    #[test]
+    fn interleaves_at_line_boundaries() {
+        let mut out = output(None);
+
+        out.write(0, b"a").unwrap();
+        out.write(1, b"b\n").unwrap();
+        out.write(0, b"c\nd\n").unwrap();
+
+        assert_eq!(written(&out), "[0] ac\n[0] d\n[1] b\n");
+    }
+
+    #[test]
+    fn holds_back_other_channels_without_stall_timeout() {
+        let mut out = output(None);
+
+        out.write(0, b"prompt> ").unwrap();
+        out.write(1, b"line\n").unwrap();
+        out.poll().unwrap();
+
+        assert_eq!(written(&out), "[0] prompt> ");
+
+        out.write(0, b"input\n").unwrap();
+
+        assert_eq!(written(&out), "[0] prompt> input\n[1] line\n");
+    }
+
+    #[test]
+    fn breaks_stalled_line_with_stall_timeout() {
+        let mut out = output(Some(Duration::from_secs(0)));
+
+        out.write(0, b"prompt> ").unwrap();
+        out.poll().unwrap();
+
+        // Nothing is waiting, so the line is left open
+        assert_eq!(written(&out), "[0] prompt> ");
+
+        out.write(1, b"line\n").unwrap();
+        out.poll().unwrap();
+
+        assert_eq!(written(&out), "[0] prompt> \n[1] line\n");
+    }
+
+    #[test]
+    fn breaks_line_once_too_much_is_held_back() {
+        let mut out = output(None);
+        let line = vec![b'x'; MAX_HELD_BACK];
+
+        out.write(0, b"prompt> ").unwrap();
+        out.write(1, &line).unwrap();
+
+        assert_eq!(written(&out), "[0] prompt> ");
+
+        out.write(1, b"x").unwrap();
+
+        let expected = format!("[0] prompt> \n[1] {}x", "x".repeat(MAX_HELD_BACK));
+        assert_eq!(written(&out), expected);
+        assert!(out.pending.iter().all(Vec::is_empty));
+    }
+
+    #[test]
+    fn continues_long_line_of_same_channel() {
+        let mut out = output(None);
+
+        out.write(0, &vec![b'x'; MAX_HELD_BACK]).unwrap();
+        out.write(0, b"xx\n").unwrap();
+
+        let expected = format!("[0] {}xx\n", "x".repeat(MAX_HELD_BACK));
+        assert_eq!(written(&out), expected);
+    }
+
+    #[test]
+    fn finish_writes_pending_data() {
+        let mut out = output(None);
+
+        out.write(0, b"partial").unwrap();
+        out.write(1, b"one\ntwo").unwrap();
+        out.finish().unwrap();
+
+        assert_eq!(written(&out), "[0] partial\n[1] one\n[1] two");
+    }
+
+    fn json_lines(stall_timeout: Option<Duration>) -> JsonLines<Vec<u8>> {
+        let channels = vec![(0, Some(String::from("Terminal"))), (2, None)];
+        JsonLines::new(Vec::new(), channels, stall_timeout)
+    }
+
+    fn objects(out: &JsonLines<Vec<u8>>) -> Vec<Value> {
+        std::str::from_utf8(&out.writer)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn writes_json_object_per_line() {
+        let mut out = json_lines(None);
+
+        out.write(0, b"hel").unwrap();
+        out.write(1, b"\xff\xfe\r\n").unwrap();
+        out.write(0, b"lo\r\n").unwrap();
+
+        let objects = objects(&out);
+        assert_eq!(objects.len(), 2);
+
+        assert_eq!(objects[0]["channel"], 2);
+        assert_eq!(objects[0]["name"], Value::Null);
+        assert_eq!(objects[0]["data"], base64::encode(b"\xff\xfe"));
+        assert!(objects[0].get("text").is_none());
+
+        assert_eq!(objects[1]["channel"], 0);
+        assert_eq!(objects[1]["name"], "Terminal");
+        assert_eq!(objects[1]["text"], "hello");
+        assert!(objects[1]["timestamp"].is_string());
+    }
+
+    #[test]
+    fn keeps_partial_json_lines_without_stall_timeout() {
+        let mut out = json_lines(None);
+
+        out.write(0, b"prompt> ").unwrap();
+        out.poll().unwrap();
+
+        assert!(objects(&out).is_empty());
+
+        out.finish().unwrap();
+
+        let objects = objects(&out);
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0]["text"], "prompt> ");
+    }
+
+    #[test]
+    fn writes_overlong_json_lines() {
+        let mut out = json_lines(None);
+
+        out.write(0, &vec![b'x'; MAX_HELD_BACK + 1]).unwrap();
+        out.write(0, b"rest\n").unwrap();
+
+        let texts: Vec<Value> = objects(&out)
+            .into_iter()
+            .map(|o| o["text"].clone())
+            .collect();
+        assert_eq!(texts, ["x".repeat(MAX_HELD_BACK + 1), String::from("rest")]);
+    }
+
+    #[test]
+    fn writes_stalled_json_lines_with_stall_timeout() {
+        let mut out = json_lines(Some(Duration::from_secs(0)));
+
+        out.write(0, b"prompt> ").unwrap();
+        out.poll().unwrap();
+        out.write(0, b"input\n").unwrap();
+
+        let texts: Vec<Value> = objects(&out)
+            .into_iter()
+            .map(|o| o["text"].clone())
+            .collect();
+        assert_eq!(texts, ["prompt> ", "input"]);
+    }
+
+    #[test]
+    fn stamps_line_starts_across_writes() {
+        let mut stamper = Stamper::new(Timestamps::Relative, 2);
+        let at = |ms| stamper.start + Duration::from_millis(ms);
+        let (first, second) = (at(1500), at(2250));
+
+        assert_eq!(
+            stamper.stamp(0, b"a\nb", first),
+            b"    1.500000 a\n    1.500000 b".to_vec()
+        );
+        assert_eq!(
+            stamper.stamp(1, b"c\n", first),
+            b"    1.500000 c\n".to_vec()
+        );
+        assert_eq!(
+            stamper.stamp(0, b"c\nd\n", second),
+            b"c\n    2.250000 d\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn stamps_delta_per_channel() {
+        let mut stamper = Stamper::new(Timestamps::Delta, 2);
+        let start = stamper.start;
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert_eq!(stamper.stamp(0, b"a\n", at(100)), b"+0.000000 a\n".to_vec());
+        assert_eq!(stamper.stamp(1, b"b\n", at(200)), b"+0.000000 b\n".to_vec());
+        assert_eq!(
+            stamper.stamp(0, b"c\nd", at(350)),
+            b"+0.250000 c\n+0.000000 d".to_vec()
+        );
+        assert_eq!(
+            stamper.stamp(0, b"\ne\n", at(500)),
+            b"\n+0.150000 e\n".to_vec()
+        );
+    }
+}
@@ -0,0 +1,233 @@
+//! Pseudo-terminals for use with serial port tools such as minicom or pyserial.
+
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::pty::openpty;
+use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg};
+use nix::unistd::{close, read, ttyname, write};
+use probe_rs_rtt::capture::Recorder;
+use probe_rs_rtt::{DownChannel, Error, UpChannel};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+
+/// The channel number to expose and the path of a symlink to create for the PTY, parsed from
+/// `[CHANNEL@]LINK`, `CHANNEL` or `LINK`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PtySpec {
+    pub channel: Option<usize>,
+    pub link: Option<PathBuf>,
+}
+
+impl std::str::FromStr for PtySpec {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<PtySpec, &'static str> {
+        let (channel, link) = match s.find('@') {
+            Some(i) => (
+                Some(
+                    s[..i]
+                        .parse()
+                        .map_err(|_| "Invalid channel number in PTY specification.")?,
+                ),
+                &s[i + 1..],
+            ),
+            None => match s.parse() {
+                Ok(channel) => (Some(channel), ""),
+                Err(_) => (None, s),
+            },
+        };
+
+        Ok(PtySpec {
+            channel,
+            link: if link.is_empty() {
+                None
+            } else {
+                Some(PathBuf::from(link))
+            },
+        })
+    }
+}
+
+/// Exposes an up/down channel pair as a pseudo-terminal.
+///
+/// The slave side is kept open so that the terminal settings persist and data is queued while no
+/// program has it open, like with a real serial port.
+pub struct ChannelPty {
+    master: RawFd,
+    slave: RawFd,
+    path: PathBuf,
+    link: Option<PathBuf>,
+    up: Option<UpChannel>,
+    down: Option<DownChannel>,
+    buffer_size: usize,
+    pending: VecDeque<u8>,
+    down_buf: Vec<u8>,
+}
+
+impl ChannelPty {
+    pub fn open(
+        link: Option<&Path>,
+        up: Option<UpChannel>,
+        down: Option<DownChannel>,
+        buffer_size: usize,
+    ) -> io::Result<ChannelPty> {
+        let pty = openpty(None, None).map_err(to_io_error)?;
+
+        let mut pty = ChannelPty {
+            master: pty.master,
+            slave: pty.slave,
+            path: PathBuf::new(),
+            link: None,
+            up,
+            down,
+            buffer_size,
+            pending: VecDeque::new(),
+            down_buf: Vec::new(),
+        };
+
+        // Pass data through unmodified unless the program opening the PTY configures it otherwise
+        let mut termios = tcgetattr(pty.slave).map_err(to_io_error)?;
+        cfmakeraw(&mut termios);
+        tcsetattr(pty.slave, SetArg::TCSANOW, &termios).map_err(to_io_error)?;
+
+        fcntl(pty.master, FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).map_err(to_io_error)?;
+
+        pty.path = ttyname(pty.slave).map_err(to_io_error)?;
+
+        if let Some(link) = link {
+            // Replace links left behind by a previous run, but never anything else
+            if fs::symlink_metadata(link)
+                .map(|m| m.file_type().is_symlink())
+                .unwrap_or(false)
+            {
+                fs::remove_file(link)?;
+            }
+
+            std::os::unix::fs::symlink(&pty.path, link)?;
+            pty.link = Some(link.to_path_buf());
+        }
+
+        Ok(pty)
+    }
+
+    /// Returns the path of the slave side of the PTY.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Transfers data in both directions without blocking. Returns the number of bytes moved,
+    /// which is zero if there was nothing to do.
+    pub fn poll(&mut self, mut recorder: Option<&mut Recorder<File>>) -> Result<usize, Error> {
+        let mut buf = [0u8; 1024];
+        let mut moved = 0;
+
+        if let Some(up) = self.up.as_ref() {
+            let count = up.read(&mut buf)?;
+            moved += count;
+
+            if let Some(recorder) = recorder.as_mut() {
+                recorder.record_up(up, &buf[..count])?;
+            }
+
+            self.pending.extend(&buf[..count]);
+        }
+
+        while !self.pending.is_empty() {
+            let (data, _) = self.pending.as_slices();
+
+            match write(self.master, data) {
+                Ok(count) => {
+                    self.pending.drain(..count);
+                    moved += count;
+                }
+                Err(err) if err.as_errno() == Some(Errno::EAGAIN) => break,
+                Err(err) => return Err(to_io_error(err).into()),
+            }
+        }
+
+        // Nobody is reading from the PTY, so discard the oldest data like a serial port would
+        let excess = self.pending.len().saturating_sub(self.buffer_size);
+        self.pending.drain(..excess);
+
+        if let Some(down) = self.down.as_ref() {
+            // Leave input in the PTY while the channel is full, so that the kernel applies
+            // backpressure to the writing program
+            if self.down_buf.is_empty() {
+                match read(self.master, &mut buf) {
+                    Ok(count) => {
+                        self.down_buf.extend_from_slice(&buf[..count]);
+                        moved += count;
+                    }
+                    Err(err) if err.as_errno() == Some(Errno::EAGAIN) => {}
+                    Err(err) => return Err(to_io_error(err).into()),
+                }
+            }
+
+            if !self.down_buf.is_empty() {
+                let count = down.write(&self.down_buf)?;
+                moved += count;
+
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.record_down(down, &self.down_buf[..count])?;
+                }
+
+                self.down_buf.drain(..count);
+            }
+        }
+
+        Ok(moved)
+    }
+}
+
+impl Drop for ChannelPty {
+    fn drop(&mut self) {
+        if let Some(link) = &self.link {
+            fs::remove_file(link).ok();
+        }
+
+        close(self.slave).ok();
+        close(self.master).ok();
+    }
+}
+
+pub fn to_io_error(err: nix::Error) -> io::Error {
+    match err.as_errno() {
+        Some(errno) => io::Error::from_raw_os_error(errno as i32),
+        // The remaining errors are caused by invalid paths
+        None => io::Error::new(io::ErrorKind::InvalidInput, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(channel: Option<usize>, link: Option<&str>) -> PtySpec {
+        PtySpec {
+            channel,
+            link: link.map(PathBuf::from),
+        }
+    }
+
+    #[test]
+    fn parses_pty_specs() {
+        assert_eq!("1".parse(), Ok(spec(Some(1), None)));
+        assert_eq!("/tmp/ttyRTT".parse(), Ok(spec(None, Some("/tmp/ttyRTT"))));
+        assert_eq!(
+            "2@/tmp/ttyRTT2".parse(),
+            Ok(spec(Some(2), Some("/tmp/ttyRTT2")))
+        );
+        assert_eq!("3@".parse(), Ok(spec(Some(3), None)));
+
+        // Only a bare number is a channel, anything else is a link path
+        assert_eq!("ttyRTT0".parse(), Ok(spec(None, Some("ttyRTT0"))));
+        assert_eq!("-1".parse(), Ok(spec(None, Some("-1"))));
+
+        assert!("x@/tmp/ttyRTT".parse::<PtySpec>().is_err());
+        assert!("-1@/tmp/ttyRTT".parse::<PtySpec>().is_err());
+        assert!("@/tmp/ttyRTT".parse::<PtySpec>().is_err());
+    }
+}
@@ -0,0 +1,214 @@
+//! Resetting the target and attaching early enough to capture the first output.
+
+use probe_rs::Session;
+use probe_rs_rtt::{ChannelMode, Error, Memory, Rtt, ScanRegion};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long the core may run before the firmware has created the control block after a reset.
+const ATTACH_TIMEOUT: Duration = Duration::from_secs(10);
+
+const HALT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Shortest time the core runs between scans with `halt` set.
+const MIN_BURST: Duration = Duration::from_millis(1);
+
+/// How often to scan the whole region while waiting for the control block to be recreated at the
+/// address of the previous one, in case the firmware has moved it.
+const FULL_SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Size of the ID at the start of the control block.
+const ID_SIZE: usize = 16;
+
+/// Resets the target and attaches to RTT as soon as the firmware has created the control block.
+///
+/// If `halt` is set, the core only runs in short bursts until the control block is found, and
+/// stays halted until [`ModeRestorer::block_if_full`] has been called, so that nothing can be
+/// written before. Bursts are lengthened to the time a scan takes so that slow scans do not
+/// starve the firmware, and the timeout applies to the time the core has run. Otherwise the core
+/// keeps running while attaching.
+pub fn attach_after_reset(
+    session: &Arc<Mutex<Session>>,
+    scan_region: &ScanRegion,
+    halt: bool,
+) -> Result<Rtt, Error> {
+    session
+        .lock()
+        .unwrap()
+        .core(0)?
+        .reset_and_halt(HALT_TIMEOUT)?;
+
+    // A reset does not clear RAM, so the control block of the previous run would be found before
+    // the firmware has initialized the new one. Invalidate it while the core is halted. The new
+    // one is most likely created at the same address, which is much faster to check than the
+    // whole region.
+    let stale = match Rtt::attach_region(session.clone(), scan_region) {
+        Ok(stale) => {
+            session
+                .lock()
+                .unwrap()
+                .write(stale.ptr(), &[0u8; ID_SIZE])?;
+
+            Some(ScanRegion::Exact(stale.ptr()))
+        }
+        Err(_) => None,
+    };
+
+    let start = Instant::now();
+    let mut run_time = Duration::from_secs(0);
+    let mut last_full_scan = Duration::from_secs(0);
+    let mut burst = MIN_BURST;
+
+    if !halt {
+        session.lock().unwrap().core(0)?.run()?;
+    }
+
+    loop {
+        if halt {
+            let resumed = Instant::now();
+            session.lock().unwrap().core(0)?.run()?;
+            thread::sleep(burst);
+            session.lock().unwrap().core(0)?.halt(HALT_TIMEOUT)?;
+            run_time += resumed.elapsed();
+        } else {
+            thread::sleep(Duration::from_millis(10));
+            run_time = start.elapsed();
+        }
+
+        let region = match &stale {
+            Some(stale) if run_time - last_full_scan < FULL_SCAN_INTERVAL => stale,
+            _ => {
+                last_full_scan = run_time;
+                scan_region
+            }
+        };
+
+        let scan = Instant::now();
+
+        match Rtt::attach_region(session.clone(), region) {
+            Err(Error::ControlBlockNotFound) if run_time < ATTACH_TIMEOUT => {
+                burst = scan.elapsed().max(MIN_BURST);
+            }
+            Err(Error::ControlBlockNotFound) => return Err(Error::Timeout),
+            result => return result,
+        }
+    }
+}
+
+/// Remembers the modes of the up channels and restores them when dropped, such as when the
+/// program exits.
+pub struct ModeRestorer {
+    session: Arc<Mutex<Session>>,
+    control_block: u32,
+    modes: Vec<(usize, ChannelMode)>,
+}
+
+impl ModeRestorer {
+    /// Switches all up channels to [`ChannelMode::BlockIfFull`] so that no output is lost, and
+    /// resumes the core if it is halted.
+    pub fn block_if_full(
+        session: Arc<Mutex<Session>>,
+        rtt: &mut Rtt,
+    ) -> Result<ModeRestorer, Error> {
+        let mut modes = Vec::new();
+
+        for chan in rtt.up_channels().iter() {
+            modes.push((chan.number(), chan.mode()?));
+            chan.set_mode(ChannelMode::BlockIfFull)?;
+        }
+
+        {
+            let mut session = session.lock().unwrap();
+            let mut core = session.core(0)?;
+
+            if core.core_halted()? {
+                core.run()?;
+            }
+        }
+
+        Ok(ModeRestorer {
+            session,
+            control_block: rtt.ptr(),
+            modes,
+        })
+    }
+
+    /// Restores the original channel modes.
+    pub fn restore(&self) -> Result<(), Error> {
+        if self.modes.is_empty() {
+            return Ok(());
+        }
+
+        // The channels may have been handed out by now, so access them through a new instance
+        let mut rtt =
+            Rtt::attach_region(self.session.clone(), &ScanRegion::Exact(self.control_block))?;
+
+        for &(number, mode) in &self.modes {
+            if let Some(chan) = rtt.up_channels().get(number) {
+                chan.set_mode(mode)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops the restorer without restoring the modes, such as when the target has lost power and
+    /// the firmware sets them up again.
+    pub fn forget(mut self) {
+        self.modes.clear();
+    }
+}
+
+impl Drop for ModeRestorer {
+    fn drop(&mut self) {
+        if let Err(err) = self.restore() {
+            eprintln!("Error restoring channel modes: {}", err);
+        }
+    }
+}
+
+/// Holds the current [`ModeRestorer`] and restores the modes when dropped or on Ctrl-C, which
+/// exits without running destructors.
+pub struct RestoreOnExit {
+    restorer: Arc<Mutex<Option<ModeRestorer>>>,
+}
+
+impl RestoreOnExit {
+    pub fn new(restorer: ModeRestorer) -> RestoreOnExit {
+        let restorer = Arc::new(Mutex::new(Some(restorer)));
+        let handler_restorer = restorer.clone();
+
+        let result = ctrlc::set_handler(move || {
+            if let Some(restorer) = handler_restorer.lock().unwrap().take() {
+                if let Err(err) = restorer.restore() {
+                    eprintln!("\nError restoring channel modes: {}", err);
+                }
+
+                restorer.forget();
+            }
+
+            std::process::exit(130);
+        });
+
+        if let Err(err) = result {
+            eprintln!("Error setting Ctrl-C handler: {}", err);
+        }
+
+        RestoreOnExit { restorer }
+    }
+
+    /// Replaces the restorer after reconnecting. The previous one is dropped without restoring
+    /// anything, which also releases its session.
+    pub fn replace(&self, restorer: Option<ModeRestorer>) {
+        if let Some(previous) = std::mem::replace(&mut *self.restorer.lock().unwrap(), restorer) {
+            previous.forget();
+        }
+    }
+}
+
+impl Drop for RestoreOnExit {
+    fn drop(&mut self) {
+        drop(self.restorer.lock().unwrap().take());
+    }
+}
@@ -0,0 +1,185 @@
+//! Detecting the completion of on-target test runs from their output.
+
+use regex::Regex;
+
+/// The marker used by `--exit-marker` unless specified otherwise.
+pub const DEFAULT_EXIT_MARKER: &str = "RTTHOST_EXIT:";
+
+/// Exit code used when `--timeout` expires, the same as that of the `timeout` command.
+pub const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Watches the lines output by several channels for conditions that end the run.
+pub struct Completion {
+    exit_on: Vec<Regex>,
+    fail_on: Vec<Regex>,
+    exit_marker: Option<String>,
+    lines: Vec<Vec<u8>>,
+}
+
+impl Completion {
+    /// Creates a watcher for lines from the specified number of channels. Lines matching one of
+    /// `exit_on` end the run successfully and lines matching one of `fail_on` end it with an
+    /// error. Lines containing the exit marker followed by a number end it with that exit code.
+    pub fn new(
+        exit_on: Vec<Regex>,
+        fail_on: Vec<Regex>,
+        exit_marker: Option<String>,
+        channels: usize,
+    ) -> Completion {
+        Completion {
+            exit_on,
+            fail_on,
+            exit_marker,
+            lines: vec![Vec::new(); channels],
+        }
+    }
+
+    /// Returns false if no conditions are configured, in which case there is no need to watch.
+    pub fn is_active(&self) -> bool {
+        !self.exit_on.is_empty() || !self.fail_on.is_empty() || self.exit_marker.is_some()
+    }
+
+    /// Checks data from the channel with the specified index. Returns the exit code to end the
+    /// run with if a line completed by the data meets a condition.
+    pub fn check(&mut self, index: usize, data: &[u8]) -> Option<i32> {
+        for part in data.split_inclusive(|&b| b == b'\n') {
+            self.lines[index].extend_from_slice(part);
+
+            if part.ends_with(b"\n") {
+                let line = std::mem::take(&mut self.lines[index]);
+                let line = String::from_utf8_lossy(&line);
+
+                if let Some(code) = self.check_line(line.trim_end_matches(&['\r', '\n'][..])) {
+                    return Some(code);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn check_line(&self, line: &str) -> Option<i32> {
+        if let Some(marker) = &self.exit_marker {
+            if let Some(i) = line.find(marker.as_str()) {
+                let code = line[i + marker.len()..]
+                    .trim_start()
+                    .split(|c: char| !c.is_ascii_digit() && c != '-')
+                    .next()
+                    .and_then(|code| code.parse().ok());
+
+                match code {
+                    Some(code) => {
+                        eprintln!("\nExit marker found, exiting with code {}.", code);
+                        return Some(code);
+                    }
+                    None => eprintln!("\nIgnoring exit marker without an exit code: {}", line),
+                }
+            }
+        }
+
+        if let Some(regex) = self.fail_on.iter().find(|regex| regex.is_match(line)) {
+            eprintln!(
+                "\nOutput matched --fail-on '{}', exiting with code 1.",
+                regex
+            );
+            return Some(1);
+        }
+
+        if let Some(regex) = self.exit_on.iter().find(|regex| regex.is_match(line)) {
+            eprintln!(
+                "\nOutput matched --exit-on '{}', exiting with code 0.",
+                regex
+            );
+            return Some(0);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regexes(patterns: &[&str]) -> Vec<Regex> {
+        patterns.iter().map(|p| Regex::new(p).unwrap()).collect()
+    }
+
+    fn marker() -> Completion {
+        Completion::new(
+            Vec::new(),
+            Vec::new(),
+            Some(String::from(DEFAULT_EXIT_MARKER)),
+            2,
+        )
+    }
+
+    #[test]
+    fn is_inactive_without_conditions() {
+        assert!(!Completion::new(Vec::new(), Vec::new(), None, 1).is_active());
+        assert!(marker().is_active());
+    }
+
+    #[test]
+    fn exits_on_complete_lines_only() {
+        let mut completion = Completion::new(regexes(&["TESTS PASSED"]), Vec::new(), None, 1);
+
+        assert_eq!(completion.check(0, b"TESTS "), None);
+        assert_eq!(completion.check(0, b"PASSED"), None);
+        assert_eq!(completion.check(0, b"\r\n"), Some(0));
+    }
+
+    #[test]
+    fn keeps_lines_of_channels_apart() {
+        let mut completion = Completion::new(regexes(&["^done$"]), Vec::new(), None, 2);
+
+        assert_eq!(completion.check(0, b"do"), None);
+        assert_eq!(completion.check(1, b"ne\n"), None);
+        assert_eq!(completion.check(0, b"ne\n"), Some(0));
+    }
+
+    #[test]
+    fn fail_on_takes_precedence_over_exit_on() {
+        let mut completion =
+            Completion::new(regexes(&["finished"]), regexes(&["panicked"]), None, 1);
+
+        assert_eq!(
+            completion.check(0, b"finished: panicked at 'oops'\n"),
+            Some(1)
+        );
+        assert_eq!(completion.check(0, b"finished\n"), Some(0));
+    }
+
+    #[test]
+    fn exits_with_marker_code() {
+        let mut completion = marker();
+
+        assert_eq!(completion.check(0, b"RTTHOST_EXIT: 3\n"), Some(3));
+        assert_eq!(
+            completion.check(1, b"[test] RTTHOST_EXIT:0 done\n"),
+            Some(0)
+        );
+        assert_eq!(completion.check(0, b"RTTHOST_EXIT: -2\n"), Some(-2));
+    }
+
+    #[test]
+    fn ignores_marker_without_code() {
+        let mut completion = marker();
+
+        assert_eq!(completion.check(0, b"RTTHOST_EXIT:\n"), None);
+        assert_eq!(completion.check(0, b"RTTHOST_EXIT: failed\n"), None);
+        assert_eq!(completion.check(0, b"RTTHOST_EXIT: -\n"), None);
+    }
+
+    #[test]
+    fn marker_takes_precedence_over_patterns() {
+        let mut completion = Completion::new(
+            regexes(&["EXIT"]),
+            regexes(&["EXIT"]),
+            Some(String::from(DEFAULT_EXIT_MARKER)),
+            1,
+        );
+
+        assert_eq!(completion.check(0, b"RTTHOST_EXIT: 5\n"), Some(5));
+    }
+}
@@ -0,0 +1,309 @@
+//! Sending a file to a down channel, optionally waiting for an acknowledgement after each block.
+
+use probe_rs_rtt::{DownChannel, Error};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How often to print the progress while sending.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Text that the target outputs on an up channel once it has processed a block.
+pub struct Ack {
+    pub text: String,
+    pub block_size: usize,
+    pub timeout: Duration,
+}
+
+/// Sends the contents of a file as fast as the target reads them from the down channel.
+pub struct FileSender {
+    data: Vec<u8>,
+    sent: usize,
+
+    /// End of the block being sent, which is the end of the data unless waiting for
+    /// acknowledgements.
+    block_end: usize,
+
+    ack: Option<Ack>,
+
+    /// When the current block was sent in full, if waiting for its acknowledgement.
+    waiting_since: Option<Instant>,
+
+    /// The end of the output of each up channel, searched for the acknowledgement.
+    received: Vec<Vec<u8>>,
+
+    started: Instant,
+    last_progress: Instant,
+}
+
+impl FileSender {
+    /// Reads the file to send. The acknowledgement is looked for in the output of the specified
+    /// number of up channels.
+    pub fn open(path: &Path, ack: Option<Ack>, channels: usize) -> io::Result<FileSender> {
+        if matches!(&ack, Some(ack) if ack.text.is_empty() || ack.block_size == 0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The acknowledgement and block size must not be empty.",
+            ));
+        }
+
+        let data = fs::read(path)?;
+
+        let block_end = match &ack {
+            Some(ack) => ack.block_size.min(data.len()),
+            None => data.len(),
+        };
+
+        Ok(FileSender {
+            data,
+            sent: 0,
+            block_end,
+            ack,
+            waiting_since: None,
+            received: vec![Vec::new(); channels],
+            started: Instant::now(),
+            last_progress: Instant::now(),
+        })
+    }
+
+    /// Writes as much of the current block as the channel has space for, and returns the data
+    /// written. Nothing is written while waiting for an acknowledgement.
+    pub fn write(&mut self, channel: &DownChannel) -> Result<&[u8], Error> {
+        if self.waiting_since.is_some() || self.sent == self.block_end {
+            return Ok(&[]);
+        }
+
+        // Nothing is written while the buffer is full, so this is retried on the next poll
+        let start = self.sent;
+        self.sent += channel.write(&self.data[start..self.block_end])?;
+
+        if self.sent == self.block_end && self.ack.is_some() {
+            self.waiting_since = Some(Instant::now());
+
+            for received in &mut self.received {
+                received.clear();
+            }
+        }
+
+        if self.last_progress.elapsed() >= PROGRESS_INTERVAL && !self.is_done() {
+            self.last_progress = Instant::now();
+
+            eprintln!(
+                "Sent {}/{} bytes ({}%), {}",
+                self.sent,
+                self.data.len(),
+                self.sent * 100 / self.data.len(),
+                self.throughput()
+            );
+        }
+
+        Ok(&self.data[start..self.sent])
+    }
+
+    /// Checks data from the up channel with the specified index for the acknowledgement of the
+    /// current block, and moves on to the next block once found.
+    pub fn check(&mut self, index: usize, data: &[u8]) {
+        let (text, block_size) = match (&self.ack, self.waiting_since) {
+            (Some(ack), Some(_)) => (ack.text.as_bytes(), ack.block_size),
+            _ => return,
+        };
+
+        let received = &mut self.received[index];
+        received.extend_from_slice(data);
+
+        if received.windows(text.len()).any(|window| window == text) {
+            self.waiting_since = None;
+            self.block_end = (self.block_end + block_size).min(self.data.len());
+
+            for received in &mut self.received {
+                received.clear();
+            }
+        } else {
+            // Keep just enough to find an acknowledgement split across reads
+            let keep = text.len() - 1;
+            let excess = received.len().saturating_sub(keep);
+            received.drain(..excess);
+        }
+    }
+
+    /// Returns an error if the acknowledgement of the current block has not been received in
+    /// time.
+    pub fn check_timeout(&self) -> Result<(), String> {
+        match (&self.ack, self.waiting_since) {
+            (Some(ack), Some(since)) if since.elapsed() >= ack.timeout => Err(format!(
+                "No acknowledgement '{}' received within {} s after sending {}/{} bytes.",
+                ack.text,
+                ack.timeout.as_secs(),
+                self.sent,
+                self.data.len()
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns true once the whole file has been sent and acknowledged.
+    pub fn is_done(&self) -> bool {
+        self.sent == self.data.len() && self.waiting_since.is_none()
+    }
+
+    /// Describes the completed transfer.
+    pub fn summary(&self) -> String {
+        format!(
+            "Sent {} bytes in {:.1} s, {}",
+            self.data.len(),
+            self.started.elapsed().as_secs_f64(),
+            self.throughput()
+        )
+    }
+
+    fn throughput(&self) -> String {
+        let seconds = self.started.elapsed().as_secs_f64().max(0.001);
+
+        format!("{:.1} KiB/s", self.sent as f64 / 1024.0 / seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    /// Writes the data to a file and opens a sender for it, waiting for `OK` after each block.
+    fn sender(name: &str, data: &[u8], block_size: usize, timeout: Duration) -> FileSender {
+        let path = std::env::temp_dir().join(format!("rtthost-{}-{}.bin", name, process::id()));
+        fs::write(&path, data).unwrap();
+
+        let ack = Ack {
+            text: String::from("OK"),
+            block_size,
+            timeout,
+        };
+
+        let sender = FileSender::open(&path, Some(ack), 2);
+        fs::remove_file(&path).unwrap();
+
+        sender.unwrap()
+    }
+
+    /// Marks the current block as sent, as `write` does once the channel has taken all of it.
+    fn send_block(sender: &mut FileSender) {
+        sender.sent = sender.block_end;
+        sender.waiting_since = Some(Instant::now());
+
+        for received in &mut sender.received {
+            received.clear();
+        }
+    }
+
+    #[test]
+    fn rejects_empty_ack() {
+        let path = std::env::temp_dir().join(format!("rtthost-ack-{}.bin", process::id()));
+
+        for (text, block_size) in &[("", 16), ("OK", 0)] {
+            let ack = Ack {
+                text: text.to_string(),
+                block_size: *block_size,
+                timeout: Duration::from_secs(1),
+            };
+
+            let err = FileSender::open(&path, Some(ack), 1).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn advances_blocks_on_ack() {
+        let mut sender = sender("blocks", &[0; 10], 4, Duration::from_secs(60));
+        assert_eq!(sender.block_end, 4);
+
+        send_block(&mut sender);
+        assert!(!sender.is_done());
+
+        // Nothing moves on without the acknowledgement
+        sender.check(0, b"busy\n");
+        assert_eq!(sender.block_end, 4);
+
+        sender.check(0, b"OK\n");
+        assert_eq!(sender.block_end, 8);
+        assert!(sender.waiting_since.is_none());
+
+        send_block(&mut sender);
+        sender.check(0, b"OK\n");
+        assert_eq!(sender.block_end, 10);
+
+        send_block(&mut sender);
+        assert!(!sender.is_done());
+        sender.check(0, b"OK\n");
+        assert_eq!(sender.block_end, 10);
+        assert!(sender.is_done());
+    }
+
+    #[test]
+    fn ignores_ack_before_block_is_sent() {
+        let mut sender = sender("early", &[0; 10], 4, Duration::from_secs(60));
+
+        sender.check(0, b"OK\n");
+        assert_eq!(sender.block_end, 4);
+        assert!(sender.received[0].is_empty());
+    }
+
+    #[test]
+    fn finds_ack_split_across_reads() {
+        let mut sender = sender("split", &[0; 10], 4, Duration::from_secs(60));
+        send_block(&mut sender);
+
+        sender.check(0, b"block received, O");
+        assert_eq!(sender.received[0], b"O");
+        assert_eq!(sender.block_end, 4);
+
+        sender.check(0, b"K\n");
+        assert_eq!(sender.block_end, 8);
+    }
+
+    #[test]
+    fn looks_for_ack_on_each_channel_separately() {
+        let mut sender = sender("channels", &[0; 10], 4, Duration::from_secs(60));
+        send_block(&mut sender);
+
+        // Halves on different channels do not make an acknowledgement
+        sender.check(0, b"O");
+        sender.check(1, b"K");
+        assert_eq!(sender.block_end, 4);
+
+        sender.check(1, b"OK");
+        assert_eq!(sender.block_end, 8);
+        assert!(sender.received.iter().all(|received| received.is_empty()));
+    }
+
+    #[test]
+    fn times_out_without_ack() {
+        let mut sender = sender("timeout", &[0; 10], 4, Duration::from_secs(0));
+        assert!(sender.check_timeout().is_ok());
+
+        send_block(&mut sender);
+        let err = sender.check_timeout().unwrap_err();
+        assert!(err.contains("'OK'"), "{}", err);
+        assert!(err.contains("4/10 bytes"), "{}", err);
+
+        sender.check(0, b"OK");
+        assert!(sender.check_timeout().is_ok());
+    }
+
+    #[test]
+    fn waits_for_ack_until_timeout() {
+        let mut sender = sender("wait", &[0; 10], 4, Duration::from_secs(60));
+        send_block(&mut sender);
+
+        assert!(sender.check_timeout().is_ok());
+    }
+
+    #[test]
+    fn sends_empty_file() {
+        let sender = sender("empty", &[], 4, Duration::from_secs(0));
+
+        assert_eq!(sender.block_end, 0);
+        assert!(sender.is_done());
+        assert!(sender.check_timeout().is_ok());
+    }
+}
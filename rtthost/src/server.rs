@@ -0,0 +1,338 @@
+//! TCP server mode, compatible with clients of JLinkRTTServer such as telnet.
+
+use probe_rs_rtt::capture::Recorder;
+use probe_rs_rtt::{DownChannel, Error, UpChannel};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+/// What to do with up channel data while no client is connected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoClientPolicy {
+    /// Buffer data up to the buffer size, discarding the oldest data, and send it to the first
+    /// client that connects.
+    Buffer,
+
+    /// Read and discard data.
+    Drop,
+
+    /// Stop reading from the channel. Depending on the channel mode, the target will then either
+    /// block or discard data once the buffer is full.
+    Pause,
+}
+
+impl std::str::FromStr for NoClientPolicy {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<NoClientPolicy, &'static str> {
+        match s {
+            "buffer" => Ok(NoClientPolicy::Buffer),
+            "drop" => Ok(NoClientPolicy::Drop),
+            "pause" => Ok(NoClientPolicy::Pause),
+            _ => Err("Invalid policy, expected 'buffer', 'drop' or 'pause'."),
+        }
+    }
+}
+
+/// A listening address and the channel number to serve on it, parsed from `[CHANNEL@]ADDR`. The
+/// address may be a bare port number, in which case only local connections are accepted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListenSpec {
+    pub channel: Option<usize>,
+    pub addr: String,
+}
+
+impl std::str::FromStr for ListenSpec {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<ListenSpec, &'static str> {
+        let (channel, addr) = match s.find('@') {
+            Some(i) => (
+                Some(
+                    s[..i]
+                        .parse()
+                        .map_err(|_| "Invalid channel number in listen address.")?,
+                ),
+                &s[i + 1..],
+            ),
+            None => (None, s),
+        };
+
+        let addr = if addr.parse::<u16>().is_ok() {
+            format!("127.0.0.1:{}", addr)
+        } else {
+            addr.to_string()
+        };
+
+        Ok(ListenSpec { channel, addr })
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    addr: SocketAddr,
+    pending: Vec<u8>,
+}
+
+/// Serves an up/down channel pair to any number of TCP clients. Up channel data is sent to every
+/// client, and data received from any client is written to the down channel.
+pub struct ChannelServer {
+    listener: TcpListener,
+    up: Option<UpChannel>,
+    down: Option<DownChannel>,
+    clients: Vec<Client>,
+    policy: NoClientPolicy,
+    buffer_size: usize,
+    backlog: VecDeque<u8>,
+    down_buf: Vec<u8>,
+}
+
+impl ChannelServer {
+    pub fn bind(
+        addr: &str,
+        up: Option<UpChannel>,
+        down: Option<DownChannel>,
+        policy: NoClientPolicy,
+        buffer_size: usize,
+    ) -> io::Result<ChannelServer> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(ChannelServer {
+            listener,
+            up,
+            down,
+            clients: Vec::new(),
+            policy,
+            buffer_size,
+            backlog: VecDeque::new(),
+            down_buf: Vec::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts new clients and transfers data in both directions without blocking. Returns the
+    /// number of bytes moved, which is zero if there was nothing to do.
+    pub fn poll(&mut self, mut recorder: Option<&mut Recorder<File>>) -> Result<usize, Error> {
+        self.accept();
+
+        let mut buf = [0u8; 1024];
+        let mut moved = 0;
+
+        if let Some(up) = self.up.as_ref() {
+            if !self.clients.is_empty() || self.policy != NoClientPolicy::Pause {
+                let count = up.read(&mut buf)?;
+                moved += count;
+
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.record_up(up, &buf[..count])?;
+                }
+
+                if self.clients.is_empty() {
+                    if self.policy == NoClientPolicy::Buffer {
+                        self.backlog.extend(&buf[..count]);
+
+                        let excess = self.backlog.len().saturating_sub(self.buffer_size);
+                        self.backlog.drain(..excess);
+                    }
+                } else {
+                    for client in &mut self.clients {
+                        client.pending.extend_from_slice(&buf[..count]);
+                    }
+                }
+            }
+        }
+
+        let mut i = 0;
+        while i < self.clients.len() {
+            match self.service(i) {
+                Some(count) => {
+                    moved += count;
+                    i += 1;
+                }
+                None => {
+                    self.clients.remove(i);
+                }
+            }
+        }
+
+        if let Some(down) = self.down.as_ref() {
+            if !self.down_buf.is_empty() {
+                let count = down.write(&self.down_buf)?;
+                moved += count;
+
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.record_down(down, &self.down_buf[..count])?;
+                }
+
+                self.down_buf.drain(..count);
+            }
+        }
+
+        Ok(moved)
+    }
+
+    /// Reads input from and writes pending data to a client. Returns the number of bytes moved,
+    /// or `None` if the client should be disconnected.
+    fn service(&mut self, index: usize) -> Option<usize> {
+        let client = &mut self.clients[index];
+        let mut buf = [0u8; 1024];
+        let mut moved = 0;
+
+        // Clients are also read without a down channel to notice when they disconnect, in which
+        // case their input is discarded
+        if self.down.is_none() || self.down_buf.len() < self.buffer_size {
+            match client.stream.read(&mut buf) {
+                Ok(0) => {
+                    eprintln!("Client {} disconnected.", client.addr);
+                    return None;
+                }
+                Ok(count) => {
+                    if self.down.is_some() {
+                        self.down_buf.extend_from_slice(&buf[..count]);
+                    }
+
+                    moved += count;
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => {
+                    eprintln!("Error reading from client {}: {}", client.addr, err);
+                    return None;
+                }
+            }
+        }
+
+        while !client.pending.is_empty() {
+            match client.stream.write(&client.pending) {
+                Ok(count) => {
+                    client.pending.drain(..count);
+                    moved += count;
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    eprintln!("Error writing to client {}: {}", client.addr, err);
+                    return None;
+                }
+            }
+        }
+
+        if client.pending.len() > self.buffer_size {
+            eprintln!(
+                "Disconnecting client {} because it is not reading data fast enough.",
+                client.addr
+            );
+            return None;
+        }
+
+        Some(moved)
+    }
+
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    if let Err(err) = stream
+                        .set_nonblocking(true)
+                        .and_then(|_| stream.set_nodelay(true))
+                    {
+                        eprintln!("Error setting up client {}: {}", addr, err);
+                        continue;
+                    }
+
+                    eprintln!("Client {} connected.", addr);
+
+                    // The first client receives the data buffered while nobody was connected
+                    let pending = if self.clients.is_empty() {
+                        self.backlog.drain(..).collect()
+                    } else {
+                        Vec::new()
+                    };
+
+                    self.clients.push(Client {
+                        stream,
+                        addr,
+                        pending,
+                    });
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+                Err(err) => {
+                    eprintln!("Error accepting client: {}", err);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn parses_listen_specs() {
+        let spec = |s: &str| s.parse::<ListenSpec>();
+
+        assert_eq!(
+            spec("19021"),
+            Ok(ListenSpec {
+                channel: None,
+                addr: String::from("127.0.0.1:19021"),
+            })
+        );
+        assert_eq!(
+            spec("1@0.0.0.0:19021"),
+            Ok(ListenSpec {
+                channel: Some(1),
+                addr: String::from("0.0.0.0:19021"),
+            })
+        );
+        assert_eq!(
+            spec("2@19022"),
+            Ok(ListenSpec {
+                channel: Some(2),
+                addr: String::from("127.0.0.1:19022"),
+            })
+        );
+        assert_eq!(
+            spec("[::1]:19021").unwrap().addr,
+            String::from("[::1]:19021")
+        );
+
+        assert!(spec("x@19021").is_err());
+        assert!(spec("-1@19021").is_err());
+        assert!(spec("@19021").is_err());
+    }
+
+    #[test]
+    fn notices_disconnects_without_down_channel() {
+        let mut server =
+            ChannelServer::bind("127.0.0.1:0", None, None, NoClientPolicy::Drop, 1024).unwrap();
+        let mut client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+
+        let poll_until = |server: &mut ChannelServer, clients: usize| {
+            let start = Instant::now();
+
+            while server.clients.len() != clients {
+                assert!(start.elapsed() < Duration::from_secs(5));
+                server.poll(None).unwrap();
+                thread::sleep(Duration::from_millis(1));
+            }
+        };
+
+        poll_until(&mut server, 1);
+
+        // Input is discarded
+        client.write_all(b"ignored").unwrap();
+        server.poll(None).unwrap();
+        assert!(server.down_buf.is_empty());
+
+        drop(client);
+        poll_until(&mut server, 0);
+    }
+}
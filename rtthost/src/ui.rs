@@ -0,0 +1,500 @@
+//! Interactive full-screen terminal interface with a tab per up channel.
+
+use crate::input::LineEnding;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use probe_rs::Session;
+use probe_rs_rtt::capture::Recorder;
+use probe_rs_rtt::{ChannelMode, DownChannel, UpChannel};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Stdout};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tui::backend::CrosstermBackend;
+use tui::layout::{Constraint, Direction, Layout, Rect};
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, Paragraph, Tabs};
+use tui::{Frame, Terminal};
+
+type Backend = CrosstermBackend<Stdout>;
+
+/// Maximum number of lines kept for each tab.
+const SCROLLBACK: usize = 10000;
+
+/// The state of a single up channel and the down channel with the same number, if any.
+struct Tab {
+    up: UpChannel,
+    down: Option<DownChannel>,
+    mode: Option<ChannelMode>,
+    lines: VecDeque<String>,
+    partial: Vec<u8>,
+
+    /// Number of lines scrolled up from the end.
+    scroll: usize,
+
+    input: String,
+    down_buf: Vec<u8>,
+
+    /// Bytes received since the throughput was last updated, and the throughput in bytes per
+    /// second.
+    received: usize,
+    rate: f64,
+}
+
+impl Tab {
+    fn new(up: UpChannel, down: Option<DownChannel>) -> Tab {
+        Tab {
+            mode: up.mode().ok(),
+            up,
+            down,
+            lines: VecDeque::new(),
+            partial: Vec::new(),
+            scroll: 0,
+            input: String::new(),
+            down_buf: Vec::new(),
+            received: 0,
+            rate: 0.0,
+        }
+    }
+
+    fn title(&self) -> String {
+        match self.up.name() {
+            Some(name) => format!("{}: {}", self.up.number(), name),
+            None => self.up.number().to_string(),
+        }
+    }
+
+    /// Appends received data, splitting it into lines.
+    fn push(&mut self, data: &[u8]) {
+        self.received += data.len();
+
+        for line in data.split_inclusive(|&b| b == b'\n') {
+            self.partial.extend_from_slice(line);
+
+            if line.ends_with(b"\n") {
+                let line = std::mem::take(&mut self.partial);
+                self.lines.push_back(to_display(&line));
+
+                // Keep the view in place while scrolled back
+                if self.scroll > 0 {
+                    self.scroll += 1;
+                }
+
+                if self.lines.len() > SCROLLBACK {
+                    self.lines.pop_front();
+                }
+            }
+        }
+
+        self.scroll = self.scroll.min(self.line_count().saturating_sub(1));
+    }
+
+    /// Returns the number of lines including the unfinished one.
+    fn line_count(&self) -> usize {
+        self.lines.len() + if self.partial.is_empty() { 0 } else { 1 }
+    }
+
+    fn line(&self, index: usize) -> String {
+        match self.lines.get(index) {
+            Some(line) => line.clone(),
+            None => to_display(&self.partial),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.lines.clear();
+        self.partial.clear();
+        self.scroll = 0;
+    }
+
+    fn scroll_up(&mut self, lines: usize) {
+        self.scroll = (self.scroll + lines).min(self.line_count().saturating_sub(1));
+    }
+
+    fn scroll_down(&mut self, lines: usize) {
+        self.scroll = self.scroll.saturating_sub(lines);
+    }
+
+    /// Scrolls back to the closest line containing the text above the current end of the view.
+    /// Returns false if there is none.
+    fn find(&mut self, text: &str) -> bool {
+        let end = self.line_count().saturating_sub(self.scroll + 1);
+
+        match (0..end).rev().find(|&i| self.line(i).contains(text)) {
+            Some(index) => {
+                self.scroll = self.line_count() - 1 - index;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Queues the input line to be written to the down channel.
+    fn send_input(&mut self, line_ending: LineEnding) {
+        if self.down.is_some() {
+            let input = std::mem::take(&mut self.input);
+            self.down_buf.extend_from_slice(input.as_bytes());
+            self.down_buf.extend_from_slice(line_ending.as_bytes());
+        }
+    }
+
+    /// Switches to the next channel mode.
+    fn cycle_mode(&mut self) -> Result<(), probe_rs_rtt::Error> {
+        let mode = match self.mode {
+            Some(ChannelMode::NoBlockSkip) => ChannelMode::NoBlockTrim,
+            Some(ChannelMode::NoBlockTrim) => ChannelMode::BlockIfFull,
+            Some(ChannelMode::BlockIfFull) | None => ChannelMode::NoBlockSkip,
+        };
+
+        self.up.set_mode(mode)?;
+        self.mode = Some(self.up.mode()?);
+
+        Ok(())
+    }
+}
+
+/// What typed text is entered into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum InputMode {
+    Channel,
+    Search,
+}
+
+/// Full-screen interface showing one tab per up channel, with an input line that writes to the
+/// down channel with the same number.
+pub struct App {
+    tabs: Vec<Tab>,
+    selected: usize,
+    control_block: u32,
+    session: Option<Arc<Mutex<Session>>>,
+    line_ending: LineEnding,
+    input_mode: InputMode,
+    search: String,
+    message: String,
+    quit: bool,
+}
+
+impl App {
+    /// Creates the interface for up channels and the down channels paired with them. The session
+    /// is used to reset the target, if there is one.
+    pub fn new(
+        control_block: u32,
+        channels: Vec<(UpChannel, Option<DownChannel>)>,
+        session: Option<Arc<Mutex<Session>>>,
+        line_ending: LineEnding,
+    ) -> App {
+        App {
+            tabs: channels
+                .into_iter()
+                .map(|(up, down)| Tab::new(up, down))
+                .collect(),
+            selected: 0,
+            control_block,
+            session,
+            line_ending,
+            input_mode: InputMode::Channel,
+            search: String::new(),
+            message: String::from("Tab: next channel, F1: help, Esc: quit"),
+            quit: false,
+        }
+    }
+
+    /// Runs the interface until the user quits or an error occurs.
+    pub fn run(&mut self, mut recorder: Option<Recorder<File>>) -> Result<(), Box<dyn Error>> {
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+
+        let result = Terminal::new(CrosstermBackend::new(io::stdout()))
+            .map_err(Box::from)
+            .and_then(|mut terminal| {
+                terminal.clear()?;
+                self.event_loop(&mut terminal, recorder.as_mut())
+            });
+
+        execute!(io::stdout(), LeaveAlternateScreen)?;
+        disable_raw_mode()?;
+
+        result
+    }
+
+    fn event_loop(
+        &mut self,
+        terminal: &mut Terminal<Backend>,
+        mut recorder: Option<&mut Recorder<File>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut buf = [0u8; 1024];
+        let mut rate_since = Instant::now();
+        let mut redraw = true;
+
+        while !self.quit {
+            for tab in &mut self.tabs {
+                let count = tab.up.read(&mut buf)?;
+
+                if count > 0 {
+                    if let Some(recorder) = recorder.as_mut() {
+                        recorder.record_up(&tab.up, &buf[..count])?;
+                    }
+
+                    tab.push(&buf[..count]);
+                    redraw = true;
+                }
+
+                if let Some(down) = tab.down.as_ref() {
+                    if !tab.down_buf.is_empty() {
+                        let count = down.write(&tab.down_buf)?;
+
+                        if let Some(recorder) = recorder.as_mut() {
+                            recorder.record_down(down, &tab.down_buf[..count])?;
+                        }
+
+                        tab.down_buf.drain(..count);
+                    }
+                }
+            }
+
+            let elapsed = rate_since.elapsed();
+            if elapsed >= Duration::from_secs(1) {
+                for tab in &mut self.tabs {
+                    tab.rate = tab.received as f64 / elapsed.as_secs_f64();
+                    tab.received = 0;
+                }
+
+                rate_since = Instant::now();
+                redraw = true;
+            }
+
+            if redraw {
+                terminal.draw(|f| self.draw(f))?;
+                redraw = false;
+            }
+
+            // Waiting for input also paces polling of the target
+            if event::poll(Duration::from_millis(10))? {
+                match event::read()? {
+                    Event::Key(key) => self.handle_key(key, terminal.size()?.height as usize),
+                    Event::Resize(_, _) => {}
+                    Event::Mouse(_) => continue,
+                }
+
+                redraw = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_key(&mut self, key: KeyEvent, height: usize) {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let page = height.saturating_sub(5).max(1);
+        let tab = &mut self.tabs[self.selected];
+
+        match key.code {
+            KeyCode::Char('c') if ctrl => self.quit = true,
+            KeyCode::Char('f') if ctrl => {
+                self.input_mode = InputMode::Search;
+                self.message = String::from("Enter: find older match, Esc: stop searching");
+            }
+            KeyCode::Char('l') if ctrl => tab.clear(),
+            KeyCode::Char('r') if ctrl => self.reset(),
+            KeyCode::Esc if self.input_mode == InputMode::Search => {
+                self.input_mode = InputMode::Channel;
+                self.search.clear();
+                self.message.clear();
+            }
+            KeyCode::Esc => self.quit = true,
+            KeyCode::F(1) => {
+                self.message = String::from(
+                    "Tab/Shift-Tab: switch channel, PgUp/PgDn/Home/End: scroll, Ctrl-F: search, \
+                     F2: change mode, Ctrl-L: clear, Ctrl-R: reset target, Esc: quit",
+                )
+            }
+            KeyCode::F(2) => {
+                self.message = match tab.cycle_mode() {
+                    Ok(()) => String::from("Channel mode changed."),
+                    Err(err) => format!("Error changing channel mode: {}", err),
+                }
+            }
+            KeyCode::Tab => self.selected = (self.selected + 1) % self.tabs.len(),
+            KeyCode::BackTab => {
+                self.selected = (self.selected + self.tabs.len() - 1) % self.tabs.len()
+            }
+            KeyCode::Up => tab.scroll_up(1),
+            KeyCode::Down => tab.scroll_down(1),
+            KeyCode::PageUp => tab.scroll_up(page),
+            KeyCode::PageDown => tab.scroll_down(page),
+            KeyCode::Home => tab.scroll_up(usize::MAX / 2),
+            KeyCode::End => tab.scroll_down(usize::MAX),
+            KeyCode::Enter if self.input_mode == InputMode::Search => self.find(),
+            KeyCode::Enter => tab.send_input(self.line_ending),
+            KeyCode::Backspace => {
+                match self.input_mode {
+                    InputMode::Channel => tab.input.pop(),
+                    InputMode::Search => self.search.pop(),
+                };
+            }
+            KeyCode::Char(c) if !ctrl => match self.input_mode {
+                InputMode::Channel => {
+                    if tab.down.is_some() {
+                        tab.input.push(c)
+                    }
+                }
+                InputMode::Search => self.search.push(c),
+            },
+            _ => {}
+        }
+    }
+
+    fn find(&mut self) {
+        if !self.search.is_empty() && !self.tabs[self.selected].find(&self.search) {
+            self.message = format!("No older match for '{}'.", self.search);
+        }
+    }
+
+    fn reset(&mut self) {
+        let result = match self.session.as_ref() {
+            Some(session) => session
+                .lock()
+                .unwrap()
+                .core(0)
+                .and_then(|mut core| core.reset()),
+            None => {
+                self.message = String::from("The target cannot be reset without a probe.");
+                return;
+            }
+        };
+
+        self.message = match result {
+            Ok(()) => String::from("Target reset."),
+            Err(err) => format!("Error resetting target: {}", err),
+        };
+    }
+
+    fn draw(&self, f: &mut Frame<Backend>) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(1),
+                Constraint::Min(3),
+                Constraint::Length(1),
+                Constraint::Length(1),
+            ])
+            .split(f.size());
+
+        let tab = &self.tabs[self.selected];
+
+        let titles = self
+            .tabs
+            .iter()
+            .map(|tab| Spans::from(tab.title()))
+            .collect();
+
+        f.render_widget(
+            Tabs::new(titles)
+                .select(self.selected)
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED)),
+            chunks[0],
+        );
+
+        self.draw_lines(f, tab, chunks[1]);
+
+        let (prompt, input) = match self.input_mode {
+            InputMode::Channel if tab.down.is_some() => ("> ", tab.input.as_str()),
+            InputMode::Channel => ("", ""),
+            InputMode::Search => ("Search: ", self.search.as_str()),
+        };
+
+        f.render_widget(Paragraph::new(format!("{}{}", prompt, input)), chunks[2]);
+
+        if !prompt.is_empty() {
+            f.set_cursor(
+                chunks[2].x + (prompt.len() + input.chars().count()) as u16,
+                chunks[2].y,
+            );
+        }
+
+        f.render_widget(
+            Paragraph::new(self.status(tab))
+                .style(Style::default().add_modifier(Modifier::REVERSED)),
+            chunks[3],
+        );
+    }
+
+    fn draw_lines(&self, f: &mut Frame<Backend>, tab: &Tab, area: Rect) {
+        let height = area.height.saturating_sub(2) as usize;
+        let end = tab.line_count() - tab.scroll.min(tab.line_count());
+        let start = end.saturating_sub(height);
+
+        let lines: Vec<Spans> = (start..end)
+            .map(|i| highlight(tab.line(i), &self.search))
+            .collect();
+
+        let title = if tab.scroll > 0 {
+            format!(" {} (scrolled back {} lines) ", tab.title(), tab.scroll)
+        } else {
+            format!(" {} ", tab.title())
+        };
+
+        f.render_widget(
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title)),
+            area,
+        );
+    }
+
+    fn status(&self, tab: &Tab) -> String {
+        let mode = match &tab.mode {
+            Some(ChannelMode::NoBlockSkip) => "NoBlockSkip",
+            Some(ChannelMode::NoBlockTrim) => "NoBlockTrim",
+            Some(ChannelMode::BlockIfFull) => "BlockIfFull",
+            None => "unknown",
+        };
+
+        let down = match tab.down.as_ref() {
+            Some(down) => format!("{} B", down.buffer_size()),
+            None => String::from("none"),
+        };
+
+        format!(
+            "Control block 0x{:08x} | up {} B, {} | down {} | {:.0} B/s | {}",
+            self.control_block,
+            tab.up.buffer_size(),
+            mode,
+            down,
+            tab.rate,
+            self.message
+        )
+    }
+}
+
+/// Converts a line of channel data to displayable text.
+fn to_display(line: &[u8]) -> String {
+    String::from_utf8_lossy(line)
+        .trim_end_matches(&['\r', '\n'][..])
+        .replace('\t', "    ")
+}
+
+/// Highlights occurrences of the search text in a line.
+fn highlight(line: String, search: &str) -> Spans<'static> {
+    if search.is_empty() {
+        return Spans::from(line);
+    }
+
+    let style = Style::default().fg(Color::Black).bg(Color::Yellow);
+    let mut spans = Vec::new();
+    let mut rest = line.as_str();
+
+    while let Some(i) = rest.find(search) {
+        spans.push(Span::raw(rest[..i].to_string()));
+        spans.push(Span::styled(search.to_string(), style));
+        rest = &rest[i + search.len()..];
+    }
+
+    spans.push(Span::raw(rest.to_string()));
+
+    Spans::from(spans)
+}